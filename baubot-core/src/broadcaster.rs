@@ -8,38 +8,129 @@
 use crate::prelude::*;
 use serde::Deserialize;
 use serde::Serialize;
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::future::Future;
-use teloxide::payloads::SendMessageSetters;
+use teloxide::types::ForceReply;
 use teloxide::types::InlineKeyboardButton;
 use teloxide::types::InlineKeyboardMarkup;
 use teloxide::types::MaybeInaccessibleMessage;
 use teloxide::types::UpdateKind;
+use tokio::sync::mpsc;
 use tokio::sync::oneshot;
 use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
 
+mod html;
+pub(crate) mod split;
+mod throttle;
 pub mod types;
 
+/// Longest caption telegram accepts on a photo or document, in UTF-16 code units.
+const MAX_CAPTION_LENGTH: usize = 1024;
+
+/// Most attachments telegram accepts in a single album.
+const MAX_MEDIA_GROUP: usize = 10;
+
+/// Everything about a [types::BauMessage] that is the same for every recipient.
+struct Delivery {
+    id: Option<String>,
+    message: String,
+    attachments: Vec<types::Attachment>,
+    parse_mode: Option<ParseMode>,
+    reply_markup: Option<ReplyMarkup>,
+    timeout: u64,
+
+    /// See [types::PendingRequest::buttons]
+    buttons: Vec<String>,
+}
+
+/// Place of a delivery in the queue of its recipient. See [Server::take_turn].
+pub(crate) struct Turn {
+    /// Resolves (or fails) once the previous delivery to the recipient has been sent
+    previous: Option<oneshot::Receiver<()>>,
+
+    /// Dropped once this delivery has been sent
+    done: oneshot::Sender<()>,
+}
+
+/// Prefix of the [types::BauMessage::id] of registrations waiting for the approval of an admin
+/// (see [crate::config::BauBotBuilder::approve_registrations]). Only [crate::BauBot] acts on
+/// their decision and it does not survive a restart, so they are never resumed by
+/// [Server::restore].
+pub(crate) const REGISTRATION_PREFIX: &str = "registration:";
+
 pub(crate) struct Server {
     store: Mutex<types::BauResponseStore>,
+
+    /// The [types::PendingRequest]s in the store, by key
+    registered: std::sync::Mutex<HashMap<i128, types::PendingRequest>>,
+
+    /// Receivers for restored requests, waiting for [crate::BauBot::reclaim]
+    parked: Mutex<HashMap<String, Vec<(String, types::BauResponseReceiver)>>>,
+
+    /// [types::BauMessage::id]s taken by [Server::reserve] until they are released
+    reserved: std::sync::Mutex<HashSet<String>>,
+
+    /// `(chat_id, message_id)` of the broadcasts still waiting for a response, by
+    /// [types::BauMessage::id]. Used by [Server::cancel]
+    requests: Mutex<HashMap<String, Vec<(i64, i32)>>>,
+
+    /// Configuration shared with [crate::BauBot]
+    pub(crate) config: Arc<crate::config::Config>,
+
+    /// Keeps the broadcasts within [crate::config::Config::rate_limits]
+    pub(crate) throttle: throttle::Throttle,
+
+    /// Bounds the deliveries in flight to [crate::config::Config::concurrency]
+    deliveries: tokio::sync::Semaphore,
+
+    /// Bounds the [types::BauMessage]s taken off the queue but not sent yet to
+    /// [crate::config::Config::concurrency], so that a bounded queue still pushes back
+    handlers: Arc<tokio::sync::Semaphore>,
+
+    /// Completion of the last delivery to each recipient, by username. See [Server::take_turn]
+    turns: std::sync::Mutex<HashMap<String, oneshot::Receiver<()>>>,
+
+    /// Cancelled when [crate::BauBot::shutdown] is called
+    pub(crate) shutdown: CancellationToken,
+
+    /// Tracks every task spawned on behalf of the [Server] so that shutdown can wait for them
+    pub(crate) tracker: TaskTracker,
 }
 
 impl Server {
     /// Start the receiver
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(config: Arc<crate::config::Config>) -> Self {
         // Create callback handlers
         let store = Default::default();
 
         // Create receiver
-        Self { store }
+        Self {
+            store,
+            registered: Default::default(),
+            parked: Default::default(),
+            reserved: Default::default(),
+            requests: Default::default(),
+            throttle: throttle::Throttle::new(config.rate_limits),
+            deliveries: tokio::sync::Semaphore::new(config.concurrency),
+            handlers: Arc::new(tokio::sync::Semaphore::new(config.concurrency)),
+            turns: Default::default(),
+            config,
+            shutdown: CancellationToken::new(),
+            tracker: TaskTracker::new(),
+        }
     }
 
     /// Listening loop
     pub(crate) fn listen<
-        Db: BauData + Send + Sync,
+        Db: BauData + Send + Sync + 'static,
         DbRef: Deref<Target = Db> + Clone + Send + Sync + 'static,
+        T: BauTransport,
     >(
         server: Arc<Self>,
-        bot: Bot,
+        transport: T,
         db: DbRef,
         mut server_socket: types::ServerSocket,
     ) -> impl Future<Output = ()> + Send + 'static {
@@ -47,125 +138,800 @@ impl Server {
             info!("Starting receiver");
 
             loop {
-                match server_socket.recv().await {
-                    // If we receive a payload
+                // Wait for a free handler before taking the next payload off the queue
+                let permit = tokio::select! {
+                    // NOTE: Safe to unwrap because the semaphore is never closed
+                    permit = server.handlers.clone().acquire_owned() => permit.unwrap(),
+                    _ = server.shutdown.cancelled() => break,
+                };
+
+                let payload = tokio::select! {
+                    payload = server_socket.recv() => payload,
+                    _ = server.shutdown.cancelled() => None,
+                };
+
+                match payload {
+                    // If we receive a payload, queue up its deliveries in order and handle it
+                    // alongside the others. Roles queue up as a whole until they are expanded.
                     Some(payload) => {
-                        Self::client_request_handler(
+                        let turns = payload
+                            .recipients
+                            .iter()
+                            .map(|(recipient, _)| server.take_turn(&recipient.to_string()))
+                            .collect();
+                        let handler = Self::client_request_handler(
                             server.clone(),
-                            bot.clone(),
+                            transport.clone(),
                             db.clone(),
                             payload,
-                        )
-                        .await
+                            turns,
+                        );
+                        server.tracker.spawn(async move {
+                            handler.await;
+                            drop(permit);
+                        });
                     }
 
-                    // Sender has gone out of scope; break the loop
+                    // Sender has gone out of scope or we are shutting down; break the loop
                     None => break,
                 };
             }
 
             warn!("Shutting down receiver");
+
+            // Refuse new payloads and turn away anything still queued
+            server_socket.close();
+            while let Ok(payload) = server_socket.try_recv() {
+                for (_, client_response_sender) in payload.recipients {
+                    if let Some(client_response_sender) = client_response_sender {
+                        let _ = client_response_sender.send(Err(types::BauBotError::Shutdown));
+                    }
+                }
+            }
         }
     }
 
-    /// Handler
+    /// Handler. Every recipient is delivered to concurrently, but only after the earlier
+    /// [types::BauMessage]s to the same recipient (see `turns`, one per recipient).
     fn client_request_handler<
-        Db: BauData + Send + Sync,
+        Db: BauData + Send + Sync + 'static,
         DbRef: Deref<Target = Db> + Clone + Send + Sync + 'static,
+        T: BauTransport,
     >(
         server: Arc<Self>,
-        bot: Bot,
+        transport: T,
         db: DbRef,
         bau_message: types::BauMessage,
+        turns: Vec<Turn>,
     ) -> impl std::future::Future<Output = ()> + Send + 'static {
         trace!("Payload received");
 
         async move {
             // Deconstruct message
             let types::BauMessage {
+                id,
                 sender: _,
                 recipients,
                 message,
-                responses: types::RequestedResponses { timeout, keyboard },
+                format,
+                attachments,
+                responses:
+                    types::RequestedResponses {
+                        timeout,
+                        keyboard,
+                        quorum,
+                        free_text,
+                    },
+                outcome,
             } = bau_message;
 
-            // Convert responses into keyboard
-            let keyboard = keyboard
+            // Prepare the message for its format
+            let (message, parse_mode) = match format {
+                Some(format) => format.render(message),
+                None => (message, server.config.parse_mode),
+            };
+
+            // Messages relying on the configured parse mode are checked here, as telegram would
+            // refuse malformed HTML (an explicit format was checked when deserialized)
+            let checked = match (format, parse_mode) {
+                (None, Some(ParseMode::Html)) => html::validate(&message),
+                _ => Ok(()),
+            };
+
+            // Only local files within the attachment root may be sent
+            let resolved = match checked {
+                Ok(()) => server.config.resolve_attachments(attachments).await,
+                Err(description) => Err(description),
+            };
+            let attachments = match resolved {
+                Ok(attachments) => attachments,
+                Err(description) => {
+                    warn!("Refusing to broadcast: {description}");
+                    for (_, client_response_sender) in recipients {
+                        if let Some(client_response_sender) = client_response_sender {
+                            let _ =
+                                client_response_sender.send(Err(types::BauBotError::Rejected {
+                                    description: description.clone(),
+                                }));
+                        }
+                    }
+                    return;
+                }
+            };
+
+            // Replace roles with their members
+            let (recipients, roles) = server.expand_roles(&*db, recipients, turns).await;
+
+            // Convert responses into a free text prompt or a keyboard. Buttons carry their index
+            // as callback data, which is mapped back to their value once pressed.
+            let buttons = keyboard
                 .iter()
-                .map(|row| {
-                    row.iter()
-                        .map(|field| InlineKeyboardButton::callback(field.clone(), field.clone()))
-                        .collect()
-                })
-                .collect::<Vec<Vec<_>>>();
-
-            // Run through each recipient
-            for (recipient, client_response_sender) in recipients {
-                // Get chat_id
-                let chat_id = db.get_chat_id(&recipient).await;
-
-                // Attempt to send the message
-                let send_attempt = Self::message_sender(
-                    bot.clone(),
-                    chat_id.clone(),
-                    message.clone(),
-                    keyboard.clone(),
-                )
-                .await;
+                .flatten()
+                .map(|button| button.value().to_string())
+                .collect();
+            let reply_markup = if free_text {
+                Some(ReplyMarkup::ForceReply(ForceReply::new()))
+            } else if !keyboard.is_empty() {
+                let mut index = 0;
+                let keyboard = keyboard
+                    .iter()
+                    .map(|row| {
+                        row.iter()
+                            .map(|button| {
+                                let callback_data = index.to_string();
+                                index += 1;
+                                InlineKeyboardButton::callback(button.label(), callback_data)
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                    .collect::<Vec<_>>();
+                Some(ReplyMarkup::InlineKeyboard(InlineKeyboardMarkup::new(
+                    keyboard,
+                )))
+            } else {
+                None
+            };
+            let delivery = Arc::new(Delivery {
+                id,
+                message,
+                attachments,
+                parse_mode,
+                reply_markup,
+                timeout,
+                buttons,
+            });
 
-                // These next steps apply only if a bau_response_sender was provided and a response
-                // is required
-                if let (Some(chat_id), Some(client_response_sender), false) =
-                    (chat_id, client_response_sender, keyboard.is_empty())
-                {
-                    tokio::task::spawn(Self::response_handler(
+            // Route every response through the quorum, if there is one
+            let quorum_channel = (quorum != types::Quorum::Independent
+                && delivery.reply_markup.is_some())
+            .then(mpsc::unbounded_channel);
+
+            // Deliver to each recipient
+            let deliveries = recipients
+                .into_iter()
+                .enumerate()
+                .map(|(index, ((recipient, client_response_sender), turn))| {
+                    let client_response_sender = match &quorum_channel {
+                        Some((quorum_sender, _)) => Some(server.quorum_relay(
+                            index,
+                            quorum_sender.clone(),
+                            client_response_sender,
+                        )),
+                        None => client_response_sender,
+                    };
+                    let broadcast = server.tracker.spawn(Self::deliver(
                         server.clone(),
-                        bot.clone(),
+                        transport.clone(),
+                        db.clone(),
+                        delivery.clone(),
+                        recipient.clone(),
+                        client_response_sender,
+                        turn,
+                    ));
+                    (recipient.to_string(), broadcast)
+                })
+                .collect::<Vec<_>>();
+            let mut broadcasts = Vec::new();
+            for (recipient, broadcast) in deliveries {
+                broadcasts.push((recipient, broadcast.await.ok().flatten()));
+            }
+
+            // Once a member responds for a role, the other members no longer have to
+            for (role, members, answered) in roles {
+                server.tracker.spawn(Self::role_handler(
+                    server.clone(),
+                    transport.clone(),
+                    db.clone(),
+                    role,
+                    answered,
+                    broadcasts[members]
+                        .iter()
+                        .filter_map(|(_, broadcast)| *broadcast)
+                        .collect(),
+                ));
+            }
+
+            // Every broadcast is registered by now so the quorum is able to retract them
+            if let Some((_, quorum_receiver)) = quorum_channel {
+                server.tracker.spawn(Self::quorum_handler(
+                    server.clone(),
+                    transport,
+                    db,
+                    quorum,
+                    quorum_receiver,
+                    broadcasts,
+                    outcome,
+                ));
+            }
+        }
+    }
+
+    /// Send `delivery` to `recipient` once it is their `turn` and a delivery slot is free (see
+    /// [crate::config::Config::concurrency]), then wait for their response if one is required.
+    /// Returns the `(chat_id, message_id)` of the broadcast, if it was sent.
+    async fn deliver<
+        Db: BauData + Send + Sync + 'static,
+        DbRef: Deref<Target = Db> + Clone + Send + Sync + 'static,
+        T: BauTransport,
+    >(
+        server: Arc<Self>,
+        transport: T,
+        db: DbRef,
+        delivery: Arc<Delivery>,
+        recipient: types::Recipient,
+        client_response_sender: Option<types::BauResponseSender>,
+        turn: Turn,
+    ) -> Option<(i64, i32)> {
+        // Wait for the earlier messages to the recipient, then for a slot
+        if let Some(previous) = turn.previous {
+            let _ = previous.await;
+        }
+        // NOTE: Safe to unwrap because the semaphore is never closed
+        let permit = server.deliveries.acquire().await.unwrap();
+
+        // Get chat_id
+        let chat_id = match &recipient {
+            types::Recipient::User(username) => db.get_chat_id(username).await,
+            types::Recipient::Chat(chat_id) => Some(*chat_id),
+            types::Recipient::Group(alias) => db.get_group_chat_id(alias).await,
+            // Roles are expanded by [Server::client_request_handler] before they get here
+            types::Recipient::Role(_) => None,
+        };
+
+        // Attempt to send the message
+        let send_attempt = Self::message_sender(
+            server.clone(),
+            transport.clone(),
+            chat_id,
+            delivery.message.clone(),
+            delivery.attachments.clone(),
+            delivery.parse_mode,
+            delivery.reply_markup.clone(),
+        )
+        .await;
+
+        // Let the next message to the recipient go
+        drop(permit);
+        drop(turn.done);
+        let broadcast = chat_id.zip(send_attempt.as_ref().ok().copied());
+
+        // These next steps apply only if a bau_response_sender was provided
+        if let Some(client_response_sender) = client_response_sender {
+            match (send_attempt, chat_id) {
+                // Message was validly out to recipient and a response is required: now we wait
+                // for a response
+                (Ok(message_id), Some(chat_id)) if delivery.reply_markup.is_some() => {
+                    let telegram_id = match &recipient {
+                        types::Recipient::User(username) => db.get_telegram_id(username).await,
+                        _ => None,
+                    };
+                    let pending = types::PendingRequest {
+                        request_id: delivery.id.clone(),
+                        recipient: recipient.to_string(),
+                        telegram_id,
                         chat_id,
-                        send_attempt,
+                        message_id,
+                        deadline: now_millis() + delivery.timeout,
+                        timeout: delivery.timeout,
+                        buttons: delivery.buttons.clone(),
+                    };
+
+                    // Persist so that the request survives a restart
+                    if let Err(err) = db.save_pending(&pending).await {
+                        error!("Unable to save pending request: {err}");
+                    }
+
+                    let bau_response_receiver = server.register(&pending).await;
+                    server.tracker.spawn(Self::await_response(
+                        server.clone(),
+                        transport,
+                        db,
+                        pending,
+                        bau_response_receiver,
                         client_response_sender,
-                        timeout,
                     ));
                 }
+
+                // No response required
+                (Ok(_), _) => {}
+
+                // Message was not validly sent out to recipient
+                (Err(err), _) => {
+                    let _ = client_response_sender.send(Err(err));
+                }
+            }
+        }
+
+        broadcast
+    }
+
+    /// Queue up behind the earlier [types::BauMessage]s to `recipient`.
+    fn take_turn(&self, recipient: &str) -> Turn {
+        let (done, next) = oneshot::channel();
+
+        // WARN: OBTAINING MUTEX
+        let mut guard = self.turns.lock().unwrap();
+
+        // Forget the recipients that have nothing in flight
+        guard.retain(|_, previous| {
+            matches!(
+                previous.try_recv(),
+                Err(oneshot::error::TryRecvError::Empty)
+            )
+        });
+        let previous = guard.insert(recipient.to_string(), next);
+        // WARN: DROPPING MUTEX
+
+        Turn { previous, done }
+    }
+
+    /// Replace every [types::Recipient::Role] of `recipients` with its members, each queued up
+    /// behind the earlier [types::BauMessage]s to them once the earlier ones to the role have been
+    /// expanded. Roles without members are answered with [types::BauBotError::Unregistered]
+    /// straight away. Also returns the members of every role that awaits a response, by their
+    /// position, along with a receiver that resolves once one of them has responded.
+    async fn expand_roles<Db: BauData>(
+        &self,
+        db: &Db,
+        recipients: Vec<(types::Recipient, Option<types::BauResponseSender>)>,
+        turns: Vec<Turn>,
+    ) -> (
+        Vec<((types::Recipient, Option<types::BauResponseSender>), Turn)>,
+        Vec<(String, std::ops::Range<usize>, oneshot::Receiver<()>)>,
+    ) {
+        let mut expanded = Vec::new();
+        let mut roles = Vec::new();
+        for ((recipient, client_response_sender), turn) in recipients.into_iter().zip(turns) {
+            let types::Recipient::Role(role) = recipient else {
+                expanded.push(((recipient, client_response_sender), turn));
+                continue;
+            };
+
+            // Wait for the earlier messages to the role to take their turns with its members
+            if let Some(previous) = turn.previous {
+                let _ = previous.await;
+            }
+
+            let members = db.get_role_members(&role).await;
+            if members.is_empty() {
+                warn!("Role {role} has no members");
+                if let Some(client_response_sender) = client_response_sender {
+                    let _ = client_response_sender.send(Err(types::BauBotError::Unregistered));
+                }
+                continue;
+            }
+
+            let member_senders: Vec<_> = match client_response_sender {
+                Some(client_response_sender) => {
+                    let (member_senders, answered) =
+                        self.role_relay(members.len(), client_response_sender);
+                    let start = expanded.len();
+                    roles.push((role, start..start + members.len(), answered));
+                    member_senders.into_iter().map(Some).collect()
+                }
+                None => (0..members.len()).map(|_| None).collect(),
+            };
+            for (member, member_sender) in members.into_iter().zip(member_senders) {
+                let turn = self.take_turn(&member);
+                expanded.push(((types::Recipient::User(member), member_sender), turn));
+            }
+            // Let the next message to the role go
+            drop(turn.done);
+        }
+        (expanded, roles)
+    }
+
+    /// Returns a [types::BauResponseSender] for each of the `members` of a role. The first
+    /// successful response of any member is passed on to `client_response_sender`, or the last
+    /// error if none of them succeeds. The returned receiver resolves once a member succeeded.
+    fn role_relay(
+        &self,
+        members: usize,
+        client_response_sender: types::BauResponseSender,
+    ) -> (Vec<types::BauResponseSender>, oneshot::Receiver<()>) {
+        let (role_sender, mut role_receiver) = mpsc::unbounded_channel();
+        let (answered_sender, answered_receiver) = oneshot::channel();
+        let member_senders = (0..members)
+            .map(|_| {
+                let (bau_response_sender, bau_response_receiver) = oneshot::channel();
+                let role_sender = role_sender.clone();
+                self.tracker.spawn(async move {
+                    // See documentation for timeout
+                    let response = bau_response_receiver
+                        .await
+                        .unwrap_or(Err(types::BauBotError::Timeout));
+                    let _ = role_sender.send(response);
+                });
+                bau_response_sender
+            })
+            .collect();
+
+        self.tracker.spawn(async move {
+            let mut response = Err(types::BauBotError::Unregistered);
+            while let Some(member_response) = role_receiver.recv().await {
+                response = member_response;
+                if response.is_ok() {
+                    let _ = answered_sender.send(());
+                    break;
+                }
+            }
+            let _ = client_response_sender.send(response);
+        });
+        (member_senders, answered_receiver)
+    }
+
+    /// Retracts the `broadcasts` to the members of `role` still waiting for a response with
+    /// [types::BauBotError::Superseded] once `answered` resolves (see [Server::role_relay]).
+    async fn role_handler<
+        Db: BauData + Send + Sync + 'static,
+        DbRef: Deref<Target = Db> + Clone + Send + Sync + 'static,
+        T: BauTransport,
+    >(
+        server: Arc<Self>,
+        transport: T,
+        db: DbRef,
+        role: String,
+        answered: oneshot::Receiver<()>,
+        broadcasts: Vec<(i64, i32)>,
+    ) {
+        // Nobody responded successfully, so there is nothing left to retract
+        if answered.await.is_err() {
+            return;
+        }
+
+        let message = format!(
+            crate::fmt!(pass "Another member of <code>%{}</code> responded. No response required."),
+            role
+        );
+        for broadcast in broadcasts {
+            Self::retract(
+                &server,
+                &transport,
+                &db,
+                broadcast,
+                types::BauBotError::Superseded,
+                Some(message.clone()),
+            )
+            .await;
+        }
+    }
+
+    /// Returns a [types::BauResponseSender] that passes the response of recipient `index` on to
+    /// the quorum (through `quorum_sender`) and then to `client_response_sender`, if any.
+    fn quorum_relay(
+        &self,
+        index: usize,
+        quorum_sender: mpsc::UnboundedSender<(usize, types::BauResponse)>,
+        client_response_sender: Option<types::BauResponseSender>,
+    ) -> types::BauResponseSender {
+        let (bau_response_sender, bau_response_receiver) = oneshot::channel();
+        self.tracker.spawn(async move {
+            // See documentation for timeout
+            let response = bau_response_receiver
+                .await
+                .unwrap_or(Err(types::BauBotError::Timeout));
+            let _ = quorum_sender.send((index, response.clone()));
+            if let Some(client_response_sender) = client_response_sender {
+                let _ = client_response_sender.send(response);
+            }
+        });
+        bau_response_sender
+    }
+
+    /// Collects the responses relayed by [Server::quorum_relay] until `quorum` is decided, then
+    /// sends the [types::QuorumOutcome] and retracts the `broadcasts` still waiting for a
+    /// response.
+    async fn quorum_handler<
+        Db: BauData + Send + Sync + 'static,
+        DbRef: Deref<Target = Db> + Clone + Send + Sync + 'static,
+        T: BauTransport,
+    >(
+        server: Arc<Self>,
+        transport: T,
+        db: DbRef,
+        quorum: types::Quorum,
+        mut quorum_receiver: mpsc::UnboundedReceiver<(usize, types::BauResponse)>,
+        broadcasts: Vec<(String, Option<(i64, i32)>)>,
+        outcome_sender: Option<types::QuorumOutcomeSender>,
+    ) {
+        let mut responses = Vec::new();
+        let mut responded = vec![false; broadcasts.len()];
+
+        let outcome = loop {
+            // NOTE: Every relay sends exactly once and the quorum is decided at the latest once
+            // everyone has responded, so this only runs dry if the relays were aborted
+            let Some((index, response)) = quorum_receiver.recv().await else {
+                return;
+            };
+            responded[index] = true;
+            responses.push((broadcasts[index].0.clone(), response));
+
+            if let Some(outcome) = quorum.decide(&responses, broadcasts.len()) {
+                break outcome;
+            }
+        };
+        trace!("Quorum decided: {outcome:?}");
+
+        // Tell the remaining recipients how the request was decided
+        let message = match &outcome {
+            types::QuorumOutcome::Answered {
+                recipient,
+                response,
+            } => format!(
+                crate::fmt!(pass "{} responded with <code>{}</code>. No response required."),
+                recipient, response
+            ),
+            types::QuorumOutcome::Approved { .. } => {
+                crate::fmt!(pass "Request approved. No response required.").to_string()
             }
+            types::QuorumOutcome::Rejected { .. } | types::QuorumOutcome::Unanswered => {
+                crate::fmt!(fail "Request rejected. No response required.").to_string()
+            }
+        };
+        for ((_, broadcast), responded) in broadcasts.into_iter().zip(responded) {
+            if let (Some((chat_id, message_id)), false) = (broadcast, responded) {
+                Self::retract(
+                    &server,
+                    &transport,
+                    &db,
+                    (chat_id, message_id),
+                    types::BauBotError::Superseded,
+                    Some(message.clone()),
+                )
+                .await;
+            }
+        }
+
+        if let Some(outcome_sender) = outcome_sender {
+            let _ = outcome_sender.send(outcome);
         }
     }
 
+    /// Resolve the request waiting on `message_id` in `chat_id` with `error`, strip its keyboard
+    /// and reply with `message`, if any. Returns `false` if the request was no longer waiting.
+    async fn retract<
+        Db: BauData + Send + Sync + 'static,
+        DbRef: Deref<Target = Db> + Clone + Send + Sync + 'static,
+        T: BauTransport,
+    >(
+        server: &Arc<Self>,
+        transport: &T,
+        db: &DbRef,
+        (chat_id, message_id): (i64, i32),
+        error: types::BauBotError,
+        message: Option<String>,
+    ) -> bool {
+        let key = Self::make_key(chat_id, message_id);
+        let bau_response_sender = {
+            // WARN: OBTAINING MUTEX
+            let mut guard = server.store.lock().await;
+            guard.remove(&key)
+            // WARN: DROPPING MUTEX
+        };
+        let Some(bau_response_sender) = bau_response_sender else {
+            return false;
+        };
+
+        trace!("Retracting {key}: {error:?}");
+        let _ = bau_response_sender.send(Err(error));
+        let _ = transport.remove_markup(chat_id, message_id).await;
+        if let Err(err) = db.remove_pending(chat_id, message_id).await {
+            error!("Unable to remove pending request {key}: {err}");
+        }
+        if let Some(message) = message {
+            let _ = transport.reply_message(chat_id, message_id, message).await;
+        }
+        true
+    }
+
+    /// Retract every broadcast of the request `request_id` that is still waiting for a response
+    /// with [types::BauBotError::Cancelled]. If `withdraw` is set the text of the broadcasts is
+    /// replaced by [crate::config::Config::withdrawn_text], otherwise the recipients are told
+    /// that no response is required. Returns the number of broadcasts retracted.
+    pub(crate) async fn cancel<
+        Db: BauData + Send + Sync + 'static,
+        DbRef: Deref<Target = Db> + Clone + Send + Sync + 'static,
+        T: BauTransport,
+    >(
+        server: &Arc<Self>,
+        transport: &T,
+        db: &DbRef,
+        request_id: &str,
+        withdraw: bool,
+    ) -> usize {
+        let broadcasts = {
+            // WARN: OBTAINING MUTEX
+            let mut guard = server.requests.lock().await;
+            guard.remove(request_id).unwrap_or_default()
+            // WARN: DROPPING MUTEX
+        };
+        trace!("Cancelling {request_id}: {broadcasts:?}");
+
+        let message = (!withdraw)
+            .then(|| crate::fmt!(fail "Request cancelled. No response required.").to_string());
+        let mut retracted = 0;
+        for (chat_id, message_id) in broadcasts {
+            if !Self::retract(
+                server,
+                transport,
+                db,
+                (chat_id, message_id),
+                types::BauBotError::Cancelled,
+                message.clone(),
+            )
+            .await
+            {
+                continue;
+            }
+            if withdraw {
+                let _ = transport
+                    .edit_text(chat_id, message_id, server.config.withdrawn_text.clone())
+                    .await;
+            }
+            retracted += 1;
+        }
+        retracted
+    }
+
     /// Sends the actual message
-    fn message_sender(
-        bot: Bot,
+    fn message_sender<T: BauTransport>(
+        server: Arc<Self>,
+        transport: T,
         chat_id: Option<i64>,
         message: String,
-        responses: Vec<Vec<InlineKeyboardButton>>,
+        attachments: Vec<types::Attachment>,
+        parse_mode: Option<ParseMode>,
+        reply_markup: Option<ReplyMarkup>,
     ) -> impl std::future::Future<Output = std::result::Result<i32, types::BauBotError>> + Send + 'static
     {
         async move {
             // Chck if chat ID exists
-            match chat_id {
-                Some(chat_id) => {
-                    trace!("Attempting to broadcast to {chat_id}: {message}");
+            let chat_id = chat_id.ok_or(types::BauBotError::Unregistered)?;
+            trace!("Attempting to broadcast to {chat_id}: {message}");
 
-                    // Send message to user
-                    let mut message_sender = bot.send_message(ChatId(chat_id), message.clone());
+            // Poll send message, telling the client why it failed if it does
+            server
+                .broadcast(
+                    &transport,
+                    chat_id,
+                    message,
+                    attachments,
+                    parse_mode,
+                    reply_markup,
+                )
+                .await
+                .map_err(|err| {
+                    warn!("Unable to broadcast to {chat_id}: {err}");
+                    err.into()
+                })
+        }
+    }
 
-                    // Check if keyboard responses provided
-                    if !responses.is_empty() {
-                        message_sender =
-                            message_sender.reply_markup(InlineKeyboardMarkup::new(responses))
-                    }
+    /// Send `message` and its `attachments` to `chat_id`, splitting `message` over several
+    /// messages if it is too long. Returns the `message_id` of the final message, which is the one
+    /// carrying `reply_markup`. An empty `message` without `reply_markup` is not sent at all if
+    /// there are attachments.
+    async fn broadcast<T: BauTransport>(
+        &self,
+        transport: &T,
+        chat_id: i64,
+        message: String,
+        attachments: Vec<types::Attachment>,
+        parse_mode: Option<ParseMode>,
+        reply_markup: Option<ReplyMarkup>,
+    ) -> Result<i32, teloxide::RequestError> {
+        // A single attachment carries the message as its caption if it fits
+        if let [attachment] = attachments.as_slice() {
+            if split::length(&message) <= MAX_CAPTION_LENGTH {
+                return self
+                    .send(chat_id, || {
+                        transport.send_attachment(
+                            chat_id,
+                            attachment.clone(),
+                            Some(message.clone()),
+                            parse_mode,
+                            reply_markup.clone(),
+                        )
+                    })
+                    .await;
+            }
+        }
 
-                    // Poll send message
-                    match message_sender.await {
-                        // If message succesfully sent, return the response receiver
-                        Ok(message) => Some(message.id.0),
+        // Otherwise send photos and documents as separate albums (telegram does not mix them),
+        // followed by the message (in parts if needed)
+        let (photos, documents): (Vec<_>, Vec<_>) = attachments
+            .into_iter()
+            .partition(types::Attachment::is_photo);
+        let mut last_attachment = None;
+        for album in [photos, documents] {
+            for chunk in album.chunks(MAX_MEDIA_GROUP) {
+                last_attachment = match chunk {
+                    [attachment] => Some(
+                        self.send(chat_id, || {
+                            transport.send_attachment(chat_id, attachment.clone(), None, None, None)
+                        })
+                        .await?,
+                    ),
+                    chunk => self
+                        .send(chat_id, || {
+                            transport.send_media_group(chat_id, chunk.to_vec())
+                        })
+                        .await?
+                        .pop(),
+                };
+            }
+        }
+        if let Some(message_id) = last_attachment {
+            if message.is_empty() && reply_markup.is_none() {
+                return Ok(message_id);
+            }
+        }
+        let mut parts = split::split(&message, parse_mode, crate::config::MAX_TEXT_LENGTH);
+        // NOTE: Safe to unwrap because [split::split] returns at least one part
+        let last = parts.pop().unwrap();
+        for part in parts {
+            self.send(chat_id, || {
+                transport.send_message(chat_id, part.clone(), parse_mode, None)
+            })
+            .await?;
+        }
+        self.send(chat_id, || {
+            transport.send_message(chat_id, last.clone(), parse_mode, reply_markup.clone())
+        })
+        .await
+    }
 
-                        // Else...
-                        Err(_) => None,
-                    }
-                }
-                None => None,
+    /// Make a single call to telegram for `chat_id` through `send`, waiting for the rate limits
+    /// first. Transient failures are retried according to [crate::config::Config::retry_policy].
+    async fn send<R, F, Fut>(&self, chat_id: i64, mut send: F) -> Result<R, teloxide::RequestError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<R, teloxide::RequestError>>,
+    {
+        let retry_policy = &self.config.retry_policy;
+        let mut backoff = retry_policy.backoff;
+        let mut attempt = 1;
+        loop {
+            self.throttle.acquire(chat_id).await;
+            let err = match send().await {
+                Ok(result) => break Ok(result),
+                Err(err) => err,
+            };
+
+            let Some(delay) =
+                retry_delay(&err, backoff).filter(|_| attempt < retry_policy.attempts)
+            else {
+                break Err(err);
+            };
+            warn!("Attempt {attempt} to send to {chat_id} failed, retrying in {delay:?}: {err}");
+
+            // Give up straight away on shutdown
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = self.shutdown.cancelled() => break Err(err),
             }
-            .ok_or(types::BauBotError::Uncontactable)
+            backoff = (backoff * 2).min(retry_policy.max_backoff);
+            attempt += 1;
         }
     }
 
@@ -175,81 +941,271 @@ impl Server {
         chat_id | (message_id as i128)
     }
 
-    /// Actual pipeline between [types::ServerSocket] and [crate::BauBot]
-    fn response_handler(
+    /// Add `pending` to the store. Returns the receiver for the response of the recipient.
+    async fn register(&self, pending: &types::PendingRequest) -> types::BauResponseReceiver {
+        // Create senders and receivers to listen for responses from baubot
+        let (bau_response_sender, bau_response_receiver) = oneshot::channel();
+
+        // Create key
+        let key = Self::make_key(pending.chat_id, pending.message_id);
+        trace!("Key for bau_response_sender: {key}.");
+
+        // Add message to map
+        {
+            // WARN: OBTAINING MUTEX
+            let mut guard = self.store.lock().await;
+            guard.insert(key, bau_response_sender);
+            // WARN: DROPPING MUTEX
+        }
+        {
+            // WARN: OBTAINING MUTEX
+            let mut guard = self.registered.lock().unwrap();
+            guard.insert(key, pending.clone());
+            // WARN: DROPPING MUTEX
+        }
+
+        // Make the message cancellable
+        if let Some(request_id) = &pending.request_id {
+            // WARN: OBTAINING MUTEX
+            let mut guard = self.requests.lock().await;
+            guard
+                .entry(request_id.clone())
+                .or_default()
+                .push((pending.chat_id, pending.message_id));
+            // WARN: DROPPING MUTEX
+        }
+
+        bau_response_receiver
+    }
+
+    /// Undo the [Server::register]ation of a `pending` request that is no longer waiting, so that
+    /// it is no longer cancellable.
+    async fn unregister(&self, pending: &types::PendingRequest) {
+        {
+            // WARN: OBTAINING MUTEX
+            let mut guard = self.registered.lock().unwrap();
+            guard.remove(&Self::make_key(pending.chat_id, pending.message_id));
+            // WARN: DROPPING MUTEX
+        }
+
+        let Some(request_id) = &pending.request_id else {
+            return;
+        };
+
+        // WARN: OBTAINING MUTEX
+        let mut guard = self.requests.lock().await;
+        if let Some(broadcasts) = guard.get_mut(request_id) {
+            broadcasts.retain(|broadcast| *broadcast != (pending.chat_id, pending.message_id));
+            if broadcasts.is_empty() {
+                guard.remove(request_id);
+            }
+        }
+        // WARN: DROPPING MUTEX
+    }
+
+    /// Wait for a response to the [Server::register]ed `pending` and pass it on to
+    /// `client_response_sender`.
+    async fn await_response<
+        Db: BauData + Send + Sync + 'static,
+        DbRef: Deref<Target = Db> + Clone + Send + Sync + 'static,
+        T: BauTransport,
+    >(
         server: Arc<Self>,
-        bot: Bot,
-        chat_id: i64,
-        send_attempt: std::result::Result<i32, types::BauBotError>,
+        transport: T,
+        db: DbRef,
+        pending: types::PendingRequest,
+        bau_response_receiver: types::BauResponseReceiver,
         client_response_sender: types::BauResponseSender,
-        timeout: u64,
-    ) -> impl std::future::Future<Output = ()> + Send {
-        async move {
-            // Check send_attempt
-            let _ = match send_attempt {
-                // Message was validly out to recipient: now we wait for a response
-                Ok(message_id) => {
-                    trace!("Waiting for response on message {message_id} on chat {chat_id}.");
-
-                    // Create senders and receivers to listen for responses from baubot
-                    let (bau_response_sender, bau_response_receiver) = oneshot::channel();
-
-                    // Create key
-                    let key = Self::make_key(chat_id, message_id);
-                    trace!("Key for bau_response_sender: {key}.");
-
-                    // Add message to map
-                    {
-                        // WARN: OBTAINING MUTEX
-                        let mut guard = server.store.lock().await;
-                        guard.insert(key, bau_response_sender);
-                        // WARN: DROPPING MUTEX
-                    }
+    ) {
+        let types::PendingRequest {
+            chat_id,
+            message_id,
+            ..
+        } = pending;
+        trace!("Waiting for response on message {message_id} on chat {chat_id}.");
 
-                    // Spawn removal hook. The deletion / dropping of the receiver will cause the
-                    // next poll on bau_response_receiver to fail
-                    tokio::task::spawn(async move {
-                        // Run a timeout
-                        tokio::time::sleep(std::time::Duration::from_millis(timeout)).await;
-
-                        // Remove response options
-                        let _ = Self::remove_markup(&bot, chat_id, message_id).await;
-
-                        // WARN: OBTAINING MUTEX
-                        let mut guard = server.store.lock().await;
-                        if let Some(_) = guard.remove(&key) {
-                            trace!("Timeout ({timeout}ms) for {key}");
-
-                            // Notify user of timeout
-                            let message =
-                                format!(crate::fmt!(timeout "Timeout ({}ms) exceeded"), timeout);
-                            let _ = reply_message(&bot, chat_id, message_id, message).await;
-                        };
-                        // WARN: DROPPING MUTEX
-                        // WARN: DROPPING RECEIVER; transaction ends here.
-                    });
-
-                    // Wait for responses from baubot
-                    match bau_response_receiver.await {
-                        // Respond okay if baubot sent us a respones on bau_response_receiver
-                        Ok(ok) => client_response_sender.send(ok),
-
-                        // See documentation for timeout
-                        Err(_) => client_response_sender.send(Err(types::BauBotError::Timeout)),
-                    }
+        // Spawn removal hook. The deletion / dropping of the receiver will cause the next poll
+        // on bau_response_receiver to fail
+        server.tracker.spawn(Self::expiry_hook(
+            server.clone(),
+            transport,
+            db,
+            pending.clone(),
+        ));
+
+        // Wait for responses from baubot
+        let _ = match bau_response_receiver.await {
+            // Respond okay if baubot sent us a respones on bau_response_receiver
+            Ok(ok) => client_response_sender.send(ok),
+
+            // See documentation for timeout
+            Err(_) => client_response_sender.send(Err(types::BauBotError::Timeout)),
+        };
+        server.unregister(&pending).await;
+    }
+
+    /// Removal hook for `pending`. Runs until its deadline (cut short if we are shutting down),
+    /// then resolves the request if it is still waiting, strips the keyboard and notifies the
+    /// recipient.
+    async fn expiry_hook<
+        Db: BauData + Send + Sync + 'static,
+        DbRef: Deref<Target = Db> + Clone + Send + Sync + 'static,
+        T: BauTransport,
+    >(
+        server: Arc<Self>,
+        transport: T,
+        db: DbRef,
+        pending: types::PendingRequest,
+    ) {
+        let types::PendingRequest {
+            request_id,
+            chat_id,
+            message_id,
+            deadline,
+            timeout,
+            ..
+        } = pending;
+        let key = Self::make_key(chat_id, message_id);
+
+        // Run a timeout, cut short if we are shutting down
+        let duration = std::time::Duration::from_millis(deadline.saturating_sub(now_millis()));
+        let shutdown = tokio::select! {
+            _ = tokio::time::sleep(duration) => false,
+            _ = server.shutdown.cancelled() => true,
+        };
+
+        // WARN: OBTAINING MUTEX
+        let mut guard = server.store.lock().await;
+        let Some(bau_response_sender) = guard.remove(&key) else {
+            // Already answered
+            return;
+        };
+
+        // Leave the message and its record alone so that the next run can resume it
+        let resume = server.config.restore_policy == crate::config::RestorePolicy::Resume
+            && request_id.is_some();
+        if shutdown && resume {
+            trace!("Shutdown for {key}, keeping it for the next run");
+            let _ = bau_response_sender.send(Err(types::BauBotError::Shutdown));
+            return;
+        }
+
+        // Remove response options
+        let _ = transport.remove_markup(chat_id, message_id).await;
+        if let Err(err) = db.remove_pending(chat_id, message_id).await {
+            error!("Unable to remove pending request {key}: {err}");
+        }
+
+        // Notify user of timeout or shutdown
+        let message = if shutdown {
+            trace!("Shutdown for {key}");
+            let _ = bau_response_sender.send(Err(types::BauBotError::Shutdown));
+            crate::fmt!(fail "Baubot is shutting down. No response required.").to_string()
+        } else {
+            trace!("Timeout ({timeout}ms) for {key}");
+            server.config.timeout_text(timeout)
+        };
+        let _ = transport.reply_message(chat_id, message_id, message).await;
+        // WARN: DROPPING MUTEX
+        // WARN: DROPPING RECEIVER; transaction ends here.
+    }
+
+    /// Restore the [types::PendingRequest]s left behind by a previous run according to
+    /// [crate::config::RestorePolicy].
+    pub(crate) async fn restore<
+        Db: BauData + Send + Sync + 'static,
+        DbRef: Deref<Target = Db> + Clone + Send + Sync + 'static,
+        T: BauTransport,
+    >(
+        server: Arc<Self>,
+        transport: T,
+        db: DbRef,
+    ) {
+        let pending = match db.load_pending().await {
+            Ok(pending) => pending,
+            Err(err) => {
+                error!("Unable to load pending requests: {err}");
+                return;
+            }
+        };
+        trace!("Restoring {} pending requests", pending.len());
+
+        for pending in pending {
+            let types::PendingRequest {
+                chat_id,
+                message_id,
+                deadline,
+                ..
+            } = pending;
+
+            // Resume waiting if we have someone to hand the response to
+            if let (crate::config::RestorePolicy::Resume, Some(request_id), true) = (
+                server.config.restore_policy,
+                pending
+                    .request_id
+                    .clone()
+                    .filter(|request_id| !request_id.starts_with(REGISTRATION_PREFIX)),
+                deadline > now_millis(),
+            ) {
+                let (client_response_sender, client_response_receiver) = oneshot::channel();
+                {
+                    // WARN: OBTAINING MUTEX
+                    let mut guard = server.parked.lock().await;
+                    guard
+                        .entry(request_id)
+                        .or_default()
+                        .push((pending.recipient.clone(), client_response_receiver));
+                    // WARN: DROPPING MUTEX
                 }
+                let bau_response_receiver = server.register(&pending).await;
+                server.tracker.spawn(Self::await_response(
+                    server.clone(),
+                    transport.clone(),
+                    db.clone(),
+                    pending,
+                    bau_response_receiver,
+                    client_response_sender,
+                ));
+                continue;
+            }
 
-                // Message was not validly sent out to recipient
-                Err(err) => client_response_sender.send(Err(err)),
-            };
+            // Otherwise expire it
+            let _ = transport.remove_markup(chat_id, message_id).await;
+            let _ = transport
+                .reply_message(
+                    chat_id,
+                    message_id,
+                    crate::fmt!(timeout "Baubot restarted before you responded. This request has expired.")
+                        .to_string(),
+                )
+                .await;
+            if let Err(err) = db.remove_pending(chat_id, message_id).await {
+                error!("Unable to remove pending request: {err}");
+            }
         }
     }
 
+    /// Take the receivers for the responses to a restored request. See [crate::BauBot::reclaim].
+    pub(crate) async fn reclaim(
+        &self,
+        request_id: &str,
+    ) -> Vec<(String, types::BauResponseReceiver)> {
+        // WARN: OBTAINING MUTEX
+        let mut guard = self.parked.lock().await;
+        guard.remove(request_id).unwrap_or_default()
+        // WARN: DROPPING MUTEX
+    }
+
     /// Handles [CallbackQuery]
-    pub(crate) fn callback_handler(
-        bot: Bot,
+    pub(crate) fn callback_handler<
+        Db: BauData + Send + Sync + 'static,
+        DbRef: Deref<Target = Db> + Clone + Send + Sync + 'static,
+        T: BauTransport,
+    >(
+        transport: T,
         server: Arc<Self>,
-        (data, chat_id, message_id): (String, i64, i32),
+        db: DbRef,
+        (callback_id, data, chat_id, message_id, from): (String, String, i64, i32, User),
     ) -> impl std::future::Future<Output = Result<(), Box<dyn std::error::Error + Send + Sync>>> + Send
     {
         // Get key
@@ -259,6 +1215,23 @@ impl Server {
         trace!("Received response to callback for message {message_id}: {data} [key: {key}].",);
 
         async move {
+            // Only the recipient may respond (anyone in a group chat can press the button)
+            if let Some(recipient) = server.intruded(key, &from) {
+                if server.config.log_intruders {
+                    warn!(
+                        "Suspicious press on message {message_id} in chat {chat_id} by {} ({}) \
+                        instead of {recipient}",
+                        from.username.as_deref().unwrap_or("<no username>"),
+                        from.id
+                    );
+                }
+                let alert = format!(crate::fmt!(fail "This request is for @{} only."), recipient);
+                transport
+                    .answer_callback(callback_id, Some(alert), true)
+                    .await?;
+                return Ok(());
+            }
+
             // Obtain sender
             let bau_response_sender = {
                 // WARN: OBTAINING MUTEX
@@ -271,11 +1244,20 @@ impl Server {
             let message = match bau_response_sender {
                 // Valid bau_response_sender
                 Some(sender) => {
+                    // Map the callback data back to the value of the button
+                    let data = server.button_value(key, data);
+
                     // Send the response
                     let _ = sender.send(Ok(data.clone()));
+                    if let Err(err) = db.remove_pending(chat_id, message_id).await {
+                        error!("Unable to remove pending request {key}: {err}");
+                    }
 
                     // Return text
-                    format!(crate::fmt!(pass "<code>{}</code>"), data)
+                    format!(
+                        crate::fmt!(pass "<code>{}</code>"),
+                        teloxide::utils::html::escape(&data)
+                    )
                 }
 
                 // Invalid bau_response_sender, most likely removed due to a timeout.
@@ -284,18 +1266,88 @@ impl Server {
                 }
             };
 
+            // Stop the client from showing a progress bar
+            transport.answer_callback(callback_id, None, false).await?;
+
             // Remove response options
-            Self::remove_markup(&bot, chat_id, message_id).await?;
+            transport.remove_markup(chat_id, message_id).await?;
 
             // Send response to user
-            reply_message(&bot, chat_id, message_id, message).await?;
+            transport
+                .reply_message(chat_id, message_id, message)
+                .await?;
 
             Ok(())
         }
     }
 
-    /// Create a [UpdateHandler] for the [Bot]
-    pub(crate) fn callback_update() -> UpdateHandler<Box<dyn std::error::Error + Send + Sync>> {
+    /// Take `request_id` until it is released through [Server::release]. Returns `false` if it
+    /// is taken already, so that a request is only sent once however quickly it is repeated.
+    pub(crate) fn reserve(&self, request_id: &str) -> bool {
+        // WARN: OBTAINING MUTEX
+        self.reserved.lock().unwrap().insert(request_id.to_string())
+        // WARN: DROPPING MUTEX
+    }
+
+    /// Release `request_id` taken by [Server::reserve].
+    pub(crate) fn release(&self, request_id: &str) {
+        // WARN: OBTAINING MUTEX
+        self.reserved.lock().unwrap().remove(request_id);
+        // WARN: DROPPING MUTEX
+    }
+
+    /// Number of broadcasts waiting for a response.
+    pub(crate) fn pending(&self) -> usize {
+        // WARN: OBTAINING MUTEX
+        self.registered.lock().unwrap().len()
+        // WARN: DROPPING MUTEX
+    }
+
+    /// Value of the button of the request `key` that carries `data`. Falls back to `data` itself
+    /// for keyboards that carry their values directly.
+    fn button_value(&self, key: i128, data: String) -> String {
+        // WARN: OBTAINING MUTEX
+        let guard = self.registered.lock().unwrap();
+        data.parse::<usize>()
+            .ok()
+            .and_then(|index| guard.get(&key)?.buttons.get(index).cloned())
+            .unwrap_or(data)
+        // WARN: DROPPING MUTEX
+    }
+
+    /// The [types::PendingRequest::recipient] of the request `key`, if it is waiting for a
+    /// response from a user and `user` is someone else. Users are known by their telegram user id
+    /// where it is known, and by their username (which telegram compares case insensitively)
+    /// otherwise. Anyone in a [types::Recipient::Chat] or [types::Recipient::Group] may respond.
+    fn intruded(&self, key: i128, user: &User) -> Option<String> {
+        let (recipient, telegram_id) = {
+            // WARN: OBTAINING MUTEX
+            let guard = self.registered.lock().unwrap();
+            let pending = guard.get(&key)?;
+            (pending.recipient.parse().ok()?, pending.telegram_id)
+            // WARN: DROPPING MUTEX
+        };
+
+        match (recipient, &user.username) {
+            (types::Recipient::User(recipient), _) if telegram_id.is_some() => {
+                (telegram_id != Some(user.id.0)).then_some(recipient)
+            }
+            (types::Recipient::User(recipient), Some(username))
+                if username.eq_ignore_ascii_case(&recipient) =>
+            {
+                None
+            }
+            (types::Recipient::User(recipient), _) => Some(recipient),
+            _ => None,
+        }
+    }
+
+    /// Create a [UpdateHandler] for the [BauTransport]
+    pub(crate) fn callback_update<
+        Db: BauData + Send + Sync + 'static,
+        DbRef: Deref<Target = Db> + Clone + Send + Sync + 'static,
+        T: BauTransport,
+    >() -> UpdateHandler<HandlerError> {
         Update::filter_callback_query()
             .filter_map(|update: Update| {
                 let callback_query = if let UpdateKind::CallbackQuery(callback_query) = update.kind
@@ -305,6 +1357,8 @@ impl Server {
                     None
                 }?;
 
+                let callback_id = callback_query.id;
+                let from = callback_query.from;
                 let data = callback_query.data?;
                 let (chat_id, message_id) =
                     if let MaybeInaccessibleMessage::Regular(message) = callback_query.message? {
@@ -313,20 +1367,146 @@ impl Server {
                         None
                     }?;
 
-                Some((data, chat_id, message_id))
+                Some((callback_id, data, chat_id, message_id, from))
             })
-            .endpoint(Self::callback_handler)
+            .endpoint(Self::callback_handler::<Db, DbRef, T>)
     }
 
-    /// Instruct the bot to remove markup
-    async fn remove_markup(
-        bot: &Bot,
-        chat_id: i64,
-        message_id: i32,
-    ) -> Result<Message, teloxide::RequestError> {
-        let mut message_edit =
-            bot.edit_message_reply_markup(ChatId(chat_id), MessageId(message_id));
-        message_edit.reply_markup = None;
-        message_edit.await
+    /// Handles a text reply to a free text prompt (see [types::RequestedResponses::free_text])
+    async fn reply_handler<
+        Db: BauData + Send + Sync + 'static,
+        DbRef: Deref<Target = Db> + Clone + Send + Sync + 'static,
+        T: BauTransport,
+    >(
+        transport: T,
+        server: Arc<Self>,
+        db: DbRef,
+        from: User,
+        (chat_id, prompt_id, message_id, text): (i64, i32, i32, String),
+    ) -> Result<(), HandlerError> {
+        let key = Self::make_key(chat_id, prompt_id);
+        trace!("Received reply to prompt {prompt_id}: {text} [key: {key}].");
+
+        // Only the recipient may respond (anyone in a group chat can reply to the prompt)
+        if let Some(recipient) = server.intruded(key, &from) {
+            if server.config.log_intruders {
+                warn!(
+                    "Suspicious reply to prompt {prompt_id} in chat {chat_id} by {} ({}) \
+                    instead of {recipient}",
+                    from.username.as_deref().unwrap_or("<no username>"),
+                    from.id
+                );
+            }
+            let message = format!(crate::fmt!(fail "This request is for @{} only."), recipient);
+            transport
+                .reply_message(chat_id, message_id, message)
+                .await?;
+            return Ok(());
+        }
+
+        // Obtain sender
+        let bau_response_sender = {
+            // WARN: OBTAINING MUTEX
+            let mut guard = server.store.lock().await;
+            guard.remove(&key)
+            // WARN: DROPPING MUTEX
+        };
+
+        // Prepare an appropriate response for user
+        let message = match bau_response_sender {
+            Some(sender) => {
+                let message = format!(
+                    crate::fmt!(pass "<code>{}</code>"),
+                    teloxide::utils::html::escape(&text)
+                );
+                let _ = sender.send(Ok(text));
+                if let Err(err) = db.remove_pending(chat_id, prompt_id).await {
+                    error!("Unable to remove pending request {key}: {err}");
+                }
+                message
+            }
+
+            // Timed out between the filter and here
+            None => crate::fmt!(timeout "The recipient probably timed out 😭").to_string(),
+        };
+
+        // Send response to user
+        transport
+            .reply_message(chat_id, message_id, message)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Create a [UpdateHandler] branch for [Message]s that reply to a free text prompt still
+    /// waiting for a response. Replies to broadcasts with a keyboard are not taken as responses.
+    pub(crate) fn reply_update<
+        Db: BauData + Send + Sync + 'static,
+        DbRef: Deref<Target = Db> + Clone + Send + Sync + 'static,
+        T: BauTransport,
+    >() -> UpdateHandler<HandlerError> {
+        dptree::filter_map(|message: Message| {
+            let prompt = message.reply_to_message()?;
+            if prompt.reply_markup().is_some() {
+                return None;
+            }
+
+            let text = message.text()?.to_string();
+            Some((message.chat.id.0, prompt.id.0, message.id.0, text))
+        })
+        .filter_async(
+            |server: Arc<Self>, (chat_id, prompt_id, _, _): (i64, i32, i32, String)| async move {
+                // WARN: OBTAINING MUTEX
+                let guard = server.store.lock().await;
+                guard.contains_key(&Self::make_key(chat_id, prompt_id))
+                // WARN: DROPPING MUTEX
+            },
+        )
+        .endpoint(Self::reply_handler::<Db, DbRef, T>)
     }
 }
+
+/// Milliseconds since the UNIX epoch.
+pub(crate) fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// How long to wait before retrying a call that failed with `err`, [None] if it should not be
+/// retried. Flood control says how long to wait; network errors wait for `backoff`.
+fn retry_delay(
+    err: &teloxide::RequestError,
+    backoff: std::time::Duration,
+) -> Option<std::time::Duration> {
+    match err {
+        teloxide::RequestError::RetryAfter(seconds) => Some(seconds.duration()),
+        teloxide::RequestError::Network(_) => Some(backoff),
+        _ => None,
+    }
+}
+
+#[test]
+fn retry_delays() {
+    use teloxide::types::Seconds;
+    use teloxide::ApiError;
+    use teloxide::RequestError;
+
+    let backoff = std::time::Duration::from_millis(100);
+    assert_eq!(
+        retry_delay(&RequestError::RetryAfter(Seconds::from_seconds(3)), backoff),
+        Some(std::time::Duration::from_secs(3))
+    );
+    assert_eq!(
+        retry_delay(&RequestError::Api(ApiError::BotBlocked), backoff),
+        None
+    );
+    assert_eq!(
+        retry_delay(
+            &RequestError::Io(std::io::ErrorKind::NotFound.into()),
+            backoff
+        ),
+        None
+    );
+}
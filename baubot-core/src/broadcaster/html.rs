@@ -0,0 +1,126 @@
+//! Validation of [super::types::MessageFormat::Html] messages against the subset of HTML telegram
+//! understands (see <https://core.telegram.org/bots/api#html-style>). Telegram refuses the whole
+//! message if anything is off, so we would rather find out before sending it.
+
+/// Tags telegram understands.
+const ALLOWED_TAGS: &[&str] = &[
+    "a",
+    "b",
+    "blockquote",
+    "code",
+    "del",
+    "em",
+    "i",
+    "ins",
+    "pre",
+    "s",
+    "span",
+    "strike",
+    "strong",
+    "tg-emoji",
+    "tg-spoiler",
+    "u",
+];
+
+/// Named entities telegram understands.
+const ALLOWED_ENTITIES: &[&str] = &["amp", "gt", "lt", "quot"];
+
+/// Check that `text` only uses the tags and entities telegram understands and that every tag is
+/// closed in the right order. Returns a description of the first problem found.
+pub(crate) fn validate(text: &str) -> Result<(), String> {
+    let mut open_tags = Vec::new();
+    let mut rest = text;
+
+    while let Some(index) = rest.find(['<', '>', '&']) {
+        let (special, tail) = rest[index..].split_at(1);
+        rest = match special {
+            "<" => {
+                let end = tail
+                    .find('>')
+                    .ok_or_else(|| "unescaped `<`, use `&lt;`".to_string())?;
+                validate_tag(&tail[..end], &mut open_tags)?;
+                &tail[end + 1..]
+            }
+            "&" => {
+                let end = tail
+                    .find(';')
+                    .filter(|end| validate_entity(&tail[..*end]))
+                    .ok_or_else(|| "unescaped `&`, use `&amp;`".to_string())?;
+                &tail[end + 1..]
+            }
+            _ => return Err("unescaped `>`, use `&gt;`".to_string()),
+        };
+    }
+
+    match open_tags.pop() {
+        Some(tag) => Err(format!("unclosed tag `<{tag}>`")),
+        None => Ok(()),
+    }
+}
+
+/// Check the contents of a single tag (without the angle brackets), keeping track of the tags
+/// still open.
+fn validate_tag<'a>(tag: &'a str, open_tags: &mut Vec<&'a str>) -> Result<(), String> {
+    // Closing tag
+    if let Some(name) = tag.strip_prefix('/') {
+        let name = name.trim();
+        return match open_tags.pop() {
+            Some(open) if open.eq_ignore_ascii_case(name) => Ok(()),
+            Some(open) => Err(format!("expected `</{open}>`, found `</{name}>`")),
+            None => Err(format!("unexpected `</{name}>`")),
+        };
+    }
+
+    // Opening tag
+    let (name, attributes) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+    if !ALLOWED_TAGS
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(name))
+    {
+        return Err(format!("unsupported tag `<{name}>`"));
+    }
+    if name.eq_ignore_ascii_case("span") && !attributes.contains("tg-spoiler") {
+        return Err("`<span>` is only supported as a spoiler".to_string());
+    }
+
+    open_tags.push(name);
+    Ok(())
+}
+
+/// Whether `entity` (between `&` and `;`) is an entity telegram understands.
+fn validate_entity(entity: &str) -> bool {
+    match entity.strip_prefix('#') {
+        Some(code) => match code.strip_prefix(['x', 'X']) {
+            Some(hex) => !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()),
+            None => !code.is_empty() && code.chars().all(|c| c.is_ascii_digit()),
+        },
+        None => ALLOWED_ENTITIES.contains(&entity),
+    }
+}
+
+#[test]
+fn valid_html() {
+    assert_eq!(validate("plain text"), Ok(()));
+    assert_eq!(
+        validate(r#"<b>bold <i>italic</i></b> <a href="https://example.com">link</a>"#),
+        Ok(())
+    );
+    assert_eq!(
+        validate(r#"<span class="tg-spoiler">secret</span> &lt;3 &amp; &#128512; &#x1F600;"#),
+        Ok(())
+    );
+    assert_eq!(validate("<pre><code>fn main() {}</code></pre>"), Ok(()));
+}
+
+#[test]
+fn invalid_html() {
+    assert!(validate("<div>block</div>").is_err());
+    assert!(validate("<b>unclosed").is_err());
+    assert!(validate("<b><i>misnested</b></i>").is_err());
+    assert!(validate("</b>").is_err());
+    assert!(validate("<span>not a spoiler</span>").is_err());
+    assert!(validate("1 < 2").is_err());
+    assert!(validate("2 > 1").is_err());
+    assert!(validate("fish & chips").is_err());
+    assert!(validate("&nbsp;").is_err());
+}
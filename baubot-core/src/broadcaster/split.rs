@@ -0,0 +1,294 @@
+//! Splitting of [super::types::BauMessage::message]s that do not fit in a single telegram
+//! message. Parts are cut on whitespace (preferably a line break) where possible, HTML tags and
+//! entities, MarkdownV2 escapes, code spans and links are never cut in half, and tags or
+//! MarkdownV2 entities left open at a cut are closed at the end of the part and reopened at the
+//! start of the next one. Lengths are counted in UTF-16 code units, as telegram does.
+
+use crate::prelude::*;
+
+/// Piece of a message that must not be split.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token<'a> {
+    /// Anything that is neither whitespace nor markup delimiting an entity (e.g. a character, an
+    /// HTML entity, a MarkdownV2 escape or a MarkdownV2 code span).
+    Text(&'a str),
+
+    /// Whitespace, where the message may be split.
+    Space(&'a str),
+
+    /// Opening HTML tag and its name.
+    Open(&'a str, &'a str),
+
+    /// Closing HTML tag.
+    Close(&'a str),
+
+    /// MarkdownV2 delimiter (e.g. `*` or `||`), which closes the entity if it is open and opens
+    /// it otherwise.
+    Marker(&'a str),
+}
+
+impl Token<'_> {
+    fn as_str(&self) -> &str {
+        match self {
+            Self::Text(text)
+            | Self::Space(text)
+            | Self::Open(text, _)
+            | Self::Close(text)
+            | Self::Marker(text) => text,
+        }
+    }
+
+    fn len(&self) -> usize {
+        length(self.as_str())
+    }
+}
+
+/// Length of `text` as telegram counts it, in UTF-16 code units.
+pub(crate) fn length(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+/// Split `text` (parsed with `parse_mode`) into parts of at most `limit` UTF-16 code units.
+/// Always returns at least one part.
+pub(crate) fn split(text: &str, parse_mode: Option<ParseMode>, limit: usize) -> Vec<String> {
+    if length(text) <= limit {
+        return vec![text.to_string()];
+    }
+
+    let tokens = tokenize(text, parse_mode);
+    let mut parts = Vec::new();
+    let mut start = 0;
+
+    // Opening tags and delimiters carried over from the previous part, with what closes them
+    let mut reopen: Vec<(&str, String)> = Vec::new();
+
+    while start < tokens.len() {
+        let mut open = reopen.clone();
+        let mut length = reopen
+            .iter()
+            .map(|(tag, _)| self::length(tag))
+            .sum::<usize>();
+        let mut end = start;
+
+        // Last whitespace and line break seen, along with the tags open at that point
+        let mut space = None;
+        let mut line_break = None;
+
+        while let Some(token) = tokens.get(end) {
+            let mut next_open = open.clone();
+            match token {
+                Token::Open(tag, name) => next_open.push((tag, format!("</{name}>"))),
+                Token::Close(_) => {
+                    next_open.pop();
+                }
+                Token::Marker(marker) => {
+                    match next_open.iter().rposition(|(tag, _)| tag == marker) {
+                        Some(index) => {
+                            next_open.remove(index);
+                        }
+                        None => next_open.push((marker, marker.to_string())),
+                    }
+                }
+                Token::Text(_) | Token::Space(_) => {}
+            }
+
+            // Leave room to close whatever is still open
+            if length + token.len() + closing_length(&next_open) > limit {
+                break;
+            }
+            length += token.len();
+            open = next_open;
+            end += 1;
+
+            if let Token::Space(space_text) = token {
+                space = Some((end, open.clone()));
+                if space_text.contains('\n') && length > limit / 2 {
+                    line_break = Some((end, open.clone()));
+                }
+            }
+        }
+
+        // Cut on the best boundary, or wherever the limit was hit if there is none
+        let (cut, cut_open) = if end == tokens.len() {
+            (end, open)
+        } else {
+            line_break.or(space).unwrap_or((end.max(start + 1), open))
+        };
+
+        let mut part = reopen.iter().map(|(tag, _)| *tag).collect::<String>();
+        part.extend(tokens[start..cut].iter().map(Token::as_str));
+        for (_, closing) in cut_open.iter().rev() {
+            part.push_str(closing);
+        }
+        parts.push(part);
+
+        reopen = cut_open;
+        start = cut;
+    }
+
+    parts
+}
+
+/// Length of the closing tags and delimiters for `open`.
+fn closing_length(open: &[(&str, String)]) -> usize {
+    open.iter().map(|(_, closing)| length(closing)).sum()
+}
+
+/// Length of the MarkdownV2 span at the start of `text` that opens with `open` and ends with
+/// the next unescaped `close`, if it is closed.
+fn markdown_span(text: &str, open: &str, close: &str) -> Option<usize> {
+    let mut index = open.len();
+    while index < text.len() {
+        let rest = &text[index..];
+        if rest.starts_with(close) {
+            return Some(index + close.len());
+        }
+        // NOTE: Safe to unwrap because `rest` is not empty
+        let next = rest.chars().next().unwrap();
+        index += next.len_utf8();
+        if next == '\\' {
+            index += rest[1..].chars().next().map_or(0, char::len_utf8);
+        }
+    }
+    None
+}
+
+/// Break `text` into [Token]s according to `parse_mode`.
+fn tokenize(text: &str, parse_mode: Option<ParseMode>) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+
+    while let Some(first) = rest.chars().next() {
+        let markdown = parse_mode == Some(ParseMode::MarkdownV2);
+        let span = match (first, parse_mode) {
+            ('<', Some(ParseMode::Html)) => rest.find('>').map(|end| end + 1),
+            ('&', Some(ParseMode::Html)) => rest
+                .find(';')
+                .filter(|end| !rest[1..*end].contains(|c: char| c.is_whitespace() || c == '&'))
+                .map(|end| end + 1),
+            ('\\', Some(ParseMode::MarkdownV2)) => rest[1..]
+                .chars()
+                .next()
+                .map(|escaped| 1 + escaped.len_utf8()),
+            ('`', Some(ParseMode::MarkdownV2)) if rest.starts_with("```") => {
+                markdown_span(rest, "```", "```")
+            }
+            ('`', Some(ParseMode::MarkdownV2)) => markdown_span(rest, "`", "`"),
+            ('[', Some(ParseMode::MarkdownV2)) => markdown_span(rest, "[", "](")
+                .and_then(|text_end| Some(text_end + markdown_span(&rest[text_end..], "", ")")?)),
+            _ => None,
+        };
+        let marker = match first {
+            '_' if markdown && rest.starts_with("__") => Some(2),
+            '|' if markdown && rest.starts_with("||") => Some(2),
+            '*' | '_' | '~' if markdown => Some(1),
+            _ => None,
+        };
+        let length = span.or(marker).unwrap_or(first.len_utf8());
+
+        let (token, tail) = rest.split_at(length);
+        tokens.push(if first.is_whitespace() {
+            Token::Space(token)
+        } else if span.is_none() && marker.is_some() {
+            Token::Marker(token)
+        } else if first == '<' && parse_mode == Some(ParseMode::Html) && length > 1 {
+            match token[1..token.len() - 1].strip_prefix('/') {
+                Some(_) => Token::Close(token),
+                None => {
+                    let name = token[1..token.len() - 1]
+                        .split(char::is_whitespace)
+                        .next()
+                        .unwrap_or_default();
+                    Token::Open(token, name)
+                }
+            }
+        } else {
+            Token::Text(token)
+        });
+        rest = tail;
+    }
+
+    tokens
+}
+
+#[test]
+fn short_text() {
+    assert_eq!(split("", None, 10), vec![""]);
+    assert_eq!(split("hello", Some(ParseMode::Html), 5), vec!["hello"]);
+}
+
+#[test]
+fn split_on_whitespace() {
+    assert_eq!(
+        split("aaaa bbbb cccc", None, 10),
+        vec!["aaaa bbbb ", "cccc"]
+    );
+
+    // Line breaks win over spaces
+    assert_eq!(
+        split("aaaa bb\ncc dd eeee", None, 12),
+        vec!["aaaa bb\n", "cc dd eeee"]
+    );
+
+    // No whitespace to cut on
+    assert_eq!(split("aaaaaaaaaa", None, 4), vec!["aaaa", "aaaa", "aa"]);
+}
+
+#[test]
+fn split_html() {
+    // Entities are not cut in half
+    assert_eq!(
+        split("aa&amp;bb", Some(ParseMode::Html), 4),
+        vec!["aa", "&amp;", "bb"]
+    );
+
+    // Open tags are closed and reopened
+    let parts = split("<b>aaaa bbbb cccc</b>", Some(ParseMode::Html), 17);
+    assert_eq!(parts, vec!["<b>aaaa bbbb </b>", "<b>cccc</b>"]);
+    for part in parts {
+        assert_eq!(super::html::validate(&part), Ok(()));
+    }
+
+    // Without HTML, markup is just text
+    assert_eq!(split("a&amp;b", None, 4), vec!["a&am", "p;b"]);
+}
+
+#[test]
+fn split_markdown() {
+    assert_eq!(
+        split(r"aaa\.bbb", Some(ParseMode::MarkdownV2), 4),
+        vec!["aaa", r"\.bb", "b"]
+    );
+
+    // Open entities are closed and reopened
+    assert_eq!(
+        split("*aaaa _bbbb_ cccc* dd", Some(ParseMode::MarkdownV2), 14),
+        vec!["*aaaa _bbbb_ *", "*cccc* dd"]
+    );
+    assert_eq!(
+        split("||aaaa __bbbb cccc__||", Some(ParseMode::MarkdownV2), 18),
+        vec!["||aaaa __bbbb __||", "||__cccc__||"]
+    );
+
+    // Code spans and links are not cut in half
+    assert_eq!(
+        split("aa `b c` dd", Some(ParseMode::MarkdownV2), 8),
+        vec!["aa ", "`b c` dd"]
+    );
+    assert_eq!(
+        split(
+            "aa [b c](https://example.com/x) dd",
+            Some(ParseMode::MarkdownV2),
+            30
+        ),
+        vec!["aa ", "[b c](https://example.com/x) ", "dd"]
+    );
+}
+
+#[test]
+fn split_utf16() {
+    // Characters outside the basic multilingual plane take two code units
+    assert_eq!(length("😀"), 2);
+    assert_eq!(split(&"😀".repeat(5), None, 4), vec!["😀😀", "😀😀", "😀"]);
+    assert_eq!(split("😀 😀", None, 4), vec!["😀 ", "😀"]);
+}
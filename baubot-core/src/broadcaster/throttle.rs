@@ -0,0 +1,174 @@
+//! Token buckets keeping broadcasts within telegram's flood limits (see
+//! <https://core.telegram.org/bots/faq#my-bot-is-hitting-limits-how-do-i-avoid-this>). Every send
+//! waits for a token from the bucket of its chat, then for one from the global bucket. [Mutex] is
+//! fair, so sends leave in the order they queued up.
+
+use crate::config::RateLimits;
+use crate::prelude::*;
+use std::collections::HashMap;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use tokio::sync::Mutex;
+use tokio::time::Duration;
+use tokio::time::Instant;
+
+/// Holds up to one second worth of tokens, refilled at `rate` tokens per second.
+struct Bucket {
+    rate: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(rate: u32) -> Self {
+        Self {
+            rate: rate as f64,
+            tokens: rate as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.updated = now;
+    }
+
+    /// Take a token, waiting for one if the bucket is empty. Returns whether it had to wait.
+    async fn take(&mut self) -> bool {
+        let mut throttled = false;
+        loop {
+            self.refill();
+            if self.tokens >= 1.0 {
+                self.tokens -= 1.0;
+                break throttled;
+            }
+            throttled = true;
+            tokio::time::sleep(Duration::from_secs_f64((1.0 - self.tokens) / self.rate)).await;
+        }
+    }
+
+    /// Whether the bucket is back to full, i.e. forgetting it changes nothing.
+    fn is_full(&mut self) -> bool {
+        self.refill();
+        self.tokens >= self.rate
+    }
+}
+
+/// Outbound rate limiter of [super::Server].
+pub(crate) struct Throttle {
+    limits: RateLimits,
+    global: Mutex<Bucket>,
+    chats: std::sync::Mutex<HashMap<i64, Arc<Mutex<Bucket>>>>,
+    queued: AtomicUsize,
+    peak_queued: AtomicUsize,
+    sent: AtomicU64,
+    throttled: AtomicU64,
+}
+
+impl Throttle {
+    pub(crate) fn new(limits: RateLimits) -> Self {
+        Self {
+            limits,
+            global: Mutex::new(Bucket::new(limits.global)),
+            chats: Default::default(),
+            queued: Default::default(),
+            peak_queued: Default::default(),
+            sent: Default::default(),
+            throttled: Default::default(),
+        }
+    }
+
+    /// Wait until a message may be sent to `chat_id`.
+    pub(crate) async fn acquire(&self, chat_id: i64) {
+        let queued = self.queued.fetch_add(1, Ordering::SeqCst) + 1;
+        self.peak_queued.fetch_max(queued, Ordering::SeqCst);
+
+        let bucket = {
+            // WARN: OBTAINING MUTEX
+            let mut guard = self.chats.lock().unwrap();
+
+            // Forget the chats nobody is waiting on that have fully recovered
+            guard.retain(|_, bucket| {
+                Arc::strong_count(bucket) > 1
+                    || bucket
+                        .try_lock()
+                        .map_or(true, |mut bucket| !bucket.is_full())
+            });
+            guard
+                .entry(chat_id)
+                .or_insert_with(|| Arc::new(Mutex::new(Bucket::new(self.limits.per_chat))))
+                .clone()
+            // WARN: DROPPING MUTEX
+        };
+
+        // Hold on to the chat's bucket until the global one lets us through, so that messages to
+        // the same chat keep their order
+        let mut chat = bucket.lock().await;
+        let mut throttled = chat.take().await;
+        throttled |= self.global.lock().await.take().await;
+        drop(chat);
+
+        if throttled {
+            trace!("Throttled message to {chat_id}");
+            self.throttled.fetch_add(1, Ordering::SeqCst);
+        }
+        self.sent.fetch_add(1, Ordering::SeqCst);
+        self.queued.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Snapshot of the counters.
+    pub(crate) fn metrics(&self) -> types::SendMetrics {
+        types::SendMetrics {
+            queued: self.queued.load(Ordering::SeqCst),
+            peak_queued: self.peak_queued.load(Ordering::SeqCst),
+            sent: self.sent.load(Ordering::SeqCst),
+            throttled: self.throttled.load(Ordering::SeqCst),
+        }
+    }
+}
+
+#[tokio::test]
+async fn per_chat_limit() {
+    let throttle = Throttle::new(RateLimits {
+        global: 100,
+        per_chat: 10,
+    });
+    let start = Instant::now();
+
+    // Other chats are not held up by a busy one
+    for _ in 0..10 {
+        throttle.acquire(1).await;
+    }
+    throttle.acquire(2).await;
+    assert!(start.elapsed() < Duration::from_millis(100));
+
+    throttle.acquire(1).await;
+    assert!(start.elapsed() >= Duration::from_millis(90));
+    assert_eq!(
+        throttle.metrics(),
+        types::SendMetrics {
+            queued: 0,
+            peak_queued: 1,
+            sent: 12,
+            throttled: 1,
+        }
+    );
+}
+
+#[tokio::test]
+async fn global_limit() {
+    let throttle = Throttle::new(RateLimits {
+        global: 10,
+        per_chat: 10,
+    });
+    let start = Instant::now();
+
+    for chat_id in 0..11 {
+        throttle.acquire(chat_id).await;
+    }
+    assert!(start.elapsed() >= Duration::from_millis(90));
+    assert_eq!(throttle.metrics().throttled, 1);
+}
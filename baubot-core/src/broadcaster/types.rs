@@ -1,13 +1,139 @@
 use super::*;
+use base64::Engine;
 use std::collections::HashMap;
 use tokio::sync::mpsc;
 use tokio::sync::oneshot;
 
-/// This is a client, i.e. someone that is capable of calling [mpsc::UnboundedSender::send]
-pub type ClientSocket = mpsc::UnboundedSender<BauMessage>;
+/// This is a client, i.e. someone that is capable of calling [ClientSocket::send]. The queue is
+/// unbounded unless [crate::config::BauBotBuilder::queue_capacity] is set: [ClientSocket::send]
+/// then waits for room and [ClientSocket::try_send] fails with
+/// [mpsc::error::TrySendError::Full] instead.
+#[derive(Debug, Clone)]
+pub struct ClientSocket(Sender);
 
-/// This is a server, i.e. someone that is capable of receivong on [mpsc::UnboundedReceiver::recv]
-pub type ServerSocket = mpsc::UnboundedReceiver<BauMessage>;
+#[derive(Debug, Clone)]
+enum Sender {
+    Unbounded(mpsc::UnboundedSender<BauMessage>),
+    Bounded(mpsc::Sender<BauMessage>),
+}
+
+impl ClientSocket {
+    /// Queue `message` for broadcast, waiting for room if the queue is bounded. Fails once
+    /// [crate::BauBot] is shutting down.
+    pub async fn send(
+        &self,
+        message: BauMessage,
+    ) -> Result<(), mpsc::error::SendError<BauMessage>> {
+        match &self.0 {
+            Sender::Unbounded(sender) => sender.send(message),
+            Sender::Bounded(sender) => sender.send(message).await,
+        }
+    }
+
+    /// Queue `message` for broadcast without waiting. Only fails with
+    /// [mpsc::error::TrySendError::Full] if the queue is bounded.
+    // Hands the message back on failure, as [mpsc::Sender::try_send] does
+    #[allow(clippy::result_large_err)]
+    pub fn try_send(
+        &self,
+        message: BauMessage,
+    ) -> Result<(), mpsc::error::TrySendError<BauMessage>> {
+        match &self.0 {
+            Sender::Unbounded(sender) => {
+                sender
+                    .send(message)
+                    .map_err(|mpsc::error::SendError(message)| {
+                        mpsc::error::TrySendError::Closed(message)
+                    })
+            }
+            Sender::Bounded(sender) => sender.try_send(message),
+        }
+    }
+
+    /// A [WeakClientSocket] that does not keep the queue open.
+    pub(crate) fn downgrade(&self) -> WeakClientSocket {
+        WeakClientSocket(match &self.0 {
+            Sender::Unbounded(sender) => WeakSender::Unbounded(sender.downgrade()),
+            Sender::Bounded(sender) => WeakSender::Bounded(sender.downgrade()),
+        })
+    }
+}
+
+/// [ClientSocket] that does not keep the queue open, see [ClientSocket::downgrade].
+#[derive(Debug, Clone)]
+pub(crate) struct WeakClientSocket(WeakSender);
+
+#[derive(Debug, Clone)]
+enum WeakSender {
+    Unbounded(mpsc::WeakUnboundedSender<BauMessage>),
+    Bounded(mpsc::WeakSender<BauMessage>),
+}
+
+impl WeakClientSocket {
+    /// The [ClientSocket], unless the queue has been closed.
+    pub(crate) fn upgrade(&self) -> Option<ClientSocket> {
+        Some(ClientSocket(match &self.0 {
+            WeakSender::Unbounded(sender) => Sender::Unbounded(sender.upgrade()?),
+            WeakSender::Bounded(sender) => Sender::Bounded(sender.upgrade()?),
+        }))
+    }
+}
+
+/// This is a server, i.e. someone that is capable of receivong on [ServerSocket::recv]
+#[derive(Debug)]
+pub struct ServerSocket(Receiver);
+
+#[derive(Debug)]
+enum Receiver {
+    Unbounded(mpsc::UnboundedReceiver<BauMessage>),
+    Bounded(mpsc::Receiver<BauMessage>),
+}
+
+impl ServerSocket {
+    /// Receive the next [BauMessage], or [None] once the queue is closed and empty.
+    pub async fn recv(&mut self) -> Option<BauMessage> {
+        match &mut self.0 {
+            Receiver::Unbounded(receiver) => receiver.recv().await,
+            Receiver::Bounded(receiver) => receiver.recv().await,
+        }
+    }
+
+    /// Receive the next [BauMessage] if one is queued.
+    pub fn try_recv(&mut self) -> Result<BauMessage, mpsc::error::TryRecvError> {
+        match &mut self.0 {
+            Receiver::Unbounded(receiver) => receiver.try_recv(),
+            Receiver::Bounded(receiver) => receiver.try_recv(),
+        }
+    }
+
+    /// Refuse any further [BauMessage]s; the queued ones can still be received.
+    pub fn close(&mut self) {
+        match &mut self.0 {
+            Receiver::Unbounded(receiver) => receiver.close(),
+            Receiver::Bounded(receiver) => receiver.close(),
+        }
+    }
+}
+
+/// Create a queue of [BauMessage]s holding at most `capacity` of them, or unbounded if [None].
+pub(crate) fn channel(capacity: Option<usize>) -> (ClientSocket, ServerSocket) {
+    match capacity {
+        Some(capacity) => {
+            let (sender, receiver) = mpsc::channel(capacity);
+            (
+                ClientSocket(Sender::Bounded(sender)),
+                ServerSocket(Receiver::Bounded(receiver)),
+            )
+        }
+        None => {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            (
+                ClientSocket(Sender::Unbounded(sender)),
+                ServerSocket(Receiver::Unbounded(receiver)),
+            )
+        }
+    }
+}
 
 /// Response from the [crate::BauBot] if responses are required
 pub type BauResponse = Result<String, BauBotError>;
@@ -26,6 +152,28 @@ pub type BauResponseSender = oneshot::Sender<BauResponse>;
 /// - When the [ServerSocket] wants to send the [BauResponse] to the [ClientSocket]
 pub type BauResponseReceiver = oneshot::Receiver<BauResponse>;
 
+/// Sender for the [QuorumOutcome] of a [BauMessage] with a [Quorum].
+pub type QuorumOutcomeSender = oneshot::Sender<QuorumOutcome>;
+
+/// Receiver for the [QuorumOutcome] of a [BauMessage] with a [Quorum].
+pub type QuorumOutcomeReceiver = oneshot::Receiver<QuorumOutcome>;
+
+/// Snapshot of the outbound queue of [crate::BauBot]. See [crate::BauBot::metrics].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct SendMetrics {
+    /// Messages currently waiting for the rate limits.
+    pub queued: usize,
+
+    /// Most messages ever waiting for the rate limits at once.
+    pub peak_queued: usize,
+
+    /// Messages let through so far.
+    pub sent: u64,
+
+    /// Messages that had to wait for the rate limits.
+    pub throttled: u64,
+}
+
 /// [HashMap] store of [BauMessage] which require a response (key is computed based on `chat_id << 64 |
 /// message_id`)
 pub type BauResponseStore = HashMap<i128, BauResponseSender>;
@@ -34,26 +182,35 @@ pub type BauResponseStore = HashMap<i128, BauResponseSender>;
 /// Form of message that can be passed between various interfaces (e.g. [ServerSocket],
 /// [crate::BauBot] and [ClientSocket]).
 pub struct BauMessage {
+    /// Identifier of the originating request, chosen by the client (or by [BauMessage::ensure_id]).
+    /// Requests with an `id` can be cancelled through [crate::BauBot::cancel] and picked up again
+    /// through [crate::BauBot::reclaim] after a restart (see [crate::config::RestorePolicy]).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+
     /// Tele username.
     ///
     /// Clients should use the [crate::BauData] trait / database to obtain the appropriate telegram
     /// username.
     pub sender: String,
 
-    /// List of recipients and handlers for that client.
-    ///
-    /// Clients should use the [crate::BauData] trait / database to obtain the appropriate telegram
-    /// username.
+    /// List of recipients and handlers for that client. See [Recipient] for the kinds of
+    /// recipient.
     #[serde(serialize_with = "serialize_recipients")]
-    pub recipients: Vec<(String, Option<BauResponseSender>)>,
+    pub recipients: Vec<(Recipient, Option<BauResponseSender>)>,
 
-    /// Message to be sent.
-    ///
-    /// # Safety
-    /// [crate::BauBot] will attempt to send the message with a Html parser. Only certain types of
-    /// HTML entities are recognized so the user has to check.
+    /// Message to be sent, interpreted according to [BauMessage::format].
     pub message: String,
 
+    /// How [BauMessage::message] is parsed. Falls back to [crate::config::Config]'s parse mode
+    /// when [None].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<MessageFormat>,
+
+    /// Files sent along with [BauMessage::message]. See [Attachment].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+
     /// Expected responses, as a grid of responses. Send an empty [Vec] to indicate that no
     /// responses required.
     ///
@@ -65,11 +222,16 @@ pub struct BauMessage {
     ///
     /// ```
     pub responses: RequestedResponses,
+
+    /// Receives the [QuorumOutcome] once [RequestedResponses::quorum] is decided. Ignored for
+    /// [Quorum::Independent].
+    #[serde(skip)]
+    pub outcome: Option<QuorumOutcomeSender>,
 }
 
 /// Serialize recipients on [BauMessage]
 fn serialize_recipients<S>(
-    recipients: &Vec<(String, Option<BauResponseSender>)>,
+    recipients: &Vec<(Recipient, Option<BauResponseSender>)>,
     serializer: S,
 ) -> Result<S::Ok, S::Error>
 where
@@ -78,20 +240,459 @@ where
     serializer.collect_seq(recipients.iter().map(|(recipient, _)| recipient))
 }
 
+/// Recipient of a [BauMessage]. Written as a string in JSON:
+/// - `alice` (or `@alice`): the user registered with that telegram username, see
+///   [crate::BauData::get_chat_id].
+/// - `-1001234567890`: the chat with that `chat_id`, as is.
+/// - `#ops`: the group or channel [crate::BauBot] was added to under that alias, see
+///   [crate::BauData::get_group_chat_id].
+/// - `%oncall`: every member of the role, see [crate::BauData::get_role_members]. Roles are
+///   expanded into a [Recipient::User] per member when the [BauMessage] is received, so each
+///   member counts on their own towards a [Quorum]. The response for the role is the first one
+///   given by any of its members.
+///
+/// Telegram usernames start with a letter, so the forms cannot be confused.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum Recipient {
+    User(String),
+    Chat(i64),
+    Group(String),
+    Role(String),
+}
+
+impl std::fmt::Display for Recipient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::User(username) => write!(f, "{username}"),
+            Self::Chat(chat_id) => write!(f, "{chat_id}"),
+            Self::Group(alias) => write!(f, "#{alias}"),
+            Self::Role(role) => write!(f, "%{role}"),
+        }
+    }
+}
+
+impl std::str::FromStr for Recipient {
+    type Err = String;
+
+    fn from_str(recipient: &str) -> Result<Self, Self::Err> {
+        let recipient = if let Some(alias) = recipient.strip_prefix('#') {
+            Self::Group(alias.to_string())
+        } else if let Some(role) = recipient.strip_prefix('%') {
+            Self::Role(role.to_string())
+        } else if let Ok(chat_id) = recipient.parse() {
+            Self::Chat(chat_id)
+        } else {
+            Self::User(recipient.trim_start_matches('@').to_string())
+        };
+        match &recipient {
+            Self::User(name) | Self::Group(name) | Self::Role(name) if name.is_empty() => {
+                Err(format!("invalid recipient `{recipient}`"))
+            }
+            _ => Ok(recipient),
+        }
+    }
+}
+
+impl TryFrom<String> for Recipient {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Recipient> for String {
+    fn from(value: Recipient) -> Self {
+        value.to_string()
+    }
+}
+
+/// Group, supergroup or channel [crate::BauBot] has been added to. Handed to
+/// [crate::BauData::save_chat] so that it can be addressed as a [Recipient::Group].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrackedChat {
+    pub chat_id: i64,
+
+    /// Name of the [Recipient::Group]: the public username of the chat if it has one, its
+    /// title in lowercase with anything but letters and digits replaced by `-` otherwise.
+    pub alias: String,
+
+    pub title: String,
+}
+
+impl TrackedChat {
+    /// Derive the [TrackedChat::alias] of a chat from its `username` and `title`.
+    pub fn new(chat_id: i64, username: Option<&str>, title: &str) -> Self {
+        let alias = match username {
+            Some(username) => username.to_lowercase(),
+            None => title
+                .to_lowercase()
+                .split(|c: char| !c.is_alphanumeric())
+                .filter(|word| !word.is_empty())
+                .collect::<Vec<_>>()
+                .join("-"),
+        };
+        Self {
+            chat_id,
+            alias,
+            title: title.to_string(),
+        }
+    }
+}
+
+/// Format of [BauMessage::message].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageFormat {
+    /// Sent verbatim. Anything telegram would mistake for markup is escaped.
+    Plain,
+
+    /// Telegram's subset of HTML (see <https://core.telegram.org/bots/api#html-style>).
+    /// Validated by [BauMessage::builder].
+    Html,
+
+    /// Telegram's MarkdownV2 (see <https://core.telegram.org/bots/api#markdownv2-style>). Not
+    /// validated; telegram refuses malformed messages.
+    MarkdownV2,
+}
+
+impl MessageFormat {
+    /// `message` as it should be sent to telegram, along with the [ParseMode] to send it with.
+    pub(crate) fn render(self, message: String) -> (String, Option<ParseMode>) {
+        match self {
+            Self::Plain => (
+                teloxide::utils::html::escape(&message),
+                Some(ParseMode::Html),
+            ),
+            Self::Html => (message, Some(ParseMode::Html)),
+            Self::MarkdownV2 => (message, Some(ParseMode::MarkdownV2)),
+        }
+    }
+}
+
+/// File attached to a [BauMessage].
+///
+/// A single attachment is sent with [BauMessage::message] as its caption if it fits. Otherwise
+/// the attachments are sent first (as albums where possible) and [BauMessage::message] follows
+/// as a separate message. Either way any keyboard is attached to the final message.
+///
+/// ```ignore
+/// {
+///     "filename": "invoice.pdf",
+///     "mime_type": "application/pdf",
+///     "base64": "JVBERi0xLjQK..."
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Attachment {
+    /// Name the file is sent under.
+    pub filename: String,
+
+    /// MIME type of the file. See [Attachment::is_photo].
+    pub mime_type: String,
+
+    /// Where the contents of the file come from.
+    #[serde(flatten)]
+    pub source: AttachmentSource,
+}
+
+/// Contents of an [Attachment].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttachmentSource {
+    /// Contents inline, base64 encoded.
+    Base64(String),
+
+    /// Path of a file local to [crate::BauBot], read when the [BauMessage] is sent. Only files
+    /// within [crate::config::BauBotBuilder::attachment_root] are sent.
+    Path(std::path::PathBuf),
+}
+
+impl Attachment {
+    /// Whether the attachment is sent as a photo rather than a document.
+    pub fn is_photo(&self) -> bool {
+        matches!(
+            self.mime_type.as_str(),
+            "image/jpeg" | "image/png" | "image/webp"
+        )
+    }
+
+    /// Turn the attachment into an [teloxide::types::InputFile]. Fails if the inline contents are
+    /// not valid base64.
+    pub fn input_file(&self) -> std::io::Result<teloxide::types::InputFile> {
+        let input_file = match &self.source {
+            AttachmentSource::Base64(data) => {
+                let data = base64::engine::general_purpose::STANDARD
+                    .decode(data)
+                    .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+                teloxide::types::InputFile::memory(data)
+            }
+            AttachmentSource::Path(path) => teloxide::types::InputFile::file(path),
+        };
+        Ok(input_file.file_name(self.filename.clone()))
+    }
+}
+
+/// A [BauMessage] sent to a single recipient that is waiting for a response. Handed to
+/// [crate::BauData::save_pending] so that it can be restored after a restart.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingRequest {
+    /// [BauMessage::id] of the originating request.
+    pub request_id: Option<String>,
+
+    /// Recipient the message was sent to.
+    pub recipient: String,
+
+    /// Telegram user id of the recipient, if it is a [Recipient::User] whose id is known (see
+    /// [crate::BauData::get_telegram_id]).
+    #[serde(default)]
+    pub telegram_id: Option<u64>,
+
+    pub chat_id: i64,
+
+    pub message_id: i32,
+
+    /// Time the request expires, in milliseconds since the UNIX epoch.
+    pub deadline: u64,
+
+    /// [RequestedResponses::timeout] of the originating request.
+    pub timeout: u64,
+
+    /// [Button::value]s of the keyboard, in order. The callback data of each button is its index
+    /// in this list.
+    #[serde(default)]
+    pub buttons: Vec<String>,
+}
+
+/// One-time invite to register with [crate::BauBot] through the deep link
+/// `https://t.me/<bot username>?start=<token>`. Created by [crate::BauBot::invite] and handed to
+/// [crate::BauData::save_invite].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Invite {
+    /// Random token carried by the deep link.
+    pub token: String,
+
+    /// Identifier of the user in the client application, bound to whoever redeems the invite
+    /// (see [crate::BauData::bind_user_id]).
+    pub user_id: String,
+
+    /// Time the invite expires, in milliseconds since the UNIX epoch.
+    pub deadline: u64,
+}
+
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct RequestedResponses {
     pub timeout: u64,
-    pub keyboard: Vec<Vec<String>>,
+    pub keyboard: Vec<Vec<Button>>,
+
+    /// How the responses of the [BauMessage::recipients] are combined.
+    #[serde(default)]
+    pub quorum: Quorum,
+
+    /// Prompt the recipients with a [teloxide::types::ForceReply] and take their typed reply as
+    /// the response. Cannot be combined with a [RequestedResponses::keyboard].
+    #[serde(default)]
+    pub free_text: bool,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl RequestedResponses {
+    /// Whether the recipients are asked for a response, through a keyboard or a free text reply.
+    pub fn is_required(&self) -> bool {
+        self.free_text || !self.keyboard.is_empty()
+    }
+}
+
+/// Button of a [RequestedResponses::keyboard]. Telegram only carries 64 bytes of callback data, so
+/// the button is only identified by its position and its value never has to fit.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Button {
+    /// Shown to the recipient and returned as the response.
+    Plain(String),
+
+    /// `label` is shown to the recipient, `value` is returned as the response.
+    Labelled { label: String, value: String },
+}
+
+impl Button {
+    /// Text shown to the recipient.
+    pub fn label(&self) -> &str {
+        match self {
+            Self::Plain(text) => text,
+            Self::Labelled { label, .. } => label,
+        }
+    }
+
+    /// Response returned when the recipient presses the button.
+    pub fn value(&self) -> &str {
+        match self {
+            Self::Plain(text) => text,
+            Self::Labelled { value, .. } => value,
+        }
+    }
+}
+
+impl From<&str> for Button {
+    fn from(value: &str) -> Self {
+        Self::Plain(value.to_string())
+    }
+}
+
+impl From<String> for Button {
+    fn from(value: String) -> Self {
+        Self::Plain(value)
+    }
+}
+
+/// Policy combining the responses of several [BauMessage::recipients] into a single
+/// [QuorumOutcome]. Once the outcome is decided, the recipients that have not responded yet have
+/// their keyboards removed, are told how the request was decided and resolve with
+/// [BauBotError::Superseded].
+///
+/// Recipients that time out or cannot be contacted count as not approving.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Quorum {
+    /// Every recipient responds on their own. No [QuorumOutcome] is produced.
+    #[default]
+    Independent,
+
+    /// The first response decides.
+    First,
+
+    /// Approved once every recipient has responded with `approve`, rejected as soon as anyone
+    /// does not.
+    All { approve: String },
+
+    /// Approved once `n` recipients have responded with `approve`, rejected as soon as that is no
+    /// longer possible.
+    NOfM { n: usize, approve: String },
+}
+
+/// Outcome of a [Quorum].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum QuorumOutcome {
+    /// [Quorum::First]: `recipient` responded first with `response`.
+    Answered { recipient: String, response: String },
+
+    /// [Quorum::First]: nobody responded.
+    Unanswered,
+
+    /// [Quorum::All] or [Quorum::NOfM] was met by `approvals`.
+    Approved { approvals: Vec<String> },
+
+    /// [Quorum::All] or [Quorum::NOfM] can no longer be met because of `rejections`.
+    Rejected { rejections: Vec<String> },
+}
+
+impl Quorum {
+    /// Whether the quorum can be decided for `responses` sent to `recipients`: `approve` must be
+    /// one of the [RequestedResponses::keyboard] values (unless taking a free text reply) and `n`
+    /// must be reachable. A [Recipient::Role] counts as a single recipient until expanded, so `n`
+    /// is only checked against the number of recipients when none of them is a role.
+    pub(crate) fn is_valid(
+        &self,
+        responses: &RequestedResponses,
+        recipients: &[Recipient],
+    ) -> bool {
+        let approvable = |approve: &str| {
+            responses.free_text
+                || responses
+                    .keyboard
+                    .iter()
+                    .flatten()
+                    .any(|button| button.value() == approve)
+        };
+
+        match self {
+            Self::Independent | Self::First => true,
+            Self::All { approve } => approvable(approve),
+            Self::NOfM { n, approve } => {
+                let has_roles = recipients
+                    .iter()
+                    .any(|recipient| matches!(recipient, Recipient::Role(_)));
+                *n > 0 && (has_roles || *n <= recipients.len()) && approvable(approve)
+            }
+        }
+    }
+
+    /// Decide the [QuorumOutcome] from the `responses` received so far (in order of arrival) out
+    /// of `total` recipients. Returns [None] while undecided.
+    pub(crate) fn decide(
+        &self,
+        responses: &[(String, BauResponse)],
+        total: usize,
+    ) -> Option<QuorumOutcome> {
+        // Split recipients by whether they approved
+        let tally = |approve: &str| {
+            let (approvals, rejections): (Vec<_>, Vec<_>) = responses
+                .iter()
+                .partition(|(_, response)| matches!(response, Ok(data) if data == approve));
+            let recipients = |responses: Vec<&(String, BauResponse)>| {
+                responses
+                    .into_iter()
+                    .map(|(recipient, _)| recipient.clone())
+                    .collect::<Vec<_>>()
+            };
+            (recipients(approvals), recipients(rejections))
+        };
+
+        match self {
+            Self::Independent => None,
+            Self::First => match responses.iter().find(|(_, response)| response.is_ok()) {
+                Some((recipient, Ok(response))) => Some(QuorumOutcome::Answered {
+                    recipient: recipient.clone(),
+                    response: response.clone(),
+                }),
+                _ => (responses.len() >= total).then_some(QuorumOutcome::Unanswered),
+            },
+            Self::All { approve } => {
+                let (approvals, rejections) = tally(approve);
+                if !rejections.is_empty() {
+                    Some(QuorumOutcome::Rejected { rejections })
+                } else {
+                    (approvals.len() >= total).then_some(QuorumOutcome::Approved { approvals })
+                }
+            }
+            Self::NOfM { n, approve } => {
+                let (approvals, rejections) = tally(approve);
+                if approvals.len() >= *n {
+                    Some(QuorumOutcome::Approved { approvals })
+                } else if rejections.len() > total.saturating_sub(*n) || responses.len() >= total {
+                    Some(QuorumOutcome::Rejected { rejections })
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(tag = "type")]
 /// Errors emitted by [ServerSocket] that are sent to the [ClientSocket].
 pub enum BauBotError {
-    /// The [BauMessage::recipients] is uncontactable.
-    /// This may happen because the recipient cannot be found in [BauData], or because [Bot] was
-    /// unable to send the message.
-    Uncontactable,
+    /// The recipient cannot be found in [BauData], i.e. they never sent `/start` to the bot.
+    Unregistered,
+
+    /// The recipient blocked the bot, deactivated their account or can otherwise no longer be
+    /// messaged. `description` is the error given by telegram.
+    Blocked { description: String },
+
+    /// Telegram refused the message itself, e.g. because of malformed markup, or [crate::BauBot]
+    /// refused to send an attachment (see [crate::config::BauBotBuilder::attachment_root]).
+    /// `description` is the error given by telegram or [crate::BauBot].
+    Rejected { description: String },
+
+    /// Telegram does not know the chat of the recipient, or [Bot] was unable to reach telegram,
+    /// even after retrying (see [crate::config::BauBotBuilder::retry_policy]). `description` is
+    /// the last error, and empty when sent by versions that did not give one.
+    Uncontactable {
+        #[serde(default)]
+        description: String,
+    },
 
     ///  The pipeline for sending a response between [crate::BauBot] and [ServerSocket] has expired. This
     ///  happens in the following circumstances:
@@ -99,11 +700,39 @@ pub enum BauBotError {
     ///  - The request server did not use the provided [BauResponseSender] for some reason (which
     ///  should not be the case, but we will provide for the possibility anyway).
     Timeout,
+
+    /// [crate::BauBot::shutdown] was called before the recipient responded (or before the
+    /// [BauMessage] was sent out).
+    Shutdown,
+
+    /// The [Quorum] of the [BauMessage] was decided, or another member of the
+    /// [Recipient::Role] responded, before the recipient responded.
+    Superseded,
+
+    /// The [BauMessage] was cancelled through [crate::BauBot::cancel] before the recipient
+    /// responded.
+    Cancelled,
 }
 
 use serde_json::Value;
 
 impl BauMessage {
+    /// Assign a unique [BauMessage::id] unless the client already chose one, and return it. The
+    /// id is the handle to pass to [crate::BauBot::cancel].
+    pub fn ensure_id(&mut self) -> &str {
+        /// Distinguishes ids generated within the same millisecond
+        static COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+        self.id.get_or_insert_with(|| {
+            let millis = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis();
+            let count = COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            format!("{millis:x}-{count:x}")
+        })
+    }
+
     /// Helper to build a [BauMessage] from a JSON string. returns a callback with a properly
     /// constructed [BauMessage] albeit that [BauMessage::recipients] will have an empty list of
     /// [BauResponseSender] by default.
@@ -113,20 +742,45 @@ impl BauMessage {
         // Attempt to create json value
         let mut json_value = serde_json::from_str::<Value>(string)?;
 
+        // Extract id
+        let id = match json_value.get_mut("id") {
+            Some(value) => serde_json::from_value(value.take())?,
+            None => None,
+        };
+
         // Extract sender
         let sender = serde_json::from_value(json_value.get_mut("sender").ok_or("sender")?.take())?;
 
         // Extract recipients
-        let recipients = serde_json::from_value::<Vec<String>>(
+        let recipients = serde_json::from_value::<Vec<Recipient>>(
             json_value.get_mut("recipients").ok_or("recipients")?.take(),
-        )?
-        .into_iter()
-        .map(|recipient| (recipient, None))
-        .collect();
+        )?;
 
         // Extract message
-        let message =
-            serde_json::from_value(json_value.get_mut("message").ok_or("message")?.take())?;
+        let message = serde_json::from_value::<String>(
+            json_value.get_mut("message").ok_or("message")?.take(),
+        )?;
+
+        // Extract format
+        let format = match json_value.get_mut("format") {
+            Some(value) => serde_json::from_value(value.take())?,
+            None => None,
+        };
+        if format == Some(MessageFormat::Html) {
+            super::html::validate(&message).map_err(SerializeError::InvalidMarkup)?;
+        }
+
+        // Extract attachments
+        let attachments = match json_value.get_mut("attachments") {
+            Some(value) => serde_json::from_value::<Vec<Attachment>>(value.take())?,
+            None => Vec::new(),
+        };
+        if attachments
+            .iter()
+            .any(|attachment| attachment.input_file().is_err())
+        {
+            return Err("attachments".into());
+        }
 
         // Extract responses
         let responses = match json_value.get_mut("responses") {
@@ -136,22 +790,48 @@ impl BauMessage {
             }
             None => RequestedResponses::default(),
         };
+        if responses.free_text && !responses.keyboard.is_empty() {
+            return Err("free_text".into());
+        }
+        if responses
+            .keyboard
+            .iter()
+            .flatten()
+            .any(|button| button.label().is_empty())
+        {
+            return Err("keyboard".into());
+        }
+        if !responses.quorum.is_valid(&responses, &recipients) {
+            return Err("quorum".into());
+        }
 
         // Return callback
         Ok(move || BauMessage {
+            id,
             sender,
-            recipients,
+            recipients: recipients
+                .into_iter()
+                .map(|recipient| (recipient, None))
+                .collect(),
             message,
+            format,
+            attachments,
             responses,
+            outcome: None,
         })
     }
 }
 
+/// Reason a [BauMessage] could not be built. Serialized as `{"type": "InvalidField", "reason":
+/// "keyboard"}`.
 #[derive(Debug, Serialize, Deserialize)]
-#[serde(tag = "type")]
+#[serde(tag = "type", content = "reason")]
 pub enum SerializeError {
     InvalidJson(String),
     InvalidField(String),
+
+    /// [BauMessage::message] is not valid for its [MessageFormat].
+    InvalidMarkup(String),
 }
 
 impl From<serde_json::Error> for SerializeError {
@@ -160,6 +840,33 @@ impl From<serde_json::Error> for SerializeError {
     }
 }
 
+impl From<teloxide::RequestError> for BauBotError {
+    fn from(err: teloxide::RequestError) -> Self {
+        use teloxide::ApiError;
+
+        // Telegram's own wording, without the prefix teloxide puts in front of it
+        let description = match &err {
+            teloxide::RequestError::Api(err) => err.to_string(),
+            err => err.to_string(),
+        };
+        match err {
+            teloxide::RequestError::Api(
+                ApiError::BotBlocked
+                | ApiError::BotKicked
+                | ApiError::BotKickedFromSupergroup
+                | ApiError::UserDeactivated
+                | ApiError::CantInitiateConversation
+                | ApiError::CantTalkWithBots,
+            ) => Self::Blocked { description },
+            teloxide::RequestError::Api(ApiError::ChatNotFound | ApiError::UserNotFound) => {
+                Self::Uncontactable { description }
+            }
+            teloxide::RequestError::Api(_) => Self::Rejected { description },
+            _ => Self::Uncontactable { description },
+        }
+    }
+}
+
 impl From<&'static str> for SerializeError {
     fn from(value: &'static str) -> Self {
         Self::InvalidField(value.into())
@@ -231,6 +938,140 @@ fn invalid_responses() {
     assert!(message.is_err());
 }
 
+#[test]
+fn attachments_conversion() {
+    let message = BauMessage::builder(
+        r#"{
+    "sender": "sender",
+    "recipients": [
+        "recipient"
+    ],
+    "message": "hello world",
+    "attachments": [
+        {"filename": "hello.txt", "mime_type": "text/plain", "base64": "aGVsbG8="},
+        {"filename": "photo.jpg", "mime_type": "image/jpeg", "path": "/tmp/photo.jpg"}
+    ]
+}"#,
+    )
+    .unwrap()();
+    assert_eq!(
+        message.attachments[0].source,
+        AttachmentSource::Base64("aGVsbG8=".to_string())
+    );
+    assert!(!message.attachments[0].is_photo());
+    assert!(message.attachments[1].is_photo());
+
+    // Inline contents must be base64
+    let message = BauMessage::builder(
+        r#"{
+    "sender": "sender",
+    "recipients": [
+        "recipient"
+    ],
+    "message": "hello world",
+    "attachments": [
+        {"filename": "hello.txt", "mime_type": "text/plain", "base64": "not base64!"}
+    ]
+}"#,
+    );
+    assert!(
+        matches!(message, Err(SerializeError::InvalidField(ref field)) if field == "attachments")
+    );
+}
+
+#[test]
+fn free_text_conversion() {
+    let message = BauMessage::builder(
+        r#"{
+    "sender": "sender",
+    "recipients": [
+        "recipient"
+    ],
+    "message": "Ticket number?",
+    "responses": {
+        "timeout": 1000,
+        "keyboard": [],
+        "free_text": true
+    }
+}"#,
+    )
+    .unwrap()();
+    assert!(message.responses.free_text);
+    assert!(message.responses.is_required());
+
+    // Keyboard and free text are mutually exclusive
+    let message = BauMessage::builder(
+        r#"{
+    "sender": "sender",
+    "recipients": [
+        "recipient"
+    ],
+    "message": "Ticket number?",
+    "responses": {
+        "timeout": 1000,
+        "keyboard": [["none"]],
+        "free_text": true
+    }
+}"#,
+    );
+    assert!(
+        matches!(message, Err(SerializeError::InvalidField(ref field)) if field == "free_text")
+    );
+}
+
+#[test]
+fn format_conversion() {
+    let message = BauMessage::builder(
+        r#"{
+    "sender": "sender",
+    "recipients": [
+        "recipient"
+    ],
+    "message": "<b>Deploy</b> &amp; restart?",
+    "format": "html"
+}"#,
+    )
+    .unwrap()();
+    assert_eq!(message.format, Some(MessageFormat::Html));
+
+    let message = BauMessage::builder(
+        r#"{
+    "sender": "sender",
+    "recipients": [
+        "recipient"
+    ],
+    "message": "<div>Deploy</div>",
+    "format": "html"
+}"#,
+    );
+    assert!(matches!(message, Err(SerializeError::InvalidMarkup(_))));
+
+    // Rejections travel to clients as JSON
+    let json = serde_json::to_value(message.err().unwrap()).unwrap();
+    assert_eq!(json["type"], "InvalidMarkup");
+    assert!(json["reason"].is_string());
+
+    // Only HTML is validated
+    let message = BauMessage::builder(
+        r#"{
+    "sender": "sender",
+    "recipients": [
+        "recipient"
+    ],
+    "message": "<div>Deploy</div>",
+    "format": "plain"
+}"#,
+    )
+    .unwrap()();
+    assert_eq!(
+        message.format.unwrap().render(message.message),
+        (
+            "&lt;div&gt;Deploy&lt;/div&gt;".to_string(),
+            Some(ParseMode::Html)
+        )
+    );
+}
+
 #[test]
 fn missing_sender() {
     let message = BauMessage::builder(
@@ -283,3 +1124,294 @@ fn invalid_recipients() {
     }
     assert!(message.is_err());
 }
+
+#[test]
+fn quorum() {
+    let approve = |recipient: &str| (recipient.to_string(), Ok("approve".to_string()));
+    let deny = |recipient: &str| (recipient.to_string(), Ok("deny".to_string()));
+    let timeout = |recipient: &str| (recipient.to_string(), Err(BauBotError::Timeout));
+
+    // First
+    assert_eq!(Quorum::First.decide(&[timeout("a")], 2), None);
+    assert_eq!(
+        Quorum::First.decide(&[timeout("a"), deny("b")], 2),
+        Some(QuorumOutcome::Answered {
+            recipient: "b".to_string(),
+            response: "deny".to_string()
+        })
+    );
+    assert_eq!(
+        Quorum::First.decide(&[timeout("a"), timeout("b")], 2),
+        Some(QuorumOutcome::Unanswered)
+    );
+
+    // All
+    let all = Quorum::All {
+        approve: "approve".to_string(),
+    };
+    assert_eq!(all.decide(&[approve("a")], 2), None);
+    assert_eq!(
+        all.decide(&[approve("a"), approve("b")], 2),
+        Some(QuorumOutcome::Approved {
+            approvals: vec!["a".to_string(), "b".to_string()]
+        })
+    );
+    assert_eq!(
+        all.decide(&[timeout("a")], 2),
+        Some(QuorumOutcome::Rejected {
+            rejections: vec!["a".to_string()]
+        })
+    );
+
+    // 2 of 3
+    let n_of_m = Quorum::NOfM {
+        n: 2,
+        approve: "approve".to_string(),
+    };
+    assert_eq!(n_of_m.decide(&[approve("a"), deny("b")], 3), None);
+    assert_eq!(
+        n_of_m.decide(&[approve("a"), deny("b"), approve("c")], 3),
+        Some(QuorumOutcome::Approved {
+            approvals: vec!["a".to_string(), "c".to_string()]
+        })
+    );
+    assert_eq!(
+        n_of_m.decide(&[deny("a"), timeout("b")], 3),
+        Some(QuorumOutcome::Rejected {
+            rejections: vec!["a".to_string(), "b".to_string()]
+        })
+    );
+}
+
+#[test]
+fn quorum_conversion() {
+    let message = BauMessage::builder(
+        r#"{
+    "sender": "sender",
+    "recipients": ["a", "b", "c"],
+    "message": "hello world",
+    "responses": {
+        "timeout": 5000,
+        "keyboard": [["approve", "deny"]],
+        "quorum": { "type": "NOfM", "n": 2, "approve": "approve" }
+    }
+}"#,
+    )
+    .unwrap()();
+
+    assert_eq!(
+        message.responses.quorum,
+        Quorum::NOfM {
+            n: 2,
+            approve: "approve".to_string()
+        }
+    );
+
+    // Quorums that approve straight away, can never be met or approve an unknown value
+    let invalid = |recipients: &str, quorum: &str| {
+        let message = BauMessage::builder(&format!(
+            r#"{{
+    "sender": "sender",
+    "recipients": {recipients},
+    "message": "hello world",
+    "responses": {{
+        "timeout": 5000,
+        "keyboard": [["approve", {{ "label": "Deny", "value": "deny" }}]],
+        "quorum": {quorum}
+    }}
+}}"#
+        ));
+        matches!(message, Err(SerializeError::InvalidField(ref field)) if field == "quorum")
+    };
+    assert!(invalid(
+        r#"["a", "b"]"#,
+        r#"{ "type": "NOfM", "n": 0, "approve": "approve" }"#
+    ));
+    assert!(invalid(
+        r#"["a", "b"]"#,
+        r#"{ "type": "NOfM", "n": 3, "approve": "approve" }"#
+    ));
+    assert!(invalid(
+        r#"["a", "b"]"#,
+        r#"{ "type": "NOfM", "n": 1, "approve": "Deny" }"#
+    ));
+    assert!(invalid(
+        r#"["a", "b"]"#,
+        r#"{ "type": "All", "approve": "yes" }"#
+    ));
+    assert!(!invalid(
+        r#"["a", "b"]"#,
+        r#"{ "type": "All", "approve": "deny" }"#
+    ));
+
+    // Roles may expand to enough recipients
+    assert!(!invalid(
+        r#"["%oncall"]"#,
+        r#"{ "type": "NOfM", "n": 3, "approve": "approve" }"#
+    ));
+}
+
+#[test]
+fn ensure_id() {
+    let mut message =
+        BauMessage::builder(r#"{"sender": "sender", "recipients": [], "message": ""}"#).unwrap()();
+    let id = message.ensure_id().to_string();
+    assert_eq!(message.ensure_id(), id);
+
+    let mut other_message =
+        BauMessage::builder(r#"{"sender": "sender", "recipients": [], "message": ""}"#).unwrap()();
+    assert_ne!(other_message.ensure_id(), id);
+
+    let mut chosen_message = BauMessage::builder(
+        r#"{"id": "chosen", "sender": "sender", "recipients": [], "message": ""}"#,
+    )
+    .unwrap()();
+    assert_eq!(chosen_message.ensure_id(), "chosen");
+}
+
+#[test]
+fn labelled_buttons() {
+    let value = "v".repeat(100);
+    let message = BauMessage::builder(&format!(
+        r#"{{
+    "sender": "sender",
+    "recipients": ["recipient"],
+    "message": "hello world",
+    "responses": {{
+        "timeout": 5000,
+        "keyboard": [["approve", {{ "label": "Deny", "value": "{value}" }}]]
+    }}
+}}"#
+    ))
+    .unwrap()();
+    let row = &message.responses.keyboard[0];
+    assert_eq!((row[0].label(), row[0].value()), ("approve", "approve"));
+    assert_eq!((row[1].label(), row[1].value()), ("Deny", value.as_str()));
+
+    // Telegram refuses buttons without text
+    let message = BauMessage::builder(
+        r#"{
+    "sender": "sender",
+    "recipients": ["recipient"],
+    "message": "hello world",
+    "responses": { "timeout": 5000, "keyboard": [[""]] }
+}"#,
+    );
+    assert!(matches!(message, Err(SerializeError::InvalidField(_))));
+}
+
+#[tokio::test]
+async fn queue() {
+    let message = || {
+        BauMessage::builder(r#"{"sender": "sender", "recipients": [], "message": ""}"#).unwrap()()
+    };
+
+    // Unbounded queues never fill up
+    let (client_socket, mut server_socket) = channel(None);
+    for _ in 0..100 {
+        client_socket.try_send(message()).unwrap();
+    }
+    client_socket.send(message()).await.unwrap();
+    assert!(server_socket.recv().await.is_some());
+
+    // Bounded ones turn messages away once full
+    let (client_socket, mut server_socket) = channel(Some(1));
+    client_socket.try_send(message()).unwrap();
+    assert!(matches!(
+        client_socket.try_send(message()),
+        Err(mpsc::error::TrySendError::Full(_))
+    ));
+
+    // Weak sockets do not keep the queue open
+    let weak_socket = client_socket.downgrade();
+    assert!(weak_socket.upgrade().is_some());
+    drop(client_socket);
+    assert!(weak_socket.upgrade().is_none());
+    assert!(server_socket.recv().await.is_some());
+    assert!(server_socket.recv().await.is_none());
+}
+
+#[test]
+fn error_conversion() {
+    use teloxide::ApiError;
+    use teloxide::RequestError;
+
+    assert_eq!(
+        BauBotError::from(RequestError::Api(ApiError::BotBlocked)),
+        BauBotError::Blocked {
+            description: "Forbidden: bot was blocked by the user".to_string()
+        }
+    );
+    assert!(matches!(
+        RequestError::Api(ApiError::CantParseEntities(
+            "Bad Request: can't parse entities".to_string()
+        ))
+        .into(),
+        BauBotError::Rejected { .. }
+    ));
+    assert!(matches!(
+        RequestError::Io(std::io::ErrorKind::TimedOut.into()).into(),
+        BauBotError::Uncontactable { .. }
+    ));
+    assert!(matches!(
+        RequestError::Api(ApiError::ChatNotFound).into(),
+        BauBotError::Uncontactable { .. }
+    ));
+
+    // Clients may still send the variant without a description
+    assert_eq!(
+        serde_json::from_str::<BauBotError>(r#"{"type":"Uncontactable"}"#).unwrap(),
+        BauBotError::Uncontactable {
+            description: String::new()
+        }
+    );
+
+    // The description survives the trip to the client
+    let error = BauBotError::Rejected {
+        description: "can't parse entities".to_string(),
+    };
+    let json = serde_json::to_string(&error).unwrap();
+    assert_eq!(
+        json,
+        r#"{"type":"Rejected","description":"can't parse entities"}"#
+    );
+    assert_eq!(serde_json::from_str::<BauBotError>(&json).unwrap(), error);
+}
+
+#[test]
+fn recipients() {
+    let parse = |recipient: &str| recipient.parse::<Recipient>();
+    assert_eq!(parse("alice"), Ok(Recipient::User("alice".to_string())));
+    assert_eq!(parse("@alice"), Ok(Recipient::User("alice".to_string())));
+    assert_eq!(parse("-1001234"), Ok(Recipient::Chat(-1001234)));
+    assert_eq!(parse("#ops"), Ok(Recipient::Group("ops".to_string())));
+    assert_eq!(parse("%oncall"), Ok(Recipient::Role("oncall".to_string())));
+    assert!(parse("").is_err());
+    assert!(parse("#").is_err());
+    assert!(parse("%").is_err());
+
+    // Recipients travel as strings
+    let message = BauMessage::builder(
+        r##"{
+    "sender": "sender",
+    "recipients": ["alice", "-1001234", "#ops", "%oncall"],
+    "message": "hello world"
+}"##,
+    )
+    .unwrap()();
+    let json = serde_json::to_value(&message).unwrap();
+    assert_eq!(
+        json["recipients"],
+        serde_json::json!(["alice", "-1001234", "#ops", "%oncall"])
+    );
+
+    // Aliases of chats without a username are derived from their title
+    assert_eq!(
+        TrackedChat::new(-1, None, "Ops & Security!").alias,
+        "ops-security"
+    );
+    assert_eq!(
+        TrackedChat::new(-1, Some("OpsTeam"), "Ops").alias,
+        "opsteam"
+    );
+}
@@ -0,0 +1,589 @@
+//! Module describing [BauBotBuilder], which configures and validates everything about
+//! [crate::BauBot] that is not the database or the [BauTransport].
+//!
+//! ```no_run
+//! # async fn example(db: std::sync::Arc<impl baubot_core::prelude::BauData + 'static>) {
+//! use baubot_core::config::BauBotBuilder;
+//! use baubot_core::prelude::Command;
+//!
+//! let baubot = BauBotBuilder::new()
+//!     .welcome_text("Welcome to the ACME alerting bot.")
+//!     .catch_all_text("Sorry, I only understand /start, /unregister and /help.")
+//!     .commands([Command::Start, Command::Unregister, Command::Help])
+//!     .build(db, "TOKEN")
+//!     .unwrap();
+//! # }
+//! ```
+
+use crate::prelude::*;
+use crate::transport::UpdateMode;
+
+/// Longest text telegram accepts in a single message, in UTF-16 code units.
+pub(crate) const MAX_TEXT_LENGTH: usize = 4096;
+
+/// Placeholder in [Config::timeout_text] that is replaced by the timeout in milliseconds.
+pub const TIMEOUT_PLACEHOLDER: &str = "{timeout}";
+
+/// What [crate::BauBot] does on startup with the [types::PendingRequest]s left behind by a
+/// previous run (see [BauData::load_pending]).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum RestorePolicy {
+    /// Strip the keyboards and tell the recipients that the request has expired.
+    #[default]
+    Expire,
+
+    /// Keep waiting for responses until the original deadline. Responses are handed to whoever
+    /// calls [crate::BauBot::reclaim] with the originating [types::BauMessage::id]; requests
+    /// without one are expired.
+    ///
+    /// [crate::BauBot::shutdown] leaves such requests untouched so that the next run can resume
+    /// them.
+    Resume,
+}
+
+/// Outbound rate limits of the broadcasts, in messages per second. Each limit also caps the burst
+/// allowed after a quiet period. Defaults to telegram's limits of 30 messages per second overall
+/// and 1 per chat.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimits {
+    /// Messages per second across every chat.
+    pub global: u32,
+
+    /// Messages per second to a single chat.
+    pub per_chat: u32,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            global: 30,
+            per_chat: 1,
+        }
+    }
+}
+
+/// How the broadcasts are retried when telegram fails for a transient reason, i.e. flood control
+/// or a network error. Anything else (e.g. the recipient blocked the bot) is not retried.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts per message, including the first one.
+    pub attempts: u32,
+
+    /// Wait before the first retry of a network error, doubled after every attempt. Flood control
+    /// waits as long as telegram asks instead.
+    pub backoff: std::time::Duration,
+
+    /// Longest wait between attempts after a network error.
+    pub max_backoff: std::time::Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 4,
+            backoff: std::time::Duration::from_millis(500),
+            max_backoff: std::time::Duration::from_secs(8),
+        }
+    }
+}
+
+/// Validated configuration of a running [crate::BauBot]. Created through [BauBotBuilder].
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Parse mode of the [types::BauMessage]s broadcast to recipients. [None] sends them as
+    /// plain text. Replies generated by [crate::BauBot] itself are always HTML.
+    pub(crate) parse_mode: Option<ParseMode>,
+
+    /// Appended to the reply to a successful `/start`. HTML.
+    pub(crate) welcome_text: String,
+
+    /// Reply sent when a recipient does not answer in time. HTML; [TIMEOUT_PLACEHOLDER] is
+    /// replaced by the timeout in milliseconds.
+    pub(crate) timeout_text: String,
+
+    /// Reply to anything [crate::BauBot] does not understand. HTML.
+    pub(crate) catch_all_text: String,
+
+    /// Replaces the text of a broadcast withdrawn through [crate::BauBot::cancel]. HTML.
+    pub(crate) withdrawn_text: String,
+
+    /// Commands users may run. Other commands get [Config::catch_all_text].
+    pub(crate) commands: Vec<Command>,
+
+    /// What to do with [types::PendingRequest]s on startup.
+    pub(crate) restore_policy: RestorePolicy,
+
+    /// Outbound rate limits of the broadcasts.
+    pub(crate) rate_limits: RateLimits,
+
+    /// How failed broadcasts are retried.
+    pub(crate) retry_policy: RetryPolicy,
+
+    /// Most [types::BauMessage]s waiting to be broadcast. [None] leaves the queue unbounded.
+    pub(crate) queue_capacity: Option<usize>,
+
+    /// Most recipients delivered to at once.
+    pub(crate) concurrency: usize,
+
+    /// Log a warning whenever someone presses a button meant for another recipient.
+    pub(crate) log_intruders: bool,
+
+    /// How long the admins have to approve a `/start` of a new user, in milliseconds. [None]
+    /// registers anyone straight away.
+    pub(crate) approval_timeout: Option<u64>,
+
+    /// Directory that [types::AttachmentSource::Path] attachments have to lie in. [None] refuses
+    /// them altogether.
+    pub(crate) attachment_root: Option<std::path::PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            parse_mode: None,
+            welcome_text: "🤗 Welcome to baubot's notification system.".to_string(),
+            timeout_text: crate::fmt!(timeout "Timeout ({timeout}ms) exceeded").to_string(),
+            catch_all_text: crate::fmt!(fail "Baubot does not know how to respond to your input. <b>Baubot is a bad elf!</b>").to_string(),
+            withdrawn_text: crate::fmt!(fail "This request was withdrawn. No response required.").to_string(),
+            commands: vec![Command::Start, Command::Unregister, Command::Help],
+            restore_policy: RestorePolicy::default(),
+            rate_limits: RateLimits::default(),
+            retry_policy: RetryPolicy::default(),
+            queue_capacity: None,
+            concurrency: 16,
+            log_intruders: true,
+            approval_timeout: None,
+            attachment_root: None,
+        }
+    }
+}
+
+impl Config {
+    /// [Config::timeout_text] for a timeout of `timeout` milliseconds.
+    pub(crate) fn timeout_text(&self, timeout: u64) -> String {
+        self.timeout_text
+            .replace(TIMEOUT_PLACEHOLDER, &timeout.to_string())
+    }
+
+    /// Replace the path of every [types::AttachmentSource::Path] of `attachments` by its canonical
+    /// form. Fails if [Config::attachment_root] is not set or a file does not lie within it
+    /// (following symlinks).
+    pub(crate) async fn resolve_attachments(
+        &self,
+        mut attachments: Vec<types::Attachment>,
+    ) -> Result<Vec<types::Attachment>, String> {
+        for attachment in &mut attachments {
+            let types::AttachmentSource::Path(path) = &attachment.source else {
+                continue;
+            };
+            let Some(attachment_root) = &self.attachment_root else {
+                return Err(format!(
+                    "Attachments from paths are disabled: {}",
+                    attachment.filename
+                ));
+            };
+
+            let attachment_root = tokio::fs::canonicalize(attachment_root)
+                .await
+                .map_err(|err| format!("Invalid attachment root: {err}"))?;
+            let resolved = tokio::fs::canonicalize(attachment_root.join(path))
+                .await
+                .map_err(|err| format!("Unable to read attachment {}: {err}", path.display()))?;
+            if !resolved.starts_with(&attachment_root) {
+                return Err(format!(
+                    "Attachment outside of the attachment root: {}",
+                    path.display()
+                ));
+            }
+            attachment.source = types::AttachmentSource::Path(resolved);
+        }
+        Ok(attachments)
+    }
+
+    /// `/help` text listing the enabled [Config::commands], followed by every [AdminCommand]
+    /// for an `admin`.
+    pub(crate) fn help_text(&self, admin: bool) -> String {
+        let admin_commands = match admin {
+            true => AdminCommand::bot_commands(),
+            false => Vec::new(),
+        };
+        Command::bot_commands()
+            .into_iter()
+            .filter(|bot_command| {
+                Command::parse(&bot_command.command, "")
+                    .is_ok_and(|command| self.commands.contains(&command))
+            })
+            .chain(admin_commands)
+            .map(|bot_command| format!("{} — {}", bot_command.command, bot_command.description))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Errors emitted by [BauBotBuilder] when the configuration is invalid.
+#[derive(Debug, PartialEq)]
+pub enum BuildError {
+    /// The named text is empty.
+    EmptyText(&'static str),
+
+    /// The named text is longer than telegram allows in a single message.
+    TextTooLong(&'static str),
+
+    /// The API URL is not an `http` or `https` URL.
+    InvalidApiUrl(String),
+
+    /// A rate limit is zero.
+    InvalidRateLimits(RateLimits),
+
+    /// The retry policy does not allow a single attempt.
+    InvalidRetryPolicy(RetryPolicy),
+
+    /// The queue capacity is zero or larger than tokio allows.
+    InvalidQueueCapacity(usize),
+
+    /// The concurrency limit is zero or larger than tokio allows.
+    InvalidConcurrency(usize),
+
+    /// The approval timeout is zero.
+    InvalidApprovalTimeout(u64),
+}
+
+/// Builder for [crate::BauBot]. Every option defaults to the behaviour of [crate::BauBot::new].
+#[derive(Debug, Default)]
+pub struct BauBotBuilder {
+    config: Config,
+    api_url: Option<url::Url>,
+    update_mode: UpdateMode,
+}
+
+impl BauBotBuilder {
+    /// Create a [BauBotBuilder] with the default configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse broadcast [types::BauMessage]s with `parse_mode` instead of sending plain text.
+    /// Messages without a [types::MessageFormat] that are not valid HTML are rejected if
+    /// `parse_mode` is [ParseMode::Html].
+    pub fn parse_mode(mut self, parse_mode: ParseMode) -> Self {
+        self.config.parse_mode = Some(parse_mode);
+        self
+    }
+
+    /// Text appended to the reply to a successful `/start`. HTML.
+    pub fn welcome_text<S: Into<String>>(mut self, welcome_text: S) -> Self {
+        self.config.welcome_text = welcome_text.into();
+        self
+    }
+
+    /// Reply sent when a recipient does not answer in time. HTML; [TIMEOUT_PLACEHOLDER] is
+    /// replaced by the timeout in milliseconds.
+    pub fn timeout_text<S: Into<String>>(mut self, timeout_text: S) -> Self {
+        self.config.timeout_text = timeout_text.into();
+        self
+    }
+
+    /// Reply to anything [crate::BauBot] does not understand. HTML.
+    pub fn catch_all_text<S: Into<String>>(mut self, catch_all_text: S) -> Self {
+        self.config.catch_all_text = catch_all_text.into();
+        self
+    }
+
+    /// Text that replaces a broadcast withdrawn through [crate::BauBot::cancel]. HTML.
+    pub fn withdrawn_text<S: Into<String>>(mut self, withdrawn_text: S) -> Self {
+        self.config.withdrawn_text = withdrawn_text.into();
+        self
+    }
+
+    /// Only respond to `commands`. Anything else gets the catch-all reply and is left out of
+    /// `/help`.
+    pub fn commands<I: IntoIterator<Item = Command>>(mut self, commands: I) -> Self {
+        self.config.commands = commands.into_iter().collect();
+        self
+    }
+
+    /// Decide what happens to the requests left pending by a previous run. Expires them by
+    /// default.
+    pub fn restore_policy(mut self, restore_policy: RestorePolicy) -> Self {
+        self.config.restore_policy = restore_policy;
+        self
+    }
+
+    /// Throttle broadcasts to `rate_limits` instead of telegram's default limits (e.g. for a bot
+    /// with raised limits).
+    pub fn rate_limits(mut self, rate_limits: RateLimits) -> Self {
+        self.config.rate_limits = rate_limits;
+        self
+    }
+
+    /// Retry broadcasts that failed for a transient reason according to `retry_policy`.
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.config.retry_policy = retry_policy;
+        self
+    }
+
+    /// Queue at most `queue_capacity` [types::BauMessage]s for broadcast. Once the queue is full
+    /// [types::ClientSocket::send] waits for room and [types::ClientSocket::try_send] fails.
+    /// Unbounded by default.
+    pub fn queue_capacity(mut self, queue_capacity: usize) -> Self {
+        self.config.queue_capacity = Some(queue_capacity);
+        self
+    }
+
+    /// Deliver to at most `concurrency` recipients at once, across at most `concurrency`
+    /// [types::BauMessage]s. Messages to the same recipient are still delivered one after another,
+    /// in order. Defaults to 16.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.config.concurrency = concurrency;
+        self
+    }
+
+    /// Whether to log a warning whenever someone presses a button meant for another recipient
+    /// (e.g. in a group chat). The press is rejected either way. Enabled by default.
+    pub fn log_intruders(mut self, log_intruders: bool) -> Self {
+        self.config.log_intruders = log_intruders;
+        self
+    }
+
+    /// Have the admins (see [BauData::is_admin]) approve or reject the `/start` of every new user
+    /// within `approval_timeout` milliseconds. The user is registered and notified once an admin
+    /// approves. Users already registered are not asked again. Disabled by default.
+    pub fn approve_registrations(mut self, approval_timeout: u64) -> Self {
+        self.config.approval_timeout = Some(approval_timeout);
+        self
+    }
+
+    /// Allow [types::AttachmentSource::Path] attachments, as long as they lie within
+    /// `attachment_root`. Relative paths are taken relative to `attachment_root`. Any client of
+    /// [crate::BauBot] (e.g. of a `BauServer`) may have the files in there sent to a chat.
+    /// Disabled by default.
+    pub fn attachment_root<P: Into<std::path::PathBuf>>(mut self, attachment_root: P) -> Self {
+        self.config.attachment_root = Some(attachment_root.into());
+        self
+    }
+
+    /// Talk to the Bot API at `api_url` (e.g. a self-hosted Bot API server) instead of
+    /// `https://api.telegram.org`. Only applies to [BauBotBuilder::build].
+    pub fn api_url(mut self, api_url: url::Url) -> Self {
+        self.api_url = Some(api_url);
+        self
+    }
+
+    /// Receive updates according to `update_mode`. Long polls by default.
+    pub fn update_mode(mut self, update_mode: UpdateMode) -> Self {
+        self.update_mode = update_mode;
+        self
+    }
+
+    /// Validate the configuration and start a [crate::BauBot] that talks to telegram through a
+    /// [Bot] created from `token`.
+    pub fn build<Db, DbRef, S>(
+        self,
+        db: DbRef,
+        token: S,
+    ) -> Result<crate::BauBot<Db, DbRef>, BuildError>
+    where
+        Db: BauData + Send + Sync + 'static,
+        DbRef: Deref<Target = Db> + Clone + Send + Sync + 'static,
+        S: Into<String>,
+    {
+        self.validate()?;
+        let bot = match &self.api_url {
+            Some(api_url) => Bot::new(token).set_api_url(api_url.clone()),
+            None => Bot::new(token),
+        };
+        Ok(crate::BauBot::start(db, bot, self.config, self.update_mode))
+    }
+
+    /// Validate the configuration and start a [crate::BauBot] on top of `transport`. The API URL
+    /// is ignored; configure `transport` directly instead.
+    pub fn build_with_transport<Db, DbRef, T>(
+        self,
+        db: DbRef,
+        transport: T,
+    ) -> Result<crate::BauBot<Db, DbRef, T>, BuildError>
+    where
+        Db: BauData + Send + Sync + 'static,
+        DbRef: Deref<Target = Db> + Clone + Send + Sync + 'static,
+        T: BauTransport,
+    {
+        self.validate()?;
+        Ok(crate::BauBot::start(
+            db,
+            transport,
+            self.config,
+            self.update_mode,
+        ))
+    }
+
+    /// Check the configuration for anything telegram would refuse.
+    fn validate(&self) -> Result<(), BuildError> {
+        let texts = [
+            ("welcome_text", &self.config.welcome_text),
+            ("timeout_text", &self.config.timeout_text),
+            ("catch_all_text", &self.config.catch_all_text),
+            ("withdrawn_text", &self.config.withdrawn_text),
+        ];
+        for (name, text) in texts {
+            if text.trim().is_empty() {
+                return Err(BuildError::EmptyText(name));
+            }
+            if crate::broadcaster::split::length(text) > MAX_TEXT_LENGTH {
+                return Err(BuildError::TextTooLong(name));
+            }
+        }
+
+        let rate_limits = self.config.rate_limits;
+        if rate_limits.global == 0 || rate_limits.per_chat == 0 {
+            return Err(BuildError::InvalidRateLimits(rate_limits));
+        }
+
+        if self.config.retry_policy.attempts == 0 {
+            return Err(BuildError::InvalidRetryPolicy(self.config.retry_policy));
+        }
+
+        if let Some(queue_capacity) = self.config.queue_capacity {
+            if queue_capacity == 0 || queue_capacity > tokio::sync::Semaphore::MAX_PERMITS {
+                return Err(BuildError::InvalidQueueCapacity(queue_capacity));
+            }
+        }
+
+        let concurrency = self.config.concurrency;
+        if concurrency == 0 || concurrency > tokio::sync::Semaphore::MAX_PERMITS {
+            return Err(BuildError::InvalidConcurrency(concurrency));
+        }
+
+        if self.config.approval_timeout == Some(0) {
+            return Err(BuildError::InvalidApprovalTimeout(0));
+        }
+
+        if let Some(api_url) = &self.api_url {
+            if !matches!(api_url.scheme(), "http" | "https") {
+                return Err(BuildError::InvalidApiUrl(api_url.to_string()));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn default_is_valid() {
+    assert_eq!(BauBotBuilder::new().validate(), Ok(()));
+    assert_eq!(
+        Config::default().timeout_text(100),
+        crate::fmt!(timeout "Timeout (100ms) exceeded")
+    );
+}
+
+#[test]
+fn invalid_config() {
+    let builder = BauBotBuilder::new().welcome_text(" ");
+    assert_eq!(
+        builder.validate(),
+        Err(BuildError::EmptyText("welcome_text"))
+    );
+
+    let builder = BauBotBuilder::new().catch_all_text("a".repeat(MAX_TEXT_LENGTH + 1));
+    assert_eq!(
+        builder.validate(),
+        Err(BuildError::TextTooLong("catch_all_text"))
+    );
+
+    let builder = BauBotBuilder::new().api_url("ftp://localhost/".parse().unwrap());
+    assert!(matches!(
+        builder.validate(),
+        Err(BuildError::InvalidApiUrl(_))
+    ));
+
+    let builder = BauBotBuilder::new().rate_limits(RateLimits {
+        global: 30,
+        per_chat: 0,
+    });
+    assert!(matches!(
+        builder.validate(),
+        Err(BuildError::InvalidRateLimits(_))
+    ));
+
+    let builder = BauBotBuilder::new().retry_policy(RetryPolicy {
+        attempts: 0,
+        ..Default::default()
+    });
+    assert!(matches!(
+        builder.validate(),
+        Err(BuildError::InvalidRetryPolicy(_))
+    ));
+
+    let builder = BauBotBuilder::new().queue_capacity(0);
+    assert_eq!(builder.validate(), Err(BuildError::InvalidQueueCapacity(0)));
+
+    let builder = BauBotBuilder::new().concurrency(0);
+    assert_eq!(builder.validate(), Err(BuildError::InvalidConcurrency(0)));
+
+    let builder = BauBotBuilder::new().approve_registrations(0);
+    assert_eq!(
+        builder.validate(),
+        Err(BuildError::InvalidApprovalTimeout(0))
+    );
+}
+
+#[test]
+fn help_text() {
+    let config = Config {
+        commands: vec![Command::Start, Command::Help],
+        ..Default::default()
+    };
+    let help_text = config.help_text(false);
+    assert!(help_text.contains("/start"));
+    assert!(help_text.contains("/help"));
+    assert!(!help_text.contains("/unregister"));
+    assert!(!help_text.contains("/broadcast"));
+
+    // Admin commands are only listed for admins
+    let help_text = config.help_text(true);
+    assert!(help_text.contains("/start"));
+    assert!(help_text.contains("/broadcast"));
+}
+
+#[tokio::test]
+async fn attachment_root() {
+    let root = std::env::temp_dir().join(format!("baubot-attachments-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("invoice.pdf"), "%PDF").unwrap();
+    let attachment = |path: &str| types::Attachment {
+        filename: "invoice.pdf".to_string(),
+        mime_type: "application/pdf".to_string(),
+        source: types::AttachmentSource::Path(path.into()),
+    };
+
+    // Paths are refused unless there is a root
+    let config = Config::default();
+    assert!(config
+        .resolve_attachments(vec![attachment("invoice.pdf")])
+        .await
+        .is_err());
+
+    // And have to lie within it
+    let config = Config {
+        attachment_root: Some(root.clone()),
+        ..Default::default()
+    };
+    let resolved = config
+        .resolve_attachments(vec![attachment("invoice.pdf")])
+        .await
+        .unwrap();
+    assert_eq!(
+        resolved[0].source,
+        types::AttachmentSource::Path(root.canonicalize().unwrap().join("invoice.pdf"))
+    );
+    assert!(config
+        .resolve_attachments(vec![attachment("../../../../../../etc/passwd")])
+        .await
+        .is_err());
+    assert!(config
+        .resolve_attachments(vec![attachment("/etc/passwd")])
+        .await
+        .is_err());
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
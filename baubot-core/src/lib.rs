@@ -25,27 +25,49 @@ pub mod prelude;
 
 pub mod broadcaster;
 
+pub mod config;
+
+pub mod transport;
+
+/// Token of an invite deep link, see [BauBot::invite].
+#[derive(Clone)]
+struct InviteToken(String);
+
 /// # [BauBot]
 /// Call [BauBot::new] with a [BauData] database to start the server(s). A new instance of [BauBot]
 /// is created that implements [Deref] to a [broadcaster::types::ClientSocket] (for sending
-/// a [broadcaster::types::BauMessage]) to [BauBot].
+/// a [broadcaster::types::BauMessage]) to [BauBot]. Sending waits for room in the queue if it is
+/// bounded (see [config::BauBotBuilder::queue_capacity]); use
+/// [broadcaster::types::ClientSocket::try_send] to fail instead.
 ///
 /// Under the hood, calling [BauBot::new] orchestrates and wraps a number of tasks. See
-/// [BauBot::new] for more information.
+/// [BauBot::with_transport] for more information.
+///
+/// [BauBot] talks to telegram through a [transport::BauTransport], which is a [Bot] unless
+/// another transport is supplied through [BauBot::with_transport].
+///
+/// Use a [config::BauBotBuilder] to change texts, commands and other settings.
 pub struct BauBot<
     Db: BauData + Send + Sync + 'static,
     DbRef: Deref<Target = Db> + Clone + Send + Sync + 'static,
+    T: BauTransport = Bot,
 > {
-    db: PhantomData<DbRef>,
+    db: PhantomData<(DbRef, T)>,
     bot_server_handle: task::JoinHandle<()>,
     request_server_handle: task::JoinHandle<()>,
+    request_server: Arc<broadcaster::Server>,
     client_socket: broadcaster::types::ClientSocket,
+
+    /// Shared with the tasks so that [BauBot::cancel] can retract broadcasts
+    database: DbRef,
+    transport: T,
 }
 
 impl<
         Db: BauData + Send + Sync + 'static,
         DbRef: Deref<Target = Db> + Clone + Send + Sync + 'static,
-    > Deref for BauBot<Db, DbRef>
+        T: BauTransport,
+    > Deref for BauBot<Db, DbRef, T>
 {
     type Target = broadcaster::types::ClientSocket;
 
@@ -57,16 +79,21 @@ impl<
 impl<
         Db: BauData + Send + Sync + 'static,
         DbRef: Deref<Target = Db> + Clone + Send + Sync + 'static,
-    > AsRef<broadcaster::types::ClientSocket> for BauBot<Db, DbRef>
+        T: BauTransport,
+    > AsRef<broadcaster::types::ClientSocket> for BauBot<Db, DbRef, T>
 {
     fn as_ref(&self) -> &broadcaster::types::ClientSocket {
         &self.client_socket
     }
 }
 
-/// Ensures that all threads are stopped on drop
-impl<Db: BauData + Send + Sync, DbRef: Deref<Target = Db> + Clone + Send + Sync + 'static> Drop
-    for BauBot<Db, DbRef>
+/// Ensures that all threads are stopped on drop. Call [BauBot::shutdown] beforehand to wind down
+/// gracefully.
+impl<
+        Db: BauData + Send + Sync,
+        DbRef: Deref<Target = Db> + Clone + Send + Sync + 'static,
+        T: BauTransport,
+    > Drop for BauBot<Db, DbRef, T>
 {
     fn drop(&mut self) {
         self.bot_server_handle.abort();
@@ -79,65 +106,241 @@ impl<
         DbRef: Deref<Target = Db> + Clone + Send + Sync + 'static,
     > BauBot<Db, DbRef>
 {
-    /// Creates a new [BauBot]. This runs a number of concurrent tasks
+    /// Creates a new [BauBot] that talks to telegram through a [Bot] created from `token`. See
+    /// [BauBot::with_transport].
+    pub fn new<S: Into<String>>(db: DbRef, token: S) -> Self {
+        Self::with_transport(db, Bot::new(token))
+    }
+}
+
+impl<
+        Db: BauData + Send + Sync + 'static,
+        DbRef: Deref<Target = Db> + Clone + Send + Sync + 'static,
+        T: BauTransport,
+    > BauBot<Db, DbRef, T>
+{
+    /// Creates a new [BauBot] on top of any [BauTransport]. This runs a number of concurrent tasks
     /// - (test mode) Initialise environment variables and logger
     /// - Initialises a request server to listen for requests sent through a
     /// [broadcaster::types::ClientSocket]
-    /// - Initialises a [Bot] to interact with telegram
-    pub fn new<S: Into<String>>(db: DbRef, token: S) -> Self {
+    /// - Restores the requests left pending by a previous run (see [BauData::load_pending])
+    /// - Dispatches updates received through the `transport` to the handlers
+    ///
+    /// Updates are long polled. See [BauBot::with_update_mode] to receive them through a webhook.
+    pub fn with_transport(db: DbRef, transport: T) -> Self {
+        Self::with_update_mode(db, transport, transport::UpdateMode::Polling)
+    }
+
+    /// Same as [BauBot::with_transport], receiving updates according to `update_mode`.
+    pub fn with_update_mode(db: DbRef, transport: T, update_mode: transport::UpdateMode) -> Self {
+        Self::start(db, transport, config::Config::default(), update_mode)
+    }
+
+    /// Start every task with an already validated `config`
+    pub(crate) fn start(
+        db: DbRef,
+        transport: T,
+        config: config::Config,
+        update_mode: transport::UpdateMode,
+    ) -> Self {
         #[cfg(test)]
         baubot_utils::init();
 
         // Create sockets
-        let (client_socket, server_socket) = tokio::sync::mpsc::unbounded_channel();
-
-        // Create bot: If in test mode use utils
-        let bot = Bot::new(token);
+        let (client_socket, server_socket) = broadcaster::types::channel(config.queue_capacity);
 
         // Create server
-        let request_server = Arc::new(broadcaster::Server::new());
+        let config = Arc::new(config);
+        let request_server = Arc::new(broadcaster::Server::new(config.clone()));
 
-        // Start server
+        // Start server. Payloads are only handled once pending requests are restored, otherwise
+        // the restore could pick up (and expire) requests broadcast in the meantime
+        let (restored_sender, restored_receiver) = tokio::sync::oneshot::channel::<()>();
         let request_server_clone = request_server.clone();
         let db_clone = db.clone();
-        let bot_clone = bot.clone();
-        let request_server_handle = task::spawn(async move {
-            broadcaster::Server::listen(request_server_clone, bot_clone, db_clone, server_socket)
-                .await;
+        let transport_clone = transport.clone();
+        let request_server_handle = request_server.tracker.spawn(async move {
+            let _ = restored_receiver.await;
+            broadcaster::Server::listen(
+                request_server_clone,
+                transport_clone,
+                db_clone,
+                server_socket,
+            )
+            .await;
         });
 
+        // Keep handles for BauBot::cancel
+        let database = db.clone();
+        let cancel_transport = transport.clone();
+
         // Create dependancy map
         let mut dependencies = DependencyMap::new();
-        dependencies.insert(db);
-        dependencies.insert(request_server);
-
-        // Wrap bot server handle
-        let bot_server_handle = task::spawn(async move {
-            Dispatcher::builder(bot, Self::handler_builder())
-                .dependencies(dependencies)
-                .build()
-                .dispatch()
-                .await
+        dependencies.insert(db.clone());
+        dependencies.insert(request_server.clone());
+        dependencies.insert(transport.clone());
+        dependencies.insert(config);
+        // Weak so that the queue still closes once every client is gone
+        dependencies.insert(client_socket.downgrade());
+
+        // Wrap bot server handle. Pending requests are restored before dispatching so that no
+        // response to them is missed
+        let request_server_clone = request_server.clone();
+        let bot_server_handle = request_server.tracker.spawn(async move {
+            let shutdown = request_server_clone.shutdown.clone();
+            broadcaster::Server::restore(request_server_clone, transport.clone(), db).await;
+            let _ = restored_sender.send(());
+            transport
+                .dispatch(Self::handler_builder(), dependencies, update_mode, shutdown)
+                .await;
         });
 
         Self {
             db: PhantomData,
             bot_server_handle,
             request_server_handle,
+            request_server,
             client_socket,
+            database,
+            transport: cancel_transport,
         }
     }
 
+    /// Gracefully shut down [BauBot]:
+    /// - New [broadcaster::types::BauMessage]s are refused and queued ones are turned away with
+    ///   [broadcaster::types::BauBotError::Shutdown].
+    /// - Pending responses are resolved with [broadcaster::types::BauBotError::Shutdown] and the
+    ///   recipients' keyboards are removed, unless they are kept for the next run (see
+    ///   [config::RestorePolicy::Resume]).
+    /// - The [BauTransport] stops dispatching updates.
+    ///
+    /// Returns once every task spawned by [BauBot] has finished.
+    pub async fn shutdown(&self) {
+        info!("Shutting down baubot");
+        self.request_server.shutdown.cancel();
+        self.request_server.tracker.close();
+        self.request_server.tracker.wait().await;
+        info!("Baubot shut down");
+    }
+
+    /// Take the receivers for the responses to the restored request `request_id` (see
+    /// [config::RestorePolicy::Resume]), one per recipient still waiting. Responses that arrived
+    /// before the call are delivered straight away. Returns an empty [Vec] if there is nothing to
+    /// reclaim, including on every call after the first.
+    pub async fn reclaim(
+        &self,
+        request_id: &str,
+    ) -> Vec<(String, broadcaster::types::BauResponseReceiver)> {
+        self.request_server.reclaim(request_id).await
+    }
+
+    /// Cancel the [broadcaster::types::BauMessage] with [broadcaster::types::BauMessage::id]
+    /// `request_id` (see [broadcaster::types::BauMessage::ensure_id]). Every broadcast still
+    /// waiting for a response has its keyboard removed and its response resolved with
+    /// [broadcaster::types::BauBotError::Cancelled]. If `withdraw` is set the text of those
+    /// broadcasts is replaced (see [config::BauBotBuilder::withdrawn_text]).
+    ///
+    /// Returns the number of broadcasts retracted, i.e. `0` if the request is unknown or has
+    /// already been answered, timed out or cancelled.
+    pub async fn cancel(&self, request_id: &str, withdraw: bool) -> usize {
+        broadcaster::Server::cancel(
+            &self.request_server,
+            &self.transport,
+            &self.database,
+            request_id,
+            withdraw,
+        )
+        .await
+    }
+
+    /// Issue a one-time [broadcaster::types::Invite] for the client application's `user_id`, valid
+    /// for `valid_for`. Returns its token; whoever opens
+    /// `https://t.me/<bot username>?start=<token>` in time is registered and bound to `user_id`
+    /// (see [BauData::bind_user_id]), without waiting for the admins (see
+    /// [config::BauBotBuilder::approve_registrations]).
+    pub async fn invite(
+        &self,
+        user_id: &str,
+        valid_for: std::time::Duration,
+    ) -> Result<String, String> {
+        use base64::Engine;
+        use rand::RngCore;
+
+        let mut bytes = [0; 24];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let invite = broadcaster::types::Invite {
+            // Telegram only allows letters, digits, `_` and `-` in deep links
+            token: base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes),
+            user_id: user_id.to_string(),
+            deadline: broadcaster::now_millis() + valid_for.as_millis() as u64,
+        };
+        self.database.save_invite(&invite).await?;
+        Ok(invite.token)
+    }
+
+    /// Current state of the outbound queue, i.e. the broadcasts waiting for the rate limits (see
+    /// [config::BauBotBuilder::rate_limits]).
+    pub fn metrics(&self) -> broadcaster::types::SendMetrics {
+        self.request_server.throttle.metrics()
+    }
+
     /// Build the handler schema
-    fn handler_builder() -> UpdateHandler<Box<dyn std::error::Error + Send + Sync + 'static>> {
+    fn handler_builder() -> UpdateHandler<HandlerError> {
         /// Only used here.
         use teloxide::dispatching::dialogue::GetChatId;
 
         // Command handler
-        let command = teloxide::filter_command::<Command, _>().endpoint(Self::command_handler);
+        // Disabled commands fall through to the catch-all
+        let command = teloxide::filter_command::<Command, _>()
+            .filter(|command: Command, config: Arc<config::Config>| {
+                config.commands.contains(&command)
+            })
+            .endpoint(Self::command_handler);
+
+        // Registrations through an invite deep link, i.e. `/start <token>` or
+        // `/start@botname <token>`
+        let invite = dptree::filter_map(|message: Message, me: teloxide::types::Me| {
+            use teloxide::utils::command::BotCommands;
+
+            let (command, args) =
+                teloxide::utils::command::parse_command(message.text()?, me.username())?;
+            let [token] = args[..] else {
+                return None;
+            };
+            (Command::parse(&format!("/{command}"), me.username()).ok()? == Command::Start)
+                .then(|| InviteToken(token.to_string()))
+        })
+        .filter(|config: Arc<config::Config>| config.commands.contains(&Command::Start))
+        .endpoint(Self::invite_handler);
+
+        // Registrations that wait for the approval of an admin
+        let approval = teloxide::filter_command::<Command, _>()
+            .filter(|command: Command, config: Arc<config::Config>| {
+                command == Command::Start
+                    && config.commands.contains(&command)
+                    && config.approval_timeout.is_some()
+            })
+            .endpoint(Self::approval_handler);
+
+        // Admin command handler
+        // Anyone but admins falls through to the catch-all
+        let admin_command = teloxide::filter_command::<AdminCommand, _>()
+            .filter_async(|user: User, db: DbRef| async move {
+                match &user.username {
+                    Some(username) => db.is_admin(username).await,
+                    None => false,
+                }
+            })
+            .endpoint(Self::admin_command_handler);
 
         // Callback handler
-        let callback = broadcaster::Server::callback_update();
+        let callback = broadcaster::Server::callback_update::<Db, DbRef, T>();
+
+        // Free text responses are taken before anything else
+        let reply = broadcaster::Server::reply_update::<Db, DbRef, T>();
+
+        // Groups and channels the bot is added to or removed from
+        let membership = Update::filter_my_chat_member().endpoint(Self::membership_handler);
 
         // Message handler
         let message = Update::filter_message()
@@ -147,40 +350,446 @@ impl<
             .filter_map(|message: Message| message.chat_id())
             // Inject messageId
             .filter_map(|message: Message| Some(message.id))
+            .branch(reply)
+            .branch(invite)
+            .branch(approval)
             .branch(command)
+            .branch(admin_command)
+            // Leave the chatter of groups alone
+            .filter(|message: Message| message.chat.is_private())
             .endpoint(Self::catch_all);
 
         // Overall handler?
-        let master = dptree::entry().branch(callback).branch(message);
+        let master = dptree::entry()
+            .branch(callback)
+            .branch(membership)
+            .branch(message);
 
         master
     }
 
     /// Parse [Command] received by the Bot
     async fn command_handler(
-        bot: Bot,
+        transport: T,
         ChatId(chat_id): ChatId,
         user: User,
         command: Command,
         MessageId(message_id): MessageId,
         db: DbRef,
+        config: Arc<config::Config>,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         // Run command
         let outcome = match command {
-            Command::Start => Self::register_user(db, chat_id, user).await,
+            Command::Start => Self::register_user(db, chat_id, user, &config).await,
             Command::Unregister => Self::delete_user(db, user).await,
-            Command::Help => Ok(Command::descriptions().to_string()),
+            Command::Help => {
+                let admin = match &user.username {
+                    Some(username) => db.is_admin(username).await,
+                    None => false,
+                };
+                Ok(config.help_text(admin))
+            }
         }
         .unwrap_or_else(|err| format!("ERROR: {err}"));
 
         // Send result
-        reply_message(&bot, chat_id, message_id, outcome).await?;
+        transport
+            .reply_message(chat_id, message_id, outcome)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Run an [AdminCommand] received by the Bot. Only admins get here.
+    async fn admin_command_handler(
+        transport: T,
+        message: Message,
+        user: User,
+        command: AdminCommand,
+        db: DbRef,
+        server: Arc<broadcaster::Server>,
+        client_socket: broadcaster::types::WeakClientSocket,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        use teloxide::utils::html::escape;
+
+        // Run command. Anything coming from the admin or the database is escaped, as replies are
+        // parsed as HTML.
+        let outcome = match command {
+            AdminCommand::AddRole { role, username } => {
+                let username = username.trim_start_matches('@');
+                let (escaped, escaped_role) = (escape(username), escape(&role));
+                db.add_role_member(&role, username)
+                    .await
+                    .map(|added| match added {
+                        true => format!(
+                            fmt!(pass "Added {} to <code>%{}</code>"),
+                            escaped, escaped_role
+                        ),
+                        false => format!("{escaped} already is in <code>%{escaped_role}</code>"),
+                    })
+            }
+            AdminCommand::RemoveRole { role, username } => {
+                let username = username.trim_start_matches('@');
+                let (escaped, escaped_role) = (escape(username), escape(&role));
+                db.remove_role_member(&role, username)
+                    .await
+                    .map(|removed| match removed {
+                        true => format!(
+                            fmt!(pass "Removed {} from <code>%{}</code>"),
+                            escaped, escaped_role
+                        ),
+                        false => format!("{escaped} is not in <code>%{escaped_role}</code>"),
+                    })
+            }
+            AdminCommand::Roles => db.get_roles().await.map(|roles| match roles.is_empty() {
+                true => "No roles yet.".to_string(),
+                false => roles
+                    .into_iter()
+                    .map(|(role, members)| {
+                        format!(
+                            "<code>%{}</code> — {}",
+                            escape(&role),
+                            escape(&members.join(", "))
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            }),
+            AdminCommand::Users => db.get_users().await.map(|users| match users.is_empty() {
+                true => "No users yet.".to_string(),
+                false => users
+                    .into_iter()
+                    .map(|(username, chat_id)| {
+                        format!("{} — <code>{chat_id}</code>", escape(&username))
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            }),
+            AdminCommand::RemoveUser(username) => {
+                let username = username.trim().trim_start_matches('@');
+                db.delete_chat_id(username).await.map(|id| {
+                    format!(
+                        fmt!(fail "{} and their chat_id <code>{}</code> have been deleted"),
+                        escape(username),
+                        id
+                    )
+                })
+            }
+            AdminCommand::Broadcast(text) => Self::broadcast(db, user, text, &client_socket).await,
+            AdminCommand::Stats => Self::stats(db, server).await,
+        }
+        .unwrap_or_else(|err| format!("ERROR: {}", escape(&err.to_string())));
+
+        // Send result
+        transport
+            .reply_message(message.chat.id.0, message.id.0, outcome)
+            .await?;
 
         Ok(())
     }
 
+    /// Send `message` from `user` to every registered user through the
+    /// [broadcaster::types::ClientSocket], like any other [broadcaster::types::BauMessage].
+    async fn broadcast(
+        db: DbRef,
+        user: User,
+        message: String,
+        client_socket: &broadcaster::types::WeakClientSocket,
+    ) -> Result<String, String> {
+        if message.trim().is_empty() {
+            return Err("Nothing to broadcast.".to_string());
+        }
+        let recipients = db
+            .get_users()
+            .await?
+            .into_iter()
+            .map(|(username, _)| (broadcaster::types::Recipient::User(username), None))
+            .collect::<Vec<_>>();
+        let count = recipients.len();
+
+        let bau_message = broadcaster::types::BauMessage {
+            id: None,
+            // NOTE: Safe to unwrap because only admins, who are known by username, get here
+            sender: user.username.unwrap(),
+            recipients,
+            message,
+            format: None,
+            attachments: Vec::new(),
+            responses: Default::default(),
+            outcome: None,
+        };
+        Self::enqueue(client_socket, bau_message).await?;
+
+        Ok(format!(fmt!(pass "Broadcasting to {} users"), count))
+    }
+
+    /// Queue `bau_message` on the [broadcaster::types::ClientSocket] unless [BauBot] is shutting
+    /// down.
+    async fn enqueue(
+        client_socket: &broadcaster::types::WeakClientSocket,
+        bau_message: broadcaster::types::BauMessage,
+    ) -> Result<(), String> {
+        client_socket
+            .upgrade()
+            .ok_or("The bot is shutting down.".to_string())?
+            .send(bau_message)
+            .await
+            .map_err(|_| "The bot is shutting down.".to_string())
+    }
+
+    /// Statistics of the bot for [AdminCommand::Stats]
+    async fn stats(db: DbRef, server: Arc<broadcaster::Server>) -> Result<String, String> {
+        let users = db.get_users().await?.len();
+        let roles = db.get_roles().await?.len();
+        let metrics = server.throttle.metrics();
+        Ok(format!(
+            "Users: {users}\n\
+            Roles: {roles}\n\
+            Waiting for a response: {}\n\
+            Queued: {} (peak {})\n\
+            Sent: {} ({} throttled)",
+            server.pending(),
+            metrics.queued,
+            metrics.peak_queued,
+            metrics.sent,
+            metrics.throttled
+        ))
+    }
+
+    /// `/start <token>` of a user following the deep link of an invite.
+    async fn invite_handler(
+        transport: T,
+        message: Message,
+        user: User,
+        InviteToken(token): InviteToken,
+        db: DbRef,
+        config: Arc<config::Config>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let chat_id = message.chat.id.0;
+        let outcome = Self::redeem_invite(db, chat_id, user, &token, &config)
+            .await
+            .unwrap_or_else(|err| format!("ERROR: {err}"));
+
+        // Send result
+        transport
+            .reply_message(chat_id, message.id.0, outcome)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Register `user` if `token` is an invite that has not expired, then bind them to the
+    /// [broadcaster::types::Invite::user_id].
+    async fn redeem_invite(
+        db: DbRef,
+        chat_id: i64,
+        user: User,
+        token: &str,
+        config: &config::Config,
+    ) -> Result<String, String> {
+        // Attempt to get username, reject if fail
+        let username = user.username.ok_or("No username supplied.".to_string())?;
+
+        let invite = db
+            .take_invite(token)
+            .await
+            .ok_or("This invite is invalid or has already been used.".to_string())?;
+        if invite.deadline < broadcaster::now_millis() {
+            return Err("This invite has expired.".to_string());
+        }
+
+        trace!(
+            "Registering {username} as {} through an invite",
+            invite.user_id
+        );
+        let registration = async {
+            db.insert_chat_id(&username, chat_id).await?;
+            db.bind_user_id(&username, user.id.0, &invite.user_id).await
+        };
+
+        // Put the invite back so that it can be redeemed once the error is resolved
+        if let Err(err) = registration.await {
+            if let Err(err) = db.save_invite(&invite).await {
+                error!("Unable to restore invite for {}: {err}", invite.user_id);
+            }
+            return Err(err);
+        }
+        Ok(format!(fmt!(pass "Registered!\n\n{}"), config.welcome_text))
+    }
+
+    /// `/start` of a user while [config::BauBotBuilder::approve_registrations] is enabled.
+    async fn approval_handler(
+        transport: T,
+        message: Message,
+        user: User,
+        db: DbRef,
+        config: Arc<config::Config>,
+        server: Arc<broadcaster::Server>,
+        client_socket: broadcaster::types::WeakClientSocket,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let chat_id = message.chat.id.0;
+        let outcome = Self::request_approval(db, chat_id, user, config, server, client_socket)
+            .await
+            .unwrap_or_else(|err| format!("ERROR: {err}"));
+
+        // Send result
+        transport
+            .reply_message(chat_id, message.id.0, outcome)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Ask the admins to approve the registration of `user`. Users that are already registered
+    /// are registered again straight away.
+    async fn request_approval(
+        db: DbRef,
+        chat_id: i64,
+        user: User,
+        config: Arc<config::Config>,
+        server: Arc<broadcaster::Server>,
+        client_socket: broadcaster::types::WeakClientSocket,
+    ) -> Result<String, String> {
+        // Attempt to get username, reject if fail
+        let username = user
+            .username
+            .clone()
+            .ok_or("No username supplied.".to_string())?;
+
+        if db.get_chat_id(&username).await.is_some() {
+            return Self::register_user(db, chat_id, user, &config).await;
+        }
+
+        let request_id = format!("{}{username}", broadcaster::REGISTRATION_PREFIX);
+        if !server.reserve(&request_id) {
+            return Ok(
+                fmt!(timeout "Your registration is already waiting for approval.").to_string(),
+            );
+        }
+
+        // Ask every admin, the first one to respond decides
+        let admins = async {
+            let mut admins = Vec::new();
+            for (admin, _) in db.get_users().await? {
+                if db.is_admin(&admin).await {
+                    admins.push((broadcaster::types::Recipient::User(admin), None));
+                }
+            }
+            if admins.is_empty() {
+                return Err("No admin is available to approve your registration.".to_string());
+            }
+            Ok(admins)
+        };
+        let admins = match admins.await {
+            Ok(admins) => admins,
+            Err(err) => {
+                server.release(&request_id);
+                return Err(err);
+            }
+        };
+
+        let (outcome_sender, outcome_receiver) = tokio::sync::oneshot::channel();
+        let bau_message = broadcaster::types::BauMessage {
+            id: Some(request_id.clone()),
+            sender: username.clone(),
+            recipients: admins,
+            message: format!(
+                "<b>{}</b> (@{username}) would like to register.",
+                teloxide::utils::html::escape(&user.full_name())
+            ),
+            format: Some(broadcaster::types::MessageFormat::Html),
+            attachments: Vec::new(),
+            responses: broadcaster::types::RequestedResponses {
+                // NOTE: Safe to unwrap because only registrations that need approval get here
+                timeout: config.approval_timeout.unwrap(),
+                keyboard: vec![vec![
+                    broadcaster::types::Button::Labelled {
+                        label: "✅ Approve".to_string(),
+                        value: "approve".to_string(),
+                    },
+                    broadcaster::types::Button::Labelled {
+                        label: "❌ Reject".to_string(),
+                        value: "reject".to_string(),
+                    },
+                ]],
+                quorum: broadcaster::types::Quorum::First,
+                free_text: false,
+            },
+            outcome: Some(outcome_sender),
+        };
+        if let Err(err) = Self::enqueue(&client_socket, bau_message).await {
+            server.release(&request_id);
+            return Err(err);
+        }
+
+        server.tracker.spawn(Self::approval_decision(
+            db,
+            chat_id,
+            username,
+            config,
+            server.clone(),
+            client_socket,
+            outcome_receiver,
+        ));
+
+        Ok(fmt!(timeout "Your registration is waiting for the approval of an admin.").to_string())
+    }
+
+    /// Register the user `username` once an admin approves, then notify them of the decision.
+    async fn approval_decision(
+        db: DbRef,
+        chat_id: i64,
+        username: String,
+        config: Arc<config::Config>,
+        server: Arc<broadcaster::Server>,
+        client_socket: broadcaster::types::WeakClientSocket,
+        outcome_receiver: broadcaster::types::QuorumOutcomeReceiver,
+    ) {
+        let message = match outcome_receiver.await {
+            Ok(broadcaster::types::QuorumOutcome::Answered {
+                recipient,
+                response,
+            }) if response == "approve" => {
+                info!("{recipient} approved the registration of {username}");
+                match db.insert_chat_id(&username, chat_id).await {
+                    Ok(_) => format!(
+                        fmt!(pass "Your registration has been approved!\n\n{}"),
+                        config.welcome_text
+                    ),
+                    Err(err) => format!("ERROR: {err}"),
+                }
+            }
+            Ok(broadcaster::types::QuorumOutcome::Answered { recipient, .. }) => {
+                info!("{recipient} rejected the registration of {username}");
+                fmt!(fail "Your registration has been rejected.").to_string()
+            }
+            _ => fmt!(timeout "Your registration was not approved in time.").to_string(),
+        };
+        server.release(&format!("{}{username}", broadcaster::REGISTRATION_PREFIX));
+
+        // Notify user
+        let bau_message = broadcaster::types::BauMessage {
+            id: None,
+            sender: username,
+            recipients: vec![(broadcaster::types::Recipient::Chat(chat_id), None)],
+            message,
+            format: Some(broadcaster::types::MessageFormat::Html),
+            attachments: Vec::new(),
+            responses: Default::default(),
+            outcome: None,
+        };
+        if let Err(err) = Self::enqueue(&client_socket, bau_message).await {
+            error!("Unable to notify {chat_id} of their registration: {err}");
+        }
+    }
+
     /// Handler to register a user in the DB
-    async fn register_user(db: DbRef, chat_id: i64, user: User) -> Result<String, String> {
+    async fn register_user(
+        db: DbRef,
+        chat_id: i64,
+        user: User,
+        config: &config::Config,
+    ) -> Result<String, String> {
         // Attempt to get username, reject if fail
         let username = user.username.ok_or(format!("No username supplied."))?;
 
@@ -194,7 +803,7 @@ impl<
                         format!(" Your old registration of <code>{id}</code> has been updated."),
                     None => "".to_string(),
                 },
-                "🤗 Welcome to baubot's notification system."
+                config.welcome_text
             )),
             Err(err) => Err(err.to_string()),
         }
@@ -215,20 +824,46 @@ impl<
         }
     }
 
+    /// Track the groups and channels the bot is a member of in the DB, so that they can be
+    /// addressed as a [broadcaster::types::Recipient::Group].
+    async fn membership_handler(
+        db: DbRef,
+        update: teloxide::types::ChatMemberUpdated,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let chat = update.chat;
+        if chat.is_private() {
+            return Ok(());
+        }
+
+        let result = if update.new_chat_member.is_present() {
+            let chat = broadcaster::types::TrackedChat::new(
+                chat.id.0,
+                chat.username(),
+                chat.title().unwrap_or_default(),
+            );
+            info!("Added to {} as #{}", chat.chat_id, chat.alias);
+            db.save_chat(&chat).await
+        } else {
+            info!("Removed from {}", chat.id);
+            db.remove_chat(chat.id.0).await
+        };
+        if let Err(err) = result {
+            error!("Unable to track chat {}: {err}", chat.id);
+        }
+
+        Ok(())
+    }
+
     /// Catch-all
     async fn catch_all(
-        bot: Bot,
+        transport: T,
         MessageId(message_id): MessageId,
         ChatId(chat_id): ChatId,
+        config: Arc<config::Config>,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        reply_message(
-            &bot,
-            chat_id,
-            message_id,
-            fmt!(fail "Baubot does not know how to respond to your input. <b>Baubot is a bad elf!</b>" )
-                .into(),
-        )
-        .await?;
+        transport
+            .reply_message(chat_id, message_id, config.catch_all_text.clone())
+            .await?;
 
         Ok(())
     }
@@ -2,6 +2,8 @@
 
 // Use this within the carate only
 pub use crate::broadcaster::types;
+pub use crate::transport::BauTransport;
+pub(crate) use crate::transport::HandlerError;
 #[allow(unused_imports)]
 pub(crate) use log::{error, info, log, trace, warn};
 pub(crate) use std::ops::Deref;
@@ -10,9 +12,12 @@ pub(crate) use teloxide::dispatching::UpdateFilterExt;
 pub(crate) use teloxide::dispatching::UpdateHandler;
 pub(crate) use teloxide::prelude::*;
 pub(crate) use teloxide::types::MessageId;
+pub use teloxide::types::ParseMode;
+pub use teloxide::types::ReplyMarkup;
 pub(crate) use teloxide::types::ReplyParameters;
 pub(crate) use teloxide::types::User;
 pub(crate) use teloxide::utils::command::BotCommands;
+pub use teloxide::Bot;
 pub(crate) use tokio::task;
 
 #[macro_export]
@@ -37,20 +42,6 @@ fn macro_test() {
     assert_eq!(other_string, string);
 }
 
-/// Instruct the bot to reply to a particular
-pub(crate) async fn reply_message(
-    bot: &Bot,
-    chat_id: i64,
-    message_id: i32,
-    text: String,
-) -> Result<Message, teloxide::RequestError> {
-    let message = bot
-        .send_message(ChatId(chat_id), text)
-        .reply_parameters(ReplyParameters::new(MessageId(message_id)))
-        .parse_mode(teloxide::types::ParseMode::Html);
-    message.await
-}
-
 /// Trait for database that [crate::BauBot] is able to interact with
 pub trait BauData
 where
@@ -103,11 +94,162 @@ where
     /// The implementation of this trait should make all necessary authentication choices at the
     /// appropriate stages (e.g. verifying that the user is allowed to receive or send requests)
     fn is_admin(&self, username: &str) -> impl std::future::Future<Output = bool> + Send;
+
+    /// Every registered user along with their chat_id, ordered by username. Empty by default, in
+    /// which case [AdminCommand::Users] lists nobody and [AdminCommand::Broadcast] reaches
+    /// nobody.
+    fn get_users(
+        &self,
+    ) -> impl std::future::Future<Output = Result<Vec<(String, i64)>, String>> + Send {
+        async { Ok(Vec::new()) }
+    }
+
+    /// Persist a [types::PendingRequest], replacing any earlier record for the same `chat_id` and
+    /// `message_id`. Does nothing by default, in which case pending requests are lost on restart.
+    fn save_pending(
+        &self,
+        pending: &types::PendingRequest,
+    ) -> impl std::future::Future<Output = Result<(), String>> + Send {
+        let _ = pending;
+        async { Ok(()) }
+    }
+
+    /// Forget the [types::PendingRequest] for `message_id` in `chat_id` once it has been answered
+    /// or has expired.
+    fn remove_pending(
+        &self,
+        chat_id: i64,
+        message_id: i32,
+    ) -> impl std::future::Future<Output = Result<(), String>> + Send {
+        let _ = (chat_id, message_id);
+        async { Ok(()) }
+    }
+
+    /// Every [types::PendingRequest] saved and not yet removed. Called once when [crate::BauBot]
+    /// starts.
+    fn load_pending(
+        &self,
+    ) -> impl std::future::Future<Output = Result<Vec<types::PendingRequest>, String>> + Send {
+        async { Ok(Vec::new()) }
+    }
+
+    /// Remember that [crate::BauBot] was added to `chat`, replacing any earlier record for the
+    /// same `chat_id`. Does nothing by default, in which case no [types::Recipient::Group] can be
+    /// resolved.
+    fn save_chat(
+        &self,
+        chat: &types::TrackedChat,
+    ) -> impl std::future::Future<Output = Result<(), String>> + Send {
+        let _ = chat;
+        async { Ok(()) }
+    }
+
+    /// Forget the chat `chat_id` once [crate::BauBot] has left it or was removed from it.
+    fn remove_chat(
+        &self,
+        chat_id: i64,
+    ) -> impl std::future::Future<Output = Result<(), String>> + Send {
+        let _ = chat_id;
+        async { Ok(()) }
+    }
+
+    /// Get the chat_id of the [types::TrackedChat] with `alias`.
+    fn get_group_chat_id(
+        &self,
+        alias: &str,
+    ) -> impl std::future::Future<Output = Option<i64>> + Send {
+        let _ = alias;
+        async { None }
+    }
+
+    /// Remember `invite` until it is taken through [BauData::take_invite]. Fails by default, in
+    /// which case [crate::BauBot::invite] cannot issue invites.
+    fn save_invite(
+        &self,
+        invite: &types::Invite,
+    ) -> impl std::future::Future<Output = Result<(), String>> + Send {
+        let _ = invite;
+        async { Err("Invites are not supported.".to_string()) }
+    }
+
+    /// Forget the [types::Invite] with `token` and return it, so that every invite is used at
+    /// most once. Expired invites may be returned; [crate::BauBot] checks the deadline itself and
+    /// saves the invite again if the registration fails.
+    fn take_invite(
+        &self,
+        token: &str,
+    ) -> impl std::future::Future<Output = Option<types::Invite>> + Send {
+        let _ = token;
+        async { None }
+    }
+
+    /// Bind the registered `username`, whose telegram user id is `telegram_id`, to `user_id` of
+    /// the client application once they redeem a [types::Invite]. Called right after
+    /// [BauData::insert_chat_id]. Does nothing by default.
+    fn bind_user_id(
+        &self,
+        username: &str,
+        telegram_id: u64,
+        user_id: &str,
+    ) -> impl std::future::Future<Output = Result<(), String>> + Send {
+        let _ = (username, telegram_id, user_id);
+        async { Ok(()) }
+    }
+
+    /// Telegram user id stored for `username` by [BauData::bind_user_id], if any. Usernames can
+    /// be changed or taken over on telegram, the id cannot. [None] by default.
+    fn get_telegram_id(
+        &self,
+        username: &str,
+    ) -> impl std::future::Future<Output = Option<u64>> + Send {
+        let _ = username;
+        async { None }
+    }
+
+    /// Usernames of the members of `role`, see [types::Recipient::Role]. Empty by default, in
+    /// which case no [types::Recipient::Role] can be resolved.
+    fn get_role_members(
+        &self,
+        role: &str,
+    ) -> impl std::future::Future<Output = Vec<String>> + Send {
+        let _ = role;
+        async { Vec::new() }
+    }
+
+    /// Every role along with its members, ordered by role. Empty by default.
+    fn get_roles(
+        &self,
+    ) -> impl std::future::Future<Output = Result<Vec<(String, Vec<String>)>, String>> + Send {
+        async { Ok(Vec::new()) }
+    }
+
+    /// Add `username` to `role`. Returns `false` if they already were a member. Fails by
+    /// default.
+    fn add_role_member(
+        &self,
+        role: &str,
+        username: &str,
+    ) -> impl std::future::Future<Output = Result<bool, String>> + Send {
+        let _ = (role, username);
+        async { Err("Roles are not supported.".to_string()) }
+    }
+
+    /// Remove `username` from `role`. Returns `false` if they were not a member. Fails by
+    /// default.
+    fn remove_role_member(
+        &self,
+        role: &str,
+        username: &str,
+    ) -> impl std::future::Future<Output = Result<bool, String>> + Send {
+        let _ = (role, username);
+        async { Err("Roles are not supported.".to_string()) }
+    }
 }
 
-#[derive(BotCommands, Clone, Debug)]
+/// Commands understood by [crate::BauBot]. See [crate::config::BauBotBuilder::commands].
+#[derive(BotCommands, Clone, Debug, PartialEq)]
 #[command(rename_rule = "lowercase")]
-pub(crate) enum Command {
+pub enum Command {
     #[command(description = "Registers you as a user of the dobby service")]
     Start,
     #[command(description = "Unregister you as a user of the dobby service")]
@@ -115,3 +257,30 @@ pub(crate) enum Command {
     #[command(description = "Get list of available commands")]
     Help,
 }
+
+/// Commands only admins may run (see [BauData::is_admin]). They are always enabled and only
+/// listed in the `/help` of admins; anyone else gets the catch-all reply.
+#[derive(BotCommands, Clone, Debug, PartialEq)]
+#[command(rename_rule = "lowercase")]
+pub enum AdminCommand {
+    #[command(
+        description = "Add a user to a role: /addrole <role> <username>",
+        parse_with = "split"
+    )]
+    AddRole { role: String, username: String },
+    #[command(
+        description = "Remove a user from a role: /removerole <role> <username>",
+        parse_with = "split"
+    )]
+    RemoveRole { role: String, username: String },
+    #[command(description = "List the roles and their members")]
+    Roles,
+    #[command(description = "List the registered users")]
+    Users,
+    #[command(description = "Unregister a user: /removeuser <username>")]
+    RemoveUser(String),
+    #[command(description = "Send a message to every registered user: /broadcast <message>")]
+    Broadcast(String),
+    #[command(description = "Show statistics of the bot")]
+    Stats,
+}
@@ -0,0 +1,359 @@
+//! Module describing [BauTransport], the interface through which [crate::BauBot] and the
+//! [crate::broadcaster] talk to telegram.
+//!
+//! [Bot] is the default [BauTransport]. Tests can swap it out for a [recorder::Recorder] (behind
+//! the `test-utils` feature) which records everything sent out and lets the test inject
+//! [Update]s without a network.
+//!
+//! [Bot] receives [Update]s by long polling unless told otherwise through [UpdateMode].
+
+use crate::prelude::*;
+use std::future::Future;
+use teloxide::dispatching::ShutdownToken;
+use teloxide::types::InputMedia;
+use teloxide::types::InputMediaDocument;
+use teloxide::types::InputMediaPhoto;
+use teloxide::update_listeners::webhooks;
+use teloxide::RequestError;
+use tokio_util::sync::CancellationToken;
+
+#[cfg(any(test, feature = "test-utils"))]
+pub mod recorder;
+
+/// Error handler type for the [UpdateHandler] tree built by [crate::BauBot].
+pub type HandlerError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// How a [BauTransport] receives [Update]s from telegram.
+#[derive(Debug, Clone, Default)]
+pub enum UpdateMode {
+    /// Long poll telegram for [Update]s.
+    #[default]
+    Polling,
+
+    /// Have telegram push [Update]s to a local HTTP listener. See [Webhook].
+    Webhook(Webhook),
+}
+
+/// Webhook configuration for [UpdateMode::Webhook].
+///
+/// The webhook is registered with telegram when dispatching starts and removed when it stops.
+/// Requests without the expected `X-Telegram-Bot-Api-Secret-Token` header are rejected.
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    /// Local address the HTTP listener binds to.
+    pub address: std::net::SocketAddr,
+
+    /// Public URL telegram sends [Update]s to (e.g. through a reverse proxy in front of
+    /// `address`). The listener serves the path of this URL.
+    pub url: url::Url,
+
+    /// Secret telegram must send in the `X-Telegram-Bot-Api-Secret-Token` header. A random secret
+    /// is generated if none is provided.
+    pub secret_token: Option<String>,
+}
+
+impl Webhook {
+    /// Listen on `address` for [Update]s sent to `url`, with a random secret.
+    pub fn new(address: std::net::SocketAddr, url: url::Url) -> Self {
+        Self {
+            address,
+            url,
+            secret_token: None,
+        }
+    }
+
+    /// Use `secret_token` instead of a random secret.
+    pub fn secret_token<S: Into<String>>(self, secret_token: S) -> Self {
+        Self {
+            secret_token: Some(secret_token.into()),
+            ..self
+        }
+    }
+}
+
+/// Everything [crate::BauBot] needs from telegram.
+///
+/// Implementors are cloned into every handler (through the [DependencyMap]) so they should be
+/// cheap to clone.
+pub trait BauTransport
+where
+    Self: Clone + Send + Sync + 'static,
+{
+    /// Send `text` to `chat_id`, parsed according to `parse_mode` and optionally attaching
+    /// `reply_markup` (an inline keyboard or a [teloxide::types::ForceReply] prompt). Returns the
+    /// `message_id` of the sent message.
+    fn send_message(
+        &self,
+        chat_id: i64,
+        text: String,
+        parse_mode: Option<ParseMode>,
+        reply_markup: Option<ReplyMarkup>,
+    ) -> impl Future<Output = Result<i32, RequestError>> + Send;
+
+    /// Send `attachment` to `chat_id` as a photo or a document (see
+    /// [types::Attachment::is_photo]) with an optional `caption`, parsed according to
+    /// `parse_mode`, and optionally attaching `reply_markup`. Returns the `message_id` of the sent
+    /// message.
+    fn send_attachment(
+        &self,
+        chat_id: i64,
+        attachment: types::Attachment,
+        caption: Option<String>,
+        parse_mode: Option<ParseMode>,
+        reply_markup: Option<ReplyMarkup>,
+    ) -> impl Future<Output = Result<i32, RequestError>> + Send;
+
+    /// Send 2 to 10 `attachments` to `chat_id` as a single album. The attachments are sent as
+    /// photos if they all are (see [types::Attachment::is_photo]), as documents otherwise. Returns
+    /// the `message_id`s of the sent messages.
+    fn send_media_group(
+        &self,
+        chat_id: i64,
+        attachments: Vec<types::Attachment>,
+    ) -> impl Future<Output = Result<Vec<i32>, RequestError>> + Send;
+
+    /// Remove the inline keyboard from `message_id` in `chat_id`.
+    fn remove_markup(
+        &self,
+        chat_id: i64,
+        message_id: i32,
+    ) -> impl Future<Output = Result<(), RequestError>> + Send;
+
+    /// Replace the text of `message_id` in `chat_id` with `text`, parsed as HTML. This also removes
+    /// the inline keyboard.
+    fn edit_text(
+        &self,
+        chat_id: i64,
+        message_id: i32,
+        text: String,
+    ) -> impl Future<Output = Result<(), RequestError>> + Send;
+
+    /// Reply to `message_id` in `chat_id` with `text`. Returns the `message_id` of the reply.
+    fn reply_message(
+        &self,
+        chat_id: i64,
+        message_id: i32,
+        text: String,
+    ) -> impl Future<Output = Result<i32, RequestError>> + Send;
+
+    /// Answer a [CallbackQuery] so that the client stops showing a progress bar. `text` is shown
+    /// to the user as a notification if provided, or as an alert they have to dismiss if `alert`
+    /// is set.
+    fn answer_callback(
+        &self,
+        callback_id: String,
+        text: Option<String>,
+        alert: bool,
+    ) -> impl Future<Output = Result<(), RequestError>> + Send;
+
+    /// Feed incoming [Update]s through `handler` until the update source runs dry or `shutdown` is
+    /// cancelled. `dependencies` must be made available to every invocation of `handler`, together
+    /// with the [Update] itself, the transport and a [teloxide::types::Me]. `update_mode` selects
+    /// where [Update]s come from; transports without a choice may ignore it.
+    ///
+    /// On `shutdown`, implementors should stop taking new [Update]s and return once the handlers
+    /// already running have finished.
+    fn dispatch(
+        self,
+        handler: UpdateHandler<HandlerError>,
+        dependencies: DependencyMap,
+        update_mode: UpdateMode,
+        shutdown: CancellationToken,
+    ) -> impl Future<Output = ()> + Send;
+}
+
+impl BauTransport for Bot {
+    async fn send_message(
+        &self,
+        chat_id: i64,
+        text: String,
+        parse_mode: Option<ParseMode>,
+        reply_markup: Option<ReplyMarkup>,
+    ) -> Result<i32, RequestError> {
+        let mut message_sender = Requester::send_message(self, ChatId(chat_id), text);
+        message_sender.parse_mode = parse_mode;
+        message_sender.reply_markup = reply_markup;
+
+        Ok(message_sender.await?.id.0)
+    }
+
+    async fn send_attachment(
+        &self,
+        chat_id: i64,
+        attachment: types::Attachment,
+        caption: Option<String>,
+        parse_mode: Option<ParseMode>,
+        reply_markup: Option<ReplyMarkup>,
+    ) -> Result<i32, RequestError> {
+        let input_file = attachment.input_file()?;
+        let message = if attachment.is_photo() {
+            let mut photo_sender = self.send_photo(ChatId(chat_id), input_file);
+            photo_sender.caption = caption;
+            photo_sender.parse_mode = parse_mode;
+            photo_sender.reply_markup = reply_markup;
+            photo_sender.await?
+        } else {
+            let mut document_sender = self.send_document(ChatId(chat_id), input_file);
+            document_sender.caption = caption;
+            document_sender.parse_mode = parse_mode;
+            document_sender.reply_markup = reply_markup;
+            document_sender.await?
+        };
+        Ok(message.id.0)
+    }
+
+    async fn send_media_group(
+        &self,
+        chat_id: i64,
+        attachments: Vec<types::Attachment>,
+    ) -> Result<Vec<i32>, RequestError> {
+        let photos = attachments.iter().all(types::Attachment::is_photo);
+        let media = attachments
+            .iter()
+            .map(|attachment| {
+                let input_file = attachment.input_file()?;
+                Ok(match photos {
+                    true => InputMedia::Photo(InputMediaPhoto::new(input_file)),
+                    false => InputMedia::Document(InputMediaDocument::new(input_file)),
+                })
+            })
+            .collect::<Result<Vec<_>, RequestError>>()?;
+
+        let messages = Requester::send_media_group(self, ChatId(chat_id), media).await?;
+        Ok(messages.into_iter().map(|message| message.id.0).collect())
+    }
+
+    async fn remove_markup(&self, chat_id: i64, message_id: i32) -> Result<(), RequestError> {
+        let mut message_edit =
+            self.edit_message_reply_markup(ChatId(chat_id), MessageId(message_id));
+        message_edit.reply_markup = None;
+        message_edit.await?;
+        Ok(())
+    }
+
+    async fn edit_text(
+        &self,
+        chat_id: i64,
+        message_id: i32,
+        text: String,
+    ) -> Result<(), RequestError> {
+        self.edit_message_text(ChatId(chat_id), MessageId(message_id), text)
+            .parse_mode(ParseMode::Html)
+            .await?;
+        Ok(())
+    }
+
+    async fn reply_message(
+        &self,
+        chat_id: i64,
+        message_id: i32,
+        text: String,
+    ) -> Result<i32, RequestError> {
+        let message = Requester::send_message(self, ChatId(chat_id), text)
+            .reply_parameters(ReplyParameters::new(MessageId(message_id)))
+            .parse_mode(ParseMode::Html);
+        Ok(message.await?.id.0)
+    }
+
+    async fn answer_callback(
+        &self,
+        callback_id: String,
+        text: Option<String>,
+        alert: bool,
+    ) -> Result<(), RequestError> {
+        let mut answer = self.answer_callback_query(callback_id);
+        answer.text = text;
+        answer.show_alert = Some(alert);
+        answer.await?;
+        Ok(())
+    }
+
+    async fn dispatch(
+        self,
+        handler: UpdateHandler<HandlerError>,
+        dependencies: DependencyMap,
+        update_mode: UpdateMode,
+        shutdown: CancellationToken,
+    ) {
+        let mut dispatcher = Dispatcher::builder(self.clone(), handler)
+            .dependencies(dependencies)
+            .build();
+        let shutdown_token = dispatcher.shutdown_token();
+
+        let webhook = match update_mode {
+            UpdateMode::Polling => {
+                return dispatch_until(dispatcher.dispatch(), shutdown_token, shutdown).await
+            }
+            UpdateMode::Webhook(webhook) => webhook,
+        };
+
+        // Bind before registering the webhook so that telegram is never pointed at nothing
+        let tcp_listener = match tokio::net::TcpListener::bind(webhook.address).await {
+            Ok(tcp_listener) => tcp_listener,
+            Err(err) => {
+                error!("Unable to bind webhook to {}: {err:?}", webhook.address);
+                return;
+            }
+        };
+
+        // Register webhook. The listener unregisters it once the dispatcher stops it.
+        let mut options = webhooks::Options::new(webhook.address, webhook.url);
+        options.secret_token = webhook.secret_token;
+        let (listener, stop_flag, router) = match webhooks::axum_to_router(self, options).await {
+            Ok(webhook) => webhook,
+            Err(err) => {
+                error!("Unable to register webhook: {err:?}");
+                return;
+            }
+        };
+        let webhook_server = tokio::task::spawn(async move {
+            axum::serve(tcp_listener, router)
+                .with_graceful_shutdown(stop_flag)
+                .await
+        });
+
+        let error_handler =
+            LoggingErrorHandler::with_custom_text("An error from the webhook listener");
+        dispatch_until(
+            dispatcher.dispatch_with_listener(listener, error_handler),
+            shutdown_token,
+            shutdown,
+        )
+        .await;
+
+        // Wait for the webhook to be unregistered
+        match webhook_server.await {
+            Ok(Ok(())) => trace!("Webhook server stopped"),
+            Ok(Err(err)) => error!("Webhook server error: {err:?}"),
+            Err(err) => error!("Webhook server panicked: {err:?}"),
+        }
+    }
+}
+
+/// Drive `dispatch` until it finishes by itself or `shutdown` is cancelled, in which case the
+/// dispatcher behind `shutdown_token` is shut down and left to finish its running handlers.
+async fn dispatch_until<F: Future<Output = ()>>(
+    dispatch: F,
+    shutdown_token: ShutdownToken,
+    shutdown: CancellationToken,
+) {
+    tokio::pin!(dispatch);
+
+    // Dispatch until we are told to shut down
+    tokio::select! {
+        _ = &mut dispatch => return,
+        _ = shutdown.cancelled() => {}
+    }
+
+    // The dispatcher refuses to shut down while it is still starting up, so keep it running
+    // until it accepts
+    while shutdown_token.shutdown().is_err() {
+        let retry = std::time::Duration::from_millis(100);
+        if tokio::time::timeout(retry, &mut dispatch).await.is_ok() {
+            return;
+        }
+    }
+
+    // Wait for running handlers to finish
+    dispatch.await
+}
@@ -0,0 +1,477 @@
+//! In-memory [BauTransport] for tests. [Recorder] never talks to telegram: every outgoing call is
+//! appended to a list of [Record]s and [Update]s are injected by the test through
+//! [Recorder::send_text], [Recorder::reply_text] and [Recorder::press_button]. Failures are
+//! injected through [Recorder::fail_next].
+
+use super::*;
+use std::collections::VecDeque;
+use std::sync::atomic::AtomicI32;
+use std::sync::atomic::Ordering;
+use teloxide::types::InlineKeyboardButtonKind;
+use teloxide::types::Me;
+use tokio::sync::mpsc;
+use tokio::sync::Notify;
+
+/// Re-exported to build the failures passed to [Recorder::fail_next].
+pub use teloxide::types::Seconds;
+pub use teloxide::ApiError;
+pub use teloxide::RequestError;
+
+/// Username of the bot as reported through [Me].
+pub const RECORDER_USERNAME: &str = "baubot";
+
+/// Outgoing call recorded by [Recorder].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Record {
+    /// [BauTransport::send_message]
+    SendMessage {
+        chat_id: i64,
+        message_id: i32,
+        text: String,
+        parse_mode: Option<ParseMode>,
+        reply_markup: Option<ReplyMarkup>,
+    },
+
+    /// [BauTransport::send_attachment]
+    SendAttachment {
+        chat_id: i64,
+        message_id: i32,
+        attachment: types::Attachment,
+        caption: Option<String>,
+        parse_mode: Option<ParseMode>,
+        reply_markup: Option<ReplyMarkup>,
+    },
+
+    /// [BauTransport::send_media_group]
+    SendMediaGroup {
+        chat_id: i64,
+        message_ids: Vec<i32>,
+        attachments: Vec<types::Attachment>,
+    },
+
+    /// [BauTransport::remove_markup]
+    RemoveMarkup { chat_id: i64, message_id: i32 },
+
+    /// [BauTransport::edit_text]
+    EditText {
+        chat_id: i64,
+        message_id: i32,
+        text: String,
+    },
+
+    /// [BauTransport::reply_message]
+    ReplyMessage {
+        chat_id: i64,
+        message_id: i32,
+        reply_to: i32,
+        text: String,
+    },
+
+    /// [BauTransport::answer_callback]
+    AnswerCallback {
+        callback_id: String,
+        text: Option<String>,
+        alert: bool,
+    },
+}
+
+/// In-memory [BauTransport]. Clones share the same state.
+#[derive(Clone)]
+pub struct Recorder {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    records: std::sync::Mutex<Vec<Record>>,
+    notify: Notify,
+    next_id: AtomicI32,
+    failures: std::sync::Mutex<VecDeque<RequestError>>,
+    update_sender: mpsc::UnboundedSender<Update>,
+    update_receiver: std::sync::Mutex<Option<mpsc::UnboundedReceiver<Update>>>,
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Recorder {
+    /// Create a new [Recorder] with no [Record]s.
+    pub fn new() -> Self {
+        let (update_sender, update_receiver) = mpsc::unbounded_channel();
+        Self {
+            inner: Arc::new(Inner {
+                records: Default::default(),
+                notify: Notify::new(),
+                next_id: AtomicI32::new(1),
+                failures: Default::default(),
+                update_sender,
+                update_receiver: std::sync::Mutex::new(Some(update_receiver)),
+            }),
+        }
+    }
+
+    /// Snapshot of every [Record] so far.
+    pub fn records(&self) -> Vec<Record> {
+        self.inner.records.lock().unwrap().clone()
+    }
+
+    /// Wait until a [Record] matching `predicate` shows up and return it.
+    pub async fn wait_for<F: Fn(&Record) -> bool>(&self, predicate: F) -> Record {
+        loop {
+            // Register interest before checking to avoid missing a notification
+            let notified = self.inner.notify.notified();
+            if let Some(record) = self.records().into_iter().find(|record| predicate(record)) {
+                break record;
+            }
+            notified.await;
+        }
+    }
+
+    /// Inject a text message (e.g. a command) sent by `username` in the private chat `chat_id`.
+    pub fn send_text(&self, chat_id: i64, username: &str, text: &str) -> i32 {
+        let message_id = self.next_id();
+        let message = serde_json::json!({
+            "message_id": message_id,
+            "date": 1,
+            "chat": Self::chat_json(chat_id, username),
+            "from": Self::user_json(chat_id, username),
+            "text": text,
+        });
+        self.push_update(serde_json::json!({ "message": message }));
+        message_id
+    }
+
+    /// Inject a text reply by `username` in the private chat `chat_id` to the message `reply_to`
+    /// sent by the bot (e.g. a [teloxide::types::ForceReply] prompt).
+    pub fn reply_text(&self, chat_id: i64, username: &str, reply_to: i32, text: &str) -> i32 {
+        let message_id = self.next_id();
+        let reply_to_message = serde_json::json!({
+            "message_id": reply_to,
+            "date": 1,
+            "chat": Self::chat_json(chat_id, username),
+            "from": Self::me_json(),
+            "text": "",
+        });
+        let message = serde_json::json!({
+            "message_id": message_id,
+            "date": 1,
+            "chat": Self::chat_json(chat_id, username),
+            "from": Self::user_json(chat_id, username),
+            "text": text,
+            "reply_to_message": reply_to_message,
+        });
+        self.push_update(serde_json::json!({ "message": message }));
+        message_id
+    }
+
+    /// Inject a press of the inline keyboard button labelled `label` on `message_id`. If no such
+    /// button was sent, `label` is taken as the callback data.
+    pub fn press_button(&self, chat_id: i64, username: &str, message_id: i32, label: &str) {
+        self.press_button_as(chat_id, chat_id, username, message_id, label)
+    }
+
+    /// [Recorder::press_button] by the user `user_id`, e.g. in a group chat.
+    pub fn press_button_as(
+        &self,
+        chat_id: i64,
+        user_id: i64,
+        username: &str,
+        message_id: i32,
+        label: &str,
+    ) {
+        let data = self.callback_data(message_id, label);
+        let callback_id = self.next_id().to_string();
+        let message = serde_json::json!({
+            "message_id": message_id,
+            "date": 1,
+            "chat": Self::chat_json(chat_id, username),
+            "from": Self::me_json(),
+            "text": "",
+        });
+        self.push_update(serde_json::json!({
+            "callback_query": {
+                "id": callback_id,
+                "from": Self::user_json(user_id, username),
+                "message": message,
+                "chat_instance": chat_id.to_string(),
+                "data": data,
+            }
+        }));
+    }
+
+    /// Inject the bot being added to (`joined`) or removed from the group `chat_id` titled
+    /// `title` by the user `user_id` known as `username`.
+    pub fn set_membership(
+        &self,
+        chat_id: i64,
+        title: &str,
+        user_id: i64,
+        username: &str,
+        joined: bool,
+    ) {
+        let status = if joined { "member" } else { "left" };
+        let old_status = if joined { "left" } else { "member" };
+        self.push_update(serde_json::json!({
+            "my_chat_member": {
+                "chat": Self::chat_json(chat_id, title),
+                "from": Self::user_json(user_id, username),
+                "date": 1,
+                "old_chat_member": { "user": Self::me_json(), "status": old_status },
+                "new_chat_member": { "user": Self::me_json(), "status": status },
+            }
+        }));
+    }
+
+    /// Callback data of the button labelled `label` on the recorded message `message_id`, or
+    /// `label` itself if there is none.
+    fn callback_data(&self, message_id: i32, label: &str) -> String {
+        self.records()
+            .into_iter()
+            .find_map(|record| match record {
+                Record::SendMessage {
+                    message_id: id,
+                    reply_markup: Some(ReplyMarkup::InlineKeyboard(markup)),
+                    ..
+                }
+                | Record::SendAttachment {
+                    message_id: id,
+                    reply_markup: Some(ReplyMarkup::InlineKeyboard(markup)),
+                    ..
+                } if id == message_id => markup
+                    .inline_keyboard
+                    .into_iter()
+                    .flatten()
+                    .find(|button| button.text == label)
+                    .and_then(|button| match button.kind {
+                        InlineKeyboardButtonKind::CallbackData(data) => Some(data),
+                        _ => None,
+                    }),
+                _ => None,
+            })
+            .unwrap_or_else(|| label.to_string())
+    }
+
+    /// Fail the next call that sends a message with `err` instead of recording it. Failures queue
+    /// up if called repeatedly.
+    pub fn fail_next(&self, err: RequestError) {
+        self.inner.failures.lock().unwrap().push_back(err);
+    }
+
+    /// Take the next injected failure, if any.
+    fn failure(&self) -> Result<(), RequestError> {
+        match self.inner.failures.lock().unwrap().pop_front() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    fn next_id(&self) -> i32 {
+        self.inner.next_id.fetch_add(1, Ordering::SeqCst)
+    }
+
+    fn record(&self, record: Record) {
+        trace!("Recording {record:?}");
+        self.inner.records.lock().unwrap().push(record);
+        self.inner.notify.notify_waiters();
+    }
+
+    fn push_update(&self, mut update: serde_json::Value) {
+        update["update_id"] = self.next_id().into();
+
+        // NOTE: Safe to unwrap because the JSON is assembled above. [Update] has to go through
+        // a string since it does not deserialize properly from a [serde_json::Value]
+        let update = serde_json::from_str::<Update>(&update.to_string()).unwrap();
+        let _ = self.inner.update_sender.send(update);
+    }
+
+    fn user_json(id: i64, username: &str) -> serde_json::Value {
+        serde_json::json!({
+            "id": id,
+            "is_bot": false,
+            "first_name": username,
+            "username": username,
+        })
+    }
+
+    /// Private chat with `username`, or a group titled `username` if `id` is negative.
+    fn chat_json(id: i64, username: &str) -> serde_json::Value {
+        if id < 0 {
+            return serde_json::json!({ "id": id, "type": "group", "title": username });
+        }
+        serde_json::json!({
+            "id": id,
+            "type": "private",
+            "first_name": username,
+            "username": username,
+        })
+    }
+
+    fn me_json() -> serde_json::Value {
+        serde_json::json!({
+            "id": 1,
+            "is_bot": true,
+            "first_name": RECORDER_USERNAME,
+            "username": RECORDER_USERNAME,
+            "can_join_groups": true,
+            "can_read_all_group_messages": false,
+            "supports_inline_queries": false,
+        })
+    }
+}
+
+impl BauTransport for Recorder {
+    async fn send_message(
+        &self,
+        chat_id: i64,
+        text: String,
+        parse_mode: Option<ParseMode>,
+        reply_markup: Option<ReplyMarkup>,
+    ) -> Result<i32, RequestError> {
+        self.failure()?;
+        let message_id = self.next_id();
+        self.record(Record::SendMessage {
+            chat_id,
+            message_id,
+            text,
+            parse_mode,
+            reply_markup,
+        });
+        Ok(message_id)
+    }
+
+    async fn send_attachment(
+        &self,
+        chat_id: i64,
+        attachment: types::Attachment,
+        caption: Option<String>,
+        parse_mode: Option<ParseMode>,
+        reply_markup: Option<ReplyMarkup>,
+    ) -> Result<i32, RequestError> {
+        self.failure()?;
+        let message_id = self.next_id();
+        self.record(Record::SendAttachment {
+            chat_id,
+            message_id,
+            attachment,
+            caption,
+            parse_mode,
+            reply_markup,
+        });
+        Ok(message_id)
+    }
+
+    async fn send_media_group(
+        &self,
+        chat_id: i64,
+        attachments: Vec<types::Attachment>,
+    ) -> Result<Vec<i32>, RequestError> {
+        self.failure()?;
+        let message_ids = attachments
+            .iter()
+            .map(|_| self.next_id())
+            .collect::<Vec<_>>();
+        self.record(Record::SendMediaGroup {
+            chat_id,
+            message_ids: message_ids.clone(),
+            attachments,
+        });
+        Ok(message_ids)
+    }
+
+    async fn remove_markup(&self, chat_id: i64, message_id: i32) -> Result<(), RequestError> {
+        self.record(Record::RemoveMarkup {
+            chat_id,
+            message_id,
+        });
+        Ok(())
+    }
+
+    async fn edit_text(
+        &self,
+        chat_id: i64,
+        message_id: i32,
+        text: String,
+    ) -> Result<(), RequestError> {
+        self.record(Record::EditText {
+            chat_id,
+            message_id,
+            text,
+        });
+        Ok(())
+    }
+
+    async fn reply_message(
+        &self,
+        chat_id: i64,
+        message_id: i32,
+        text: String,
+    ) -> Result<i32, RequestError> {
+        let reply_id = self.next_id();
+        self.record(Record::ReplyMessage {
+            chat_id,
+            message_id: reply_id,
+            reply_to: message_id,
+            text,
+        });
+        Ok(reply_id)
+    }
+
+    async fn answer_callback(
+        &self,
+        callback_id: String,
+        text: Option<String>,
+        alert: bool,
+    ) -> Result<(), RequestError> {
+        self.record(Record::AnswerCallback {
+            callback_id,
+            text,
+            alert,
+        });
+        Ok(())
+    }
+
+    async fn dispatch(
+        self,
+        handler: UpdateHandler<HandlerError>,
+        mut dependencies: DependencyMap,
+        update_mode: UpdateMode,
+        shutdown: CancellationToken,
+    ) {
+        if let UpdateMode::Webhook(webhook) = update_mode {
+            warn!("Recorder ignores webhook {:?}", webhook.url);
+        }
+
+        // Only one dispatcher may drain the injected updates
+        let Some(mut update_receiver) = self.inner.update_receiver.lock().unwrap().take() else {
+            error!("Recorder is already dispatching");
+            return;
+        };
+
+        // NOTE: Safe to unwrap because the JSON is assembled above
+        let me = serde_json::from_value::<Me>(Self::me_json()).unwrap();
+        dependencies.insert(me);
+        dependencies.insert(self.clone());
+
+        loop {
+            let update = tokio::select! {
+                update = update_receiver.recv() => update,
+                _ = shutdown.cancelled() => None,
+            };
+            let Some(update) = update else {
+                break;
+            };
+
+            let update_id = update.id;
+            let mut dependencies = dependencies.clone();
+            dependencies.insert(update);
+
+            match handler.dispatch(dependencies).await {
+                std::ops::ControlFlow::Break(Err(err)) => error!("Handler error: {err:?}"),
+                std::ops::ControlFlow::Break(Ok(())) => {}
+                std::ops::ControlFlow::Continue(_) => warn!("Unhandled update: {update_id:?}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,1249 @@
+use baubot_core::config::BauBotBuilder;
+use baubot_core::config::RestorePolicy;
+use baubot_core::prelude::types::*;
+use baubot_core::prelude::BauData;
+use baubot_core::prelude::Command;
+use baubot_core::prelude::ParseMode;
+use baubot_core::prelude::ReplyMarkup;
+use baubot_core::transport::recorder::*;
+use baubot_core::BauBot;
+use baubot_data::test_db::TestDB;
+use baubot_utils::*;
+use std::sync::Arc;
+
+/// Build a [BauMessage] for [TEST_USER] with a single response sender attached.
+fn message(keyboard: Vec<Vec<String>>, timeout: u64) -> (BauMessage, BauResponseReceiver) {
+    let (sender, receiver) = tokio::sync::oneshot::channel();
+    let message = BauMessage {
+        id: None,
+        sender: TEST_USER.to_string(),
+        recipients: vec![(Recipient::User(TEST_USER.to_string()), Some(sender))],
+        message: "Approve?".to_string(),
+        format: None,
+        attachments: vec![],
+        responses: RequestedResponses {
+            timeout,
+            keyboard: keyboard
+                .into_iter()
+                .map(|row| row.into_iter().map(Button::from).collect())
+                .collect(),
+            quorum: Quorum::Independent,
+            free_text: false,
+        },
+        outcome: None,
+    };
+    (message, receiver)
+}
+
+/// Wait for the broadcast to [TEST_USER] and return its `message_id`
+async fn broadcast_id(recorder: &Recorder) -> i32 {
+    let chat_id = TEST_CHATID as i64;
+    match recorder
+        .wait_for(
+            |record| matches!(record, Record::SendMessage { chat_id: id, .. } if *id == chat_id),
+        )
+        .await
+    {
+        Record::SendMessage { message_id, .. } => message_id,
+        _ => unreachable!(),
+    }
+}
+
+#[tokio::test]
+async fn simple_message() {
+    baubot_utils::init();
+
+    let recorder = Recorder::new();
+    let baubot = BauBot::with_transport(Arc::new(TestDB::seed()), recorder.clone());
+
+    let (message, _) = message(vec![], 0);
+    baubot.send(message).await.unwrap();
+
+    let record = recorder
+        .wait_for(|record| matches!(record, Record::SendMessage { .. }))
+        .await;
+    info!("Broadcast: {record:#?}");
+    assert!(matches!(
+        record,
+        Record::SendMessage {
+            reply_markup: None,
+            ..
+        }
+    ));
+}
+
+#[tokio::test]
+async fn response_required() {
+    baubot_utils::init();
+
+    let recorder = Recorder::new();
+    let baubot = BauBot::with_transport(Arc::new(TestDB::seed()), recorder.clone());
+
+    let keyboard = vec![vec!["approve".to_string(), "deny".to_string()]];
+    let (message, receiver) = message(keyboard, 10000);
+    baubot.send(message).await.unwrap();
+
+    // Press approve
+    let message_id = broadcast_id(&recorder).await;
+    recorder.press_button(TEST_CHATID as i64, TEST_USER, message_id, "approve");
+
+    let response = receiver.await.unwrap();
+    info!("Received response: {response:#?}");
+    assert!(matches!(response, Ok(ref data) if data == "approve"));
+
+    // Keyboard must be stripped from the broadcast
+    recorder
+        .wait_for(|record| {
+            *record
+                == Record::RemoveMarkup {
+                    chat_id: TEST_CHATID as i64,
+                    message_id,
+                }
+        })
+        .await;
+    recorder
+        .wait_for(|record| matches!(record, Record::AnswerCallback { .. }))
+        .await;
+}
+
+#[tokio::test]
+async fn timeout() {
+    baubot_utils::init();
+
+    let recorder = Recorder::new();
+    let baubot = BauBot::with_transport(Arc::new(TestDB::seed()), recorder.clone());
+
+    let keyboard = vec![vec!["approve".to_string(), "deny".to_string()]];
+    let (message, receiver) = message(keyboard, 100);
+    baubot.send(message).await.unwrap();
+
+    let message_id = broadcast_id(&recorder).await;
+    let response = receiver.await.unwrap();
+    info!("Received response: {response:#?}");
+    assert!(matches!(response, Err(BauBotError::Timeout)));
+
+    // User is notified of the timeout
+    recorder
+        .wait_for(|record| matches!(record, Record::ReplyMessage { reply_to, .. } if *reply_to == message_id))
+        .await;
+}
+
+#[tokio::test]
+async fn register() {
+    baubot_utils::init();
+
+    let recorder = Recorder::new();
+    let db = Arc::new(TestDB::seed());
+    let _baubot = BauBot::with_transport(db.clone(), recorder.clone());
+
+    let message_id = recorder.send_text(42, "newcomer", "/start");
+    let record = recorder
+        .wait_for(|record| matches!(record, Record::ReplyMessage { reply_to, .. } if *reply_to == message_id))
+        .await;
+    info!("Reply: {record:#?}");
+
+    assert_eq!(db.get_chat_id("newcomer").await, Some(42));
+}
+
+#[tokio::test]
+async fn shutdown() {
+    baubot_utils::init();
+
+    let recorder = Recorder::new();
+    let baubot = BauBot::with_transport(Arc::new(TestDB::seed()), recorder.clone());
+
+    let keyboard = vec![vec!["approve".to_string(), "deny".to_string()]];
+    let (pending_message, receiver) = message(keyboard, 10000);
+    baubot.send(pending_message).await.unwrap();
+    let message_id = broadcast_id(&recorder).await;
+
+    baubot.shutdown().await;
+
+    // Pending request is resolved and its keyboard stripped
+    let response = receiver.await.unwrap();
+    assert!(matches!(response, Err(BauBotError::Shutdown)));
+    assert!(recorder.records().contains(&Record::RemoveMarkup {
+        chat_id: TEST_CHATID as i64,
+        message_id
+    }));
+
+    // New messages are refused
+    let (late_message, _) = message(vec![], 0);
+    assert!(baubot.send(late_message).await.is_err());
+}
+
+#[tokio::test]
+async fn builder() {
+    baubot_utils::init();
+
+    let recorder = Recorder::new();
+    let db = Arc::new(TestDB::seed());
+    let baubot = BauBotBuilder::new()
+        .parse_mode(ParseMode::Html)
+        .welcome_text("Welcome to the test bot.")
+        .catch_all_text("Say what?")
+        .commands([Command::Start, Command::Help])
+        .build_with_transport(db.clone(), recorder.clone())
+        .unwrap();
+
+    // Broadcasts use the configured parse mode
+    let (plain, _) = message(vec![], 0);
+    baubot.send(plain).await.unwrap();
+    let record = recorder
+        .wait_for(|record| matches!(record, Record::SendMessage { .. }))
+        .await;
+    assert!(matches!(
+        record,
+        Record::SendMessage {
+            parse_mode: Some(ParseMode::Html),
+            ..
+        }
+    ));
+
+    // Which means HTML is checked even without a format
+    let (mut malformed, receiver) = message(vec![], 0);
+    malformed.message = "<b>Approve?".to_string();
+    baubot.send(malformed).await.unwrap();
+    assert!(matches!(
+        receiver.await,
+        Ok(Err(BauBotError::Rejected { .. }))
+    ));
+    assert!(!recorder
+        .records()
+        .iter()
+        .any(|record| matches!(record, Record::SendMessage { text, .. } if text == "<b>Approve?")));
+
+    // Custom welcome text
+    let message_id = recorder.send_text(42, "newcomer", "/start");
+    let record = recorder
+        .wait_for(|record| matches!(record, Record::ReplyMessage { reply_to, .. } if *reply_to == message_id))
+        .await;
+    assert!(
+        matches!(record, Record::ReplyMessage { ref text, .. } if text.contains("Welcome to the test bot."))
+    );
+
+    // Disabled commands fall through to the catch-all
+    let message_id = recorder.send_text(42, "newcomer", "/unregister");
+    let record = recorder
+        .wait_for(|record| matches!(record, Record::ReplyMessage { reply_to, .. } if *reply_to == message_id))
+        .await;
+    assert!(matches!(record, Record::ReplyMessage { ref text, .. } if text == "Say what?"));
+    assert_eq!(db.get_chat_id("newcomer").await, Some(42));
+}
+
+#[tokio::test]
+async fn restore_resume() {
+    baubot_utils::init();
+
+    let db = Arc::new(TestDB::seed());
+    let recorder = Recorder::new();
+    let baubot = BauBotBuilder::new()
+        .restore_policy(RestorePolicy::Resume)
+        .build_with_transport(db.clone(), recorder.clone())
+        .unwrap();
+
+    let keyboard = vec![vec!["approve".to_string(), "deny".to_string()]];
+    let (mut pending_message, receiver) = message(keyboard, 10000);
+    pending_message.id = Some("request".to_string());
+    baubot.send(pending_message).await.unwrap();
+    let message_id = broadcast_id(&recorder).await;
+
+    // Keyboard is left alone on shutdown
+    baubot.shutdown().await;
+    assert!(matches!(receiver.await, Ok(Err(BauBotError::Shutdown))));
+    assert!(!recorder
+        .records()
+        .iter()
+        .any(|record| matches!(record, Record::RemoveMarkup { .. })));
+    drop(baubot);
+
+    // Restart and answer the old message
+    let recorder = Recorder::new();
+    let baubot = BauBotBuilder::new()
+        .restore_policy(RestorePolicy::Resume)
+        .build_with_transport(db.clone(), recorder.clone())
+        .unwrap();
+    recorder.press_button(TEST_CHATID as i64, TEST_USER, message_id, "approve");
+    recorder
+        .wait_for(|record| matches!(record, Record::RemoveMarkup { .. }))
+        .await;
+
+    let mut receivers = baubot.reclaim("request").await;
+    assert_eq!(receivers.len(), 1);
+    let (recipient, receiver) = receivers.pop().unwrap();
+    assert_eq!(recipient, TEST_USER);
+    assert!(matches!(receiver.await, Ok(Ok(ref data)) if data == "approve"));
+    assert!(baubot.reclaim("request").await.is_empty());
+    assert!(db.load_pending().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn restore_expire() {
+    baubot_utils::init();
+
+    let db = Arc::new(TestDB::seed());
+    let recorder = Recorder::new();
+    let baubot = BauBot::with_transport(db.clone(), recorder.clone());
+
+    let keyboard = vec![vec!["approve".to_string(), "deny".to_string()]];
+    let (pending_message, _receiver) = message(keyboard, 10000);
+    baubot.send(pending_message).await.unwrap();
+    let message_id = broadcast_id(&recorder).await;
+
+    // Crash without shutting down
+    drop(baubot);
+    assert_eq!(db.load_pending().await.unwrap().len(), 1);
+
+    // Old message is expired on restart
+    let recorder = Recorder::new();
+    let _baubot = BauBot::with_transport(db.clone(), recorder.clone());
+    recorder
+        .wait_for(|record| {
+            *record
+                == Record::RemoveMarkup {
+                    chat_id: TEST_CHATID as i64,
+                    message_id,
+                }
+        })
+        .await;
+    recorder
+        .wait_for(|record| matches!(record, Record::ReplyMessage { reply_to, .. } if *reply_to == message_id))
+        .await;
+    assert!(db.load_pending().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn quorum_first() {
+    baubot_utils::init();
+
+    let recorder = Recorder::new();
+    let db = Arc::new(TestDB::seed());
+    db.insert_chat_id("second", 42).await.unwrap();
+    let baubot = BauBot::with_transport(db.clone(), recorder.clone());
+
+    let keyboard = vec![vec!["approve".to_string(), "deny".to_string()]];
+    let (mut quorum_message, receiver) = message(keyboard, 10000);
+    let (second_sender, second_receiver) = tokio::sync::oneshot::channel();
+    let (outcome_sender, outcome_receiver) = tokio::sync::oneshot::channel();
+    quorum_message
+        .recipients
+        .push((Recipient::User("second".to_string()), Some(second_sender)));
+    quorum_message.responses.quorum = Quorum::First;
+    quorum_message.outcome = Some(outcome_sender);
+    baubot.send(quorum_message).await.unwrap();
+
+    // Wait for both broadcasts, then press approve as the first recipient
+    let message_id = broadcast_id(&recorder).await;
+    let second_id = match recorder
+        .wait_for(|record| matches!(record, Record::SendMessage { chat_id: 42, .. }))
+        .await
+    {
+        Record::SendMessage { message_id, .. } => message_id,
+        _ => unreachable!(),
+    };
+    recorder.press_button(TEST_CHATID as i64, TEST_USER, message_id, "approve");
+
+    assert!(matches!(receiver.await, Ok(Ok(ref data)) if data == "approve"));
+    assert!(matches!(
+        second_receiver.await,
+        Ok(Err(BauBotError::Superseded))
+    ));
+    assert_eq!(
+        outcome_receiver.await.unwrap(),
+        QuorumOutcome::Answered {
+            recipient: TEST_USER.to_string(),
+            response: "approve".to_string(),
+        }
+    );
+
+    // The other recipient's keyboard is retracted
+    recorder
+        .wait_for(|record| {
+            *record
+                == Record::RemoveMarkup {
+                    chat_id: 42,
+                    message_id: second_id,
+                }
+        })
+        .await;
+    recorder
+        .wait_for(|record| matches!(record, Record::ReplyMessage { reply_to, .. } if *reply_to == second_id))
+        .await;
+}
+
+#[tokio::test]
+async fn cancel() {
+    baubot_utils::init();
+
+    let recorder = Recorder::new();
+    let db = Arc::new(TestDB::seed());
+    let baubot = BauBot::with_transport(db.clone(), recorder.clone());
+
+    let keyboard = vec![vec!["approve".to_string(), "deny".to_string()]];
+    let (mut pending_message, receiver) = message(keyboard, 10000);
+    let id = pending_message.ensure_id().to_string();
+    baubot.send(pending_message).await.unwrap();
+    let message_id = broadcast_id(&recorder).await;
+
+    // Cancel without withdrawing: keyboard is stripped and the recipient is told
+    assert_eq!(baubot.cancel(&id, false).await, 1);
+    assert!(matches!(receiver.await, Ok(Err(BauBotError::Cancelled))));
+    let records = recorder.records();
+    assert!(records.contains(&Record::RemoveMarkup {
+        chat_id: TEST_CHATID as i64,
+        message_id
+    }));
+    assert!(records.iter().any(
+        |record| matches!(record, Record::ReplyMessage { reply_to, .. } if *reply_to == message_id)
+    ));
+    assert!(!records
+        .iter()
+        .any(|record| matches!(record, Record::EditText { .. })));
+    assert!(db.load_pending().await.unwrap().is_empty());
+
+    // Nothing left to cancel
+    assert_eq!(baubot.cancel(&id, false).await, 0);
+}
+
+#[tokio::test]
+async fn free_text() {
+    baubot_utils::init();
+
+    let recorder = Recorder::new();
+    let db = Arc::new(TestDB::seed());
+    let baubot = BauBot::with_transport(db.clone(), recorder.clone());
+
+    let (mut prompt, receiver) = message(vec![], 10000);
+    prompt.responses.free_text = true;
+    baubot.send(prompt).await.unwrap();
+
+    // Recipient is prompted for a reply
+    let record = recorder
+        .wait_for(|record| matches!(record, Record::SendMessage { .. }))
+        .await;
+    let Record::SendMessage {
+        message_id,
+        reply_markup: Some(ReplyMarkup::ForceReply(_)),
+        ..
+    } = record
+    else {
+        panic!("Unexpected broadcast: {record:?}");
+    };
+
+    // Unrelated text still falls through to the catch-all
+    let unrelated_id = recorder.send_text(TEST_CHATID as i64, TEST_USER, "hello?");
+    recorder
+        .wait_for(|record| matches!(record, Record::ReplyMessage { reply_to, .. } if *reply_to == unrelated_id))
+        .await;
+
+    // Someone else replying is turned away and the prompt stays pending
+    let intruder_id = recorder.reply_text(TEST_CHATID as i64, "intruder", message_id, "TICKET-0");
+    let record = recorder
+        .wait_for(|record| matches!(record, Record::ReplyMessage { reply_to, .. } if *reply_to == intruder_id))
+        .await;
+    assert!(
+        matches!(record, Record::ReplyMessage { ref text, .. } if text.contains(&format!("@{TEST_USER} only")))
+    );
+    assert_eq!(db.load_pending().await.unwrap().len(), 1);
+
+    let reply_id = recorder.reply_text(TEST_CHATID as i64, TEST_USER, message_id, "<TICKET-42>");
+    assert!(matches!(receiver.await, Ok(Ok(ref text)) if text == "<TICKET-42>"));
+
+    // Reply is acknowledged with the text escaped
+    let record = recorder
+        .wait_for(|record| matches!(record, Record::ReplyMessage { reply_to, .. } if *reply_to == reply_id))
+        .await;
+    assert!(
+        matches!(record, Record::ReplyMessage { ref text, .. } if text.contains("&lt;TICKET-42&gt;"))
+    );
+    assert!(db.load_pending().await.unwrap().is_empty());
+}
+
+/// [Attachment] named `filename` with a few inline bytes
+fn attachment(filename: &str, mime_type: &str) -> Attachment {
+    Attachment {
+        filename: filename.to_string(),
+        mime_type: mime_type.to_string(),
+        source: AttachmentSource::Base64("aGVsbG8=".to_string()),
+    }
+}
+
+#[tokio::test]
+async fn attachments() {
+    baubot_utils::init();
+
+    let recorder = Recorder::new();
+    let baubot = BauBot::with_transport(Arc::new(TestDB::seed()), recorder.clone());
+    let chat_id = TEST_CHATID as i64;
+    let keyboard = vec![vec!["approve".to_string(), "deny".to_string()]];
+
+    // A single attachment carries the message and the keyboard
+    let (mut single_message, receiver) = message(keyboard.clone(), 10000);
+    single_message.attachments = vec![attachment("scan.png", "image/png")];
+    baubot.send(single_message).await.unwrap();
+    let record = recorder
+        .wait_for(|record| matches!(record, Record::SendAttachment { .. }))
+        .await;
+    let Record::SendAttachment {
+        message_id,
+        caption: Some(ref caption),
+        reply_markup: Some(ReplyMarkup::InlineKeyboard(_)),
+        ..
+    } = record
+    else {
+        panic!("Unexpected broadcast: {record:?}");
+    };
+    assert_eq!(caption, "Approve?");
+    recorder.press_button(chat_id, TEST_USER, message_id, "approve");
+    assert!(matches!(receiver.await, Ok(Ok(ref data)) if data == "approve"));
+
+    // Several attachments are sent as albums ahead of the message
+    let (mut album_message, _receiver) = message(keyboard, 10000);
+    album_message.attachments = vec![
+        attachment("first.png", "image/png"),
+        attachment("report.pdf", "application/pdf"),
+        attachment("second.jpg", "image/jpeg"),
+    ];
+    baubot.send(album_message).await.unwrap();
+    broadcast_id(&recorder).await;
+
+    let records = recorder.records();
+    let album = records
+        .iter()
+        .position(|record| matches!(record, Record::SendMediaGroup { attachments, .. } if attachments.len() == 2))
+        .unwrap();
+    let document = records
+        .iter()
+        .position(|record| matches!(record, Record::SendAttachment { attachment, caption: None, .. } if attachment.filename == "report.pdf"))
+        .unwrap();
+    let text = records
+        .iter()
+        .position(|record| {
+            matches!(
+                record,
+                Record::SendMessage {
+                    reply_markup: Some(_),
+                    ..
+                }
+            )
+        })
+        .unwrap();
+    assert!(album < document && document < text);
+
+    // An empty message without a keyboard is not sent after the album
+    let (mut empty_message, receiver) = message(vec![], 0);
+    empty_message.message = String::new();
+    empty_message.attachments = vec![
+        attachment("third.png", "image/png"),
+        attachment("fourth.png", "image/png"),
+    ];
+    baubot.send(empty_message).await.unwrap();
+    // Dropped without a response once delivered
+    assert!(receiver.await.is_err());
+    let records = recorder.records();
+    assert!(records.iter().any(
+        |record| matches!(record, Record::SendMediaGroup { attachments, .. } if attachments[0].filename == "third.png")
+    ));
+    assert!(!records
+        .iter()
+        .any(|record| matches!(record, Record::SendMessage { text, .. } if text.is_empty())));
+
+    // Local files are only sent from within the attachment root, which is not set
+    let (mut path_message, receiver) = message(vec![], 0);
+    path_message.attachments = vec![Attachment {
+        filename: "passwd".to_string(),
+        mime_type: "text/plain".to_string(),
+        source: AttachmentSource::Path("/etc/passwd".into()),
+    }];
+    baubot.send(path_message).await.unwrap();
+    assert!(matches!(
+        receiver.await,
+        Ok(Err(BauBotError::Rejected { .. }))
+    ));
+    assert!(!recorder.records().iter().any(
+        |record| matches!(record, Record::SendAttachment { attachment, .. } if attachment.filename == "passwd")
+    ));
+}
+
+#[tokio::test]
+async fn message_format() {
+    baubot_utils::init();
+
+    let recorder = Recorder::new();
+    let baubot = BauBot::with_transport(Arc::new(TestDB::seed()), recorder.clone());
+
+    // Plain text is escaped and sent as HTML
+    let (mut plain_message, _receiver) = message(vec![], 10000);
+    plain_message.message = "1 < 2 & <b>not bold</b>".to_string();
+    plain_message.format = Some(MessageFormat::Plain);
+    baubot.send(plain_message).await.unwrap();
+    let record = recorder
+        .wait_for(|record| matches!(record, Record::SendMessage { .. }))
+        .await;
+    assert!(matches!(
+        record,
+        Record::SendMessage {
+            ref text,
+            parse_mode: Some(ParseMode::Html),
+            ..
+        } if text == "1 &lt; 2 &amp; &lt;b&gt;not bold&lt;/b&gt;"
+    ));
+
+    // Markdown is passed through untouched
+    let (mut markdown_message, _receiver) = message(vec![], 10000);
+    markdown_message.message = "*bold*".to_string();
+    markdown_message.format = Some(MessageFormat::MarkdownV2);
+    baubot.send(markdown_message).await.unwrap();
+    let record = recorder
+        .wait_for(|record| {
+            matches!(
+                record,
+                Record::SendMessage {
+                    parse_mode: Some(ParseMode::MarkdownV2),
+                    ..
+                }
+            )
+        })
+        .await;
+    assert!(matches!(record, Record::SendMessage { ref text, .. } if text == "*bold*"));
+}
+
+#[tokio::test]
+async fn long_message() {
+    baubot_utils::init();
+
+    let recorder = Recorder::new();
+    let baubot = BauBot::with_transport(Arc::new(TestDB::seed()), recorder.clone());
+    let chat_id = TEST_CHATID as i64;
+
+    let (mut long_message, receiver) = message(vec![vec!["ack".to_string()]], 10000);
+    long_message.message = "word ".repeat(1000);
+    baubot.send(long_message).await.unwrap();
+
+    // Only the last part carries the keyboard
+    let record = recorder
+        .wait_for(|record| {
+            matches!(
+                record,
+                Record::SendMessage {
+                    reply_markup: Some(_),
+                    ..
+                }
+            )
+        })
+        .await;
+    let Record::SendMessage { message_id, .. } = record else {
+        panic!("Unexpected broadcast: {record:?}");
+    };
+    let parts = recorder
+        .records()
+        .into_iter()
+        .filter_map(|record| match record {
+            Record::SendMessage { text, .. } => Some(text),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(parts.len(), 2);
+    assert!(parts.iter().all(|part| part.chars().count() <= 4096));
+    assert_eq!(parts.concat(), "word ".repeat(1000));
+
+    // The second part had to wait for the per-chat rate limit
+    let metrics = baubot.metrics();
+    assert_eq!((metrics.sent, metrics.throttled), (2, 1));
+
+    // Responses are keyed on the last part
+    recorder.press_button(chat_id, TEST_USER, message_id, "ack");
+    assert!(matches!(receiver.await, Ok(Ok(ref data)) if data == "ack"));
+}
+
+#[tokio::test]
+async fn retry() {
+    baubot_utils::init();
+
+    let recorder = Recorder::new();
+    let baubot = BauBot::with_transport(Arc::new(TestDB::seed()), recorder.clone());
+    let keyboard = vec![vec!["ack".to_string()]];
+
+    // Flood control is waited out
+    recorder.fail_next(RequestError::RetryAfter(Seconds::from_seconds(1)));
+    let (retried_message, receiver) = message(keyboard.clone(), 10000);
+    baubot.send(retried_message).await.unwrap();
+    let message_id = broadcast_id(&recorder).await;
+    recorder.press_button(TEST_CHATID as i64, TEST_USER, message_id, "ack");
+    assert!(matches!(receiver.await, Ok(Ok(ref data)) if data == "ack"));
+
+    // A blocked bot is given up on straight away
+    recorder.fail_next(RequestError::Api(ApiError::BotBlocked));
+    let (blocked_message, receiver) = message(keyboard, 10000);
+    baubot.send(blocked_message).await.unwrap();
+    assert!(matches!(
+        receiver.await,
+        Ok(Err(BauBotError::Blocked { .. }))
+    ));
+    let broadcasts = recorder
+        .records()
+        .into_iter()
+        .filter(|record| matches!(record, Record::SendMessage { .. }))
+        .count();
+    assert_eq!(broadcasts, 1);
+}
+
+#[tokio::test]
+async fn bounded_queue() {
+    baubot_utils::init();
+
+    let recorder = Recorder::new();
+    let baubot = BauBotBuilder::new()
+        .queue_capacity(1)
+        .concurrency(1)
+        .build_with_transport(Arc::new(TestDB::seed()), recorder.clone())
+        .unwrap();
+
+    // The second message waits a second for the per-chat rate limit, holding up the third in the
+    // queue
+    for _ in 0..3 {
+        let (queued_message, _) = message(vec![], 0);
+        baubot.send(queued_message).await.unwrap();
+    }
+    let (busy_message, _) = message(vec![], 0);
+    assert!(matches!(
+        baubot.try_send(busy_message),
+        Err(tokio::sync::mpsc::error::TrySendError::Full(_))
+    ));
+
+    // The queue drains eventually
+    let (late_message, _) = message(vec![], 0);
+    baubot.send(late_message).await.unwrap();
+}
+
+#[tokio::test]
+async fn concurrent_delivery() {
+    baubot_utils::init();
+
+    let recorder = Recorder::new();
+    let db = Arc::new(TestDB::seed());
+    db.insert_chat_id("other", 2000).await.unwrap();
+    let baubot = BauBot::with_transport(db, recorder.clone());
+    let chat_id = TEST_CHATID as i64;
+
+    for text in ["first", "second"] {
+        let (mut ordered_message, _) = message(vec![], 0);
+        ordered_message.message = text.to_string();
+        baubot.send(ordered_message).await.unwrap();
+    }
+    let (mut other_message, _) = message(vec![], 0);
+    other_message.recipients = vec![(Recipient::User("other".to_string()), None)];
+    baubot.send(other_message).await.unwrap();
+
+    // The second message waits a second for the per-chat rate limit without holding up the other
+    // recipient
+    recorder
+        .wait_for(|record| matches!(record, Record::SendMessage { chat_id: 2000, .. }))
+        .await;
+    let texts = |recorder: &Recorder| {
+        recorder
+            .records()
+            .into_iter()
+            .filter_map(|record| match record {
+                Record::SendMessage {
+                    chat_id: id, text, ..
+                } if id == chat_id => Some(text),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(texts(&recorder), vec!["first"]);
+
+    // Messages to the same recipient keep their order
+    recorder
+        .wait_for(|record| matches!(record, Record::SendMessage { text, .. } if text == "second"))
+        .await;
+    assert_eq!(texts(&recorder), vec!["first", "second"]);
+}
+
+#[tokio::test]
+async fn recipient_errors() {
+    baubot_utils::init();
+
+    let recorder = Recorder::new();
+    let baubot = BauBot::with_transport(Arc::new(TestDB::seed()), recorder.clone());
+
+    // Recipients that never registered are told apart, even if no response is required
+    let (mut unregistered_message, _) = message(vec![], 0);
+    let (sender, receiver) = tokio::sync::oneshot::channel();
+    unregistered_message.recipients = vec![(Recipient::User("stranger".to_string()), Some(sender))];
+    baubot.send(unregistered_message).await.unwrap();
+    assert!(matches!(receiver.await, Ok(Err(BauBotError::Unregistered))));
+
+    // Telegram's reason for refusing the message is passed on
+    recorder.fail_next(RequestError::Api(ApiError::CantParseEntities(
+        "Bad Request: can't parse entities".to_string(),
+    )));
+    let (rejected_message, receiver) = message(vec![], 0);
+    baubot.send(rejected_message).await.unwrap();
+    assert!(matches!(
+        receiver.await,
+        Ok(Err(BauBotError::Rejected { ref description }))
+            if description == "Bad Request: can't parse entities"
+    ));
+}
+
+#[tokio::test]
+async fn labelled_buttons() {
+    baubot_utils::init();
+
+    let recorder = Recorder::new();
+    let baubot = BauBot::with_transport(Arc::new(TestDB::seed()), recorder.clone());
+    let chat_id = TEST_CHATID as i64;
+
+    // Values longer than telegram's 64 bytes of callback data
+    let value = "approved by the change advisory board ".repeat(4);
+    let (mut labelled_message, receiver) = message(vec![], 10000);
+    labelled_message.responses.keyboard = vec![vec![
+        Button::Labelled {
+            label: "Approve".to_string(),
+            value: value.clone(),
+        },
+        "deny".into(),
+    ]];
+    baubot.send(labelled_message).await.unwrap();
+
+    let record = recorder
+        .wait_for(|record| matches!(record, Record::SendMessage { .. }))
+        .await;
+    let Record::SendMessage {
+        message_id,
+        reply_markup: Some(ReplyMarkup::InlineKeyboard(markup)),
+        ..
+    } = record
+    else {
+        panic!("Unexpected broadcast: {record:?}");
+    };
+    let labels = markup.inline_keyboard[0]
+        .iter()
+        .map(|button| button.text.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(labels, vec!["Approve", "deny"]);
+
+    recorder.press_button(chat_id, TEST_USER, message_id, "Approve");
+    assert!(matches!(receiver.await, Ok(Ok(ref data)) if *data == value));
+}
+
+#[tokio::test]
+async fn intruder() {
+    baubot_utils::init();
+
+    let recorder = Recorder::new();
+    let db = Arc::new(TestDB::seed());
+    let baubot = BauBot::with_transport(db.clone(), recorder.clone());
+    let chat_id = TEST_CHATID as i64;
+
+    let keyboard = vec![vec!["approve".to_string(), "deny".to_string()]];
+    let (pending_message, receiver) = message(keyboard.clone(), 10000);
+    baubot.send(pending_message).await.unwrap();
+    let message_id = broadcast_id(&recorder).await;
+
+    // Someone else in the chat is turned away with an alert
+    recorder.press_button(chat_id, "intruder", message_id, "approve");
+    let record = recorder
+        .wait_for(|record| matches!(record, Record::AnswerCallback { .. }))
+        .await;
+    assert!(matches!(record, Record::AnswerCallback { alert: true, .. }));
+    assert!(!recorder
+        .records()
+        .iter()
+        .any(|record| matches!(record, Record::RemoveMarkup { .. })));
+
+    // The recipient can still respond
+    recorder.press_button(chat_id, TEST_USER, message_id, "deny");
+    assert!(matches!(receiver.await, Ok(Ok(ref data)) if data == "deny"));
+
+    // Once their telegram user id is known, the recipient is recognised by it rather than by
+    // their username
+    db.bind_user_id(TEST_USER, 7, "user-7").await.unwrap();
+    let (pending_message, receiver) = message(keyboard, 10000);
+    baubot.send(pending_message).await.unwrap();
+    let record = recorder
+        .wait_for(|record| matches!(record, Record::SendMessage { message_id: id, .. } if *id != message_id))
+        .await;
+    let Record::SendMessage { message_id, .. } = record else {
+        unreachable!();
+    };
+    recorder.press_button_as(chat_id, 8, TEST_USER, message_id, "approve");
+    let alerts = |records: Vec<Record>| {
+        records
+            .iter()
+            .filter(|record| matches!(record, Record::AnswerCallback { alert: true, .. }))
+            .count()
+    };
+    recorder.wait_for(|_| alerts(recorder.records()) == 2).await;
+    recorder.press_button_as(chat_id, 7, "renamed", message_id, "deny");
+    assert!(matches!(receiver.await, Ok(Ok(ref data)) if data == "deny"));
+}
+
+#[tokio::test]
+async fn chat_recipients() {
+    baubot_utils::init();
+
+    let recorder = Recorder::new();
+    let db = Arc::new(TestDB::seed());
+    let baubot = BauBot::with_transport(db.clone(), recorder.clone());
+    let group_id = -5000;
+
+    // The bot is added to a group, which is then known by its alias
+    recorder.set_membership(group_id, "Ops Team", TEST_CHATID as i64, TEST_USER, true);
+    while db.get_group_chat_id("ops-team").await.is_none() {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+
+    // Groups and raw chat_ids are sent to as is, and anyone in them may respond
+    let keyboard = vec![vec!["ack".to_string()]];
+    let (mut group_message, receiver) = message(keyboard, 10000);
+    group_message.recipients[0].0 = Recipient::Group("ops-team".to_string());
+    group_message
+        .recipients
+        .push((Recipient::Chat(-6000), None));
+    baubot.send(group_message).await.unwrap();
+    let record = recorder
+        .wait_for(
+            |record| matches!(record, Record::SendMessage { chat_id, .. } if *chat_id == group_id),
+        )
+        .await;
+    let Record::SendMessage { message_id, .. } = record else {
+        unreachable!();
+    };
+    recorder
+        .wait_for(|record| matches!(record, Record::SendMessage { chat_id: -6000, .. }))
+        .await;
+    recorder.press_button_as(group_id, 2000, "colleague", message_id, "ack");
+    assert!(matches!(receiver.await, Ok(Ok(ref data)) if data == "ack"));
+
+    // Once removed from the group, its alias is forgotten
+    recorder.set_membership(group_id, "Ops Team", TEST_CHATID as i64, TEST_USER, false);
+    while db.get_group_chat_id("ops-team").await.is_some() {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    let (mut group_message, receiver) = message(vec![], 0);
+    group_message.recipients[0].0 = Recipient::Group("ops-team".to_string());
+    baubot.send(group_message).await.unwrap();
+    assert!(matches!(receiver.await, Ok(Err(BauBotError::Unregistered))));
+}
+
+#[tokio::test]
+async fn roles() {
+    baubot_utils::init();
+
+    let recorder = Recorder::new();
+    let db = Arc::new(TestDB::seed());
+    let baubot = BauBot::with_transport(db.clone(), recorder.clone());
+    db.insert_chat_id("second", 2000).await.unwrap();
+
+    // Admins manage the members of a role
+    let message_id = recorder.send_text(TEST_CHATID as i64, TEST_USER, "/addrole oncall @second");
+    let record = recorder
+        .wait_for(|record| matches!(record, Record::ReplyMessage { reply_to, .. } if *reply_to == message_id))
+        .await;
+    assert!(
+        matches!(record, Record::ReplyMessage { ref text, .. } if text.contains("Added second"))
+    );
+    db.add_role_member("oncall", TEST_USER).await.unwrap();
+    let message_id = recorder.send_text(TEST_CHATID as i64, TEST_USER, "/roles");
+    let record = recorder
+        .wait_for(|record| matches!(record, Record::ReplyMessage { reply_to, .. } if *reply_to == message_id))
+        .await;
+    assert!(
+        matches!(record, Record::ReplyMessage { ref text, .. } if text.contains(&format!("%oncall</code> — second, {TEST_USER}")))
+    );
+
+    // Every member is asked and the first response is given for the role
+    let keyboard = vec![vec!["ack".to_string()]];
+    let (mut role_message, receiver) = message(keyboard, 10000);
+    role_message.recipients[0].0 = Recipient::Role("oncall".to_string());
+    baubot.send(role_message).await.unwrap();
+    let record = recorder
+        .wait_for(|record| matches!(record, Record::SendMessage { chat_id, .. } if *chat_id == TEST_CHATID as i64))
+        .await;
+    let Record::SendMessage {
+        message_id: other_id,
+        ..
+    } = record
+    else {
+        unreachable!();
+    };
+    let record = recorder
+        .wait_for(|record| matches!(record, Record::SendMessage { chat_id: 2000, .. }))
+        .await;
+    let Record::SendMessage { message_id, .. } = record else {
+        unreachable!();
+    };
+    recorder.press_button(2000, "second", message_id, "ack");
+    assert!(matches!(receiver.await, Ok(Ok(ref data)) if data == "ack"));
+
+    // The other members no longer have to respond
+    recorder
+        .wait_for(|record| matches!(record, Record::RemoveMarkup { message_id, .. } if *message_id == other_id))
+        .await;
+    let record = recorder
+        .wait_for(|record| matches!(record, Record::ReplyMessage { reply_to, .. } if *reply_to == other_id))
+        .await;
+    assert!(
+        matches!(record, Record::ReplyMessage { ref text, .. } if text.contains("Another member"))
+    );
+    assert!(db.load_pending().await.unwrap().is_empty());
+
+    // Roles without members cannot be reached
+    let message_id = recorder.send_text(TEST_CHATID as i64, TEST_USER, "/removerole oncall second");
+    recorder
+        .wait_for(|record| matches!(record, Record::ReplyMessage { reply_to, .. } if *reply_to == message_id))
+        .await;
+    assert_eq!(
+        db.get_role_members("oncall").await,
+        vec![TEST_USER.to_string()]
+    );
+    let (mut role_message, receiver) = message(vec![], 0);
+    role_message.recipients[0].0 = Recipient::Role("nobody".to_string());
+    baubot.send(role_message).await.unwrap();
+    assert!(matches!(receiver.await, Ok(Err(BauBotError::Unregistered))));
+
+    // Replies are HTML, so whatever the admin typed is escaped
+    let message_id = recorder.send_text(TEST_CHATID as i64, TEST_USER, "/addrole a<b bob");
+    let record = recorder
+        .wait_for(|record| matches!(record, Record::ReplyMessage { reply_to, .. } if *reply_to == message_id))
+        .await;
+    assert!(
+        matches!(record, Record::ReplyMessage { ref text, .. } if text.contains("Added bob to <code>%a&lt;b</code>"))
+    );
+    assert_eq!(db.get_role_members("a<b").await, vec!["bob".to_string()]);
+}
+
+#[tokio::test]
+async fn admin_commands() {
+    baubot_utils::init();
+
+    let recorder = Recorder::new();
+    let db = Arc::new(TestDB::seed());
+    let _baubot = BauBot::with_transport(db.clone(), recorder.clone());
+    db.insert_chat_id("second", 2000).await.unwrap();
+    let reply = |chat_id: i64, username: &str, text: &str| {
+        let recorder = recorder.clone();
+        let message_id = recorder.send_text(chat_id, username, text);
+        async move {
+            let record = recorder
+                .wait_for(|record| matches!(record, Record::ReplyMessage { reply_to, .. } if *reply_to == message_id))
+                .await;
+            let Record::ReplyMessage { text, .. } = record else {
+                unreachable!();
+            };
+            text
+        }
+    };
+
+    // Regular users neither see nor run admin commands
+    assert!(!reply(2000, "second", "/help").await.contains("/broadcast"));
+    assert!(!reply(2000, "second", "/stats").await.contains("Users:"));
+    assert!(reply(TEST_CHATID as i64, TEST_USER, "/help")
+        .await
+        .contains("/broadcast"));
+
+    // Admins list users, broadcast to them and look at the statistics
+    assert!(reply(TEST_CHATID as i64, TEST_USER, "/users")
+        .await
+        .contains("second — <code>2000</code>"));
+    assert!(reply(
+        TEST_CHATID as i64,
+        TEST_USER,
+        "/broadcast maintenance tonight"
+    )
+    .await
+    .contains("Broadcasting to 2 users"));
+    recorder
+        .wait_for(|record| matches!(record, Record::SendMessage { chat_id: 2000, text, .. } if text == "maintenance tonight"))
+        .await;
+    assert!(reply(TEST_CHATID as i64, TEST_USER, "/stats")
+        .await
+        .contains("Users: 2"));
+
+    // And remove users
+    reply(TEST_CHATID as i64, TEST_USER, "/removeuser @second").await;
+    assert_eq!(db.get_chat_id("second").await, None);
+}
+
+#[tokio::test]
+async fn approve_registrations() {
+    baubot_utils::init();
+
+    let recorder = Recorder::new();
+    let db = Arc::new(TestDB::seed());
+    let _baubot = BauBotBuilder::new()
+        .approve_registrations(10000)
+        .build_with_transport(db.clone(), recorder.clone())
+        .unwrap();
+    let admin_request = |after: i32| {
+        let recorder = recorder.clone();
+        async move {
+            let record = recorder
+                .wait_for(|record| {
+                    matches!(record, Record::SendMessage { chat_id, message_id, reply_markup: Some(_), .. } if *chat_id == TEST_CHATID as i64 && *message_id > after)
+                })
+                .await;
+            let Record::SendMessage { message_id, .. } = record else {
+                unreachable!();
+            };
+            message_id
+        }
+    };
+
+    // New users wait for an admin
+    let message_id = recorder.send_text(42, "newcomer", "/start");
+    let record = recorder
+        .wait_for(|record| matches!(record, Record::ReplyMessage { reply_to, .. } if *reply_to == message_id))
+        .await;
+    assert!(matches!(record, Record::ReplyMessage { ref text, .. } if text.contains("waiting")));
+    assert_eq!(db.get_chat_id("newcomer").await, None);
+
+    // And are registered once approved
+    let approved_id = admin_request(0).await;
+    recorder.press_button(TEST_CHATID as i64, TEST_USER, approved_id, "✅ Approve");
+    recorder
+        .wait_for(|record| matches!(record, Record::SendMessage { chat_id: 42, text, .. } if text.contains("approved")))
+        .await;
+    assert_eq!(db.get_chat_id("newcomer").await, Some(42));
+
+    // Or turned away
+    recorder.send_text(43, "stranger", "/start");
+    let message_id = admin_request(approved_id).await;
+    recorder.press_button(TEST_CHATID as i64, TEST_USER, message_id, "❌ Reject");
+    recorder
+        .wait_for(|record| matches!(record, Record::SendMessage { chat_id: 43, text, .. } if text.contains("rejected")))
+        .await;
+    assert_eq!(db.get_chat_id("stranger").await, None);
+
+    // Repeating `/start` right away does not ask the admins again
+    recorder.send_text(44, "eager", "/start");
+    let message_id = recorder.send_text(44, "eager", "/start");
+    let record = recorder
+        .wait_for(|record| matches!(record, Record::ReplyMessage { reply_to, .. } if *reply_to == message_id))
+        .await;
+    assert!(
+        matches!(record, Record::ReplyMessage { ref text, .. } if text.contains("already waiting"))
+    );
+    admin_request(message_id).await;
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    let prompts = recorder
+        .records()
+        .into_iter()
+        .filter(|record| {
+            matches!(record, Record::SendMessage { text, reply_markup: Some(_), .. } if text.contains("@eager"))
+        })
+        .count();
+    assert_eq!(prompts, 1);
+}
+
+#[tokio::test]
+async fn approval_restore() {
+    baubot_utils::init();
+
+    let db = Arc::new(TestDB::seed());
+    let recorder = Recorder::new();
+    let baubot = BauBotBuilder::new()
+        .approve_registrations(10000)
+        .restore_policy(RestorePolicy::Resume)
+        .build_with_transport(db.clone(), recorder.clone())
+        .unwrap();
+    recorder.send_text(42, "newcomer", "/start");
+    let message_id = broadcast_id(&recorder).await;
+    while db.load_pending().await.unwrap().is_empty() {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    drop(baubot);
+
+    // Nobody is left to act on the decision, so the approval expires even when resuming
+    let recorder = Recorder::new();
+    let _baubot = BauBotBuilder::new()
+        .approve_registrations(10000)
+        .restore_policy(RestorePolicy::Resume)
+        .build_with_transport(db.clone(), recorder.clone())
+        .unwrap();
+    recorder
+        .wait_for(|record| matches!(record, Record::RemoveMarkup { message_id: id, .. } if *id == message_id))
+        .await;
+    assert!(db.load_pending().await.unwrap().is_empty());
+
+    // And the user may ask again
+    let message_id = recorder.send_text(42, "newcomer", "/start");
+    let record = recorder
+        .wait_for(|record| matches!(record, Record::ReplyMessage { reply_to, .. } if *reply_to == message_id))
+        .await;
+    assert!(
+        matches!(record, Record::ReplyMessage { ref text, .. } if text.contains("waiting for the approval"))
+    );
+}
+
+#[tokio::test]
+async fn invites() {
+    baubot_utils::init();
+
+    let recorder = Recorder::new();
+    let db = Arc::new(TestDB::seed());
+    let baubot = BauBotBuilder::new()
+        .approve_registrations(10000)
+        .build_with_transport(db.clone(), recorder.clone())
+        .unwrap();
+    let reply = |chat_id: i64, username: &str, text: &str| {
+        let recorder = recorder.clone();
+        let message_id = recorder.send_text(chat_id, username, text);
+        async move {
+            let record = recorder
+                .wait_for(|record| matches!(record, Record::ReplyMessage { reply_to, .. } if *reply_to == message_id))
+                .await;
+            let Record::ReplyMessage { text, .. } = record else {
+                unreachable!();
+            };
+            text
+        }
+    };
+
+    // Invited users are registered straight away and bound to their id in the application
+    let token = baubot
+        .invite("user-42", std::time::Duration::from_secs(60))
+        .await
+        .unwrap();
+    assert!(token
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-'));
+    assert!(reply(42, "invited", &format!("/start {token}"))
+        .await
+        .contains("Registered!"));
+    assert_eq!(db.get_chat_id("invited").await, Some(42));
+    assert_eq!(db.get_user_id("invited").await, Some("user-42".to_string()));
+    assert_eq!(db.get_telegram_id("invited").await, Some(42));
+
+    // Invites are used once
+    assert!(reply(43, "freeloader", &format!("/start {token}"))
+        .await
+        .contains("already been used"));
+    assert_eq!(db.get_chat_id("freeloader").await, None);
+
+    // The command may name the bot, as in group chats
+    let token = baubot
+        .invite("user-45", std::time::Duration::from_secs(60))
+        .await
+        .unwrap();
+    assert!(
+        reply(45, "named", &format!("/start@{RECORDER_USERNAME} {token}"))
+            .await
+            .contains("Registered!")
+    );
+    assert_eq!(db.get_user_id("named").await, Some("user-45".to_string()));
+
+    // Invites expire
+    let token = baubot
+        .invite("user-44", std::time::Duration::ZERO)
+        .await
+        .unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    assert!(reply(44, "latecomer", &format!("/start {token}"))
+        .await
+        .contains("expired"));
+    assert_eq!(db.get_chat_id("latecomer").await, None);
+}
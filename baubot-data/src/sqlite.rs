@@ -0,0 +1,729 @@
+//! [BauData] backed by SQLite.
+
+use crate::*;
+use rusqlite::params;
+use rusqlite::Connection;
+use rusqlite::OptionalExtension;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+/// Schema migrations. Each entry moves the database from `user_version` `n` to `n + 1`; add new
+/// entries to the end and never edit an entry that has been released.
+const MIGRATIONS: &[&str] = &[
+    // 1: users
+    "CREATE TABLE users (
+        username TEXT PRIMARY KEY NOT NULL,
+        chat_id INTEGER,
+        is_admin INTEGER NOT NULL DEFAULT 0
+    );",
+    // 2: pending requests
+    "CREATE TABLE pending (
+        chat_id INTEGER NOT NULL,
+        message_id INTEGER NOT NULL,
+        request_id TEXT,
+        recipient TEXT NOT NULL,
+        deadline INTEGER NOT NULL,
+        timeout INTEGER NOT NULL,
+        PRIMARY KEY (chat_id, message_id)
+    );",
+    // 3: keyboard values of pending requests, as a JSON list
+    "ALTER TABLE pending ADD COLUMN buttons TEXT NOT NULL DEFAULT '[]';",
+    // 4: groups and channels the bot is in
+    "CREATE TABLE chats (
+        chat_id INTEGER PRIMARY KEY NOT NULL,
+        alias TEXT NOT NULL,
+        title TEXT NOT NULL
+    );",
+    // 5: members of recipient roles
+    "CREATE TABLE roles (
+        role TEXT NOT NULL,
+        username TEXT NOT NULL,
+        PRIMARY KEY (role, username)
+    );",
+    // 6: invite deep links
+    "CREATE TABLE invites (
+        token TEXT PRIMARY KEY NOT NULL,
+        user_id TEXT NOT NULL,
+        deadline INTEGER NOT NULL
+    );",
+    // 7: application-level user ids bound through invites
+    "ALTER TABLE users ADD COLUMN user_id TEXT;",
+    // 8: usernames are case insensitive, as they are on telegram. Users that only differ in case
+    // are merged into the registered one.
+    "CREATE TABLE users_nocase (
+        username TEXT PRIMARY KEY NOT NULL COLLATE NOCASE,
+        chat_id INTEGER,
+        is_admin INTEGER NOT NULL DEFAULT 0,
+        user_id TEXT
+    );
+    INSERT INTO users_nocase (username, chat_id, is_admin, user_id)
+        SELECT username, chat_id, is_admin, user_id FROM users WHERE true ORDER BY chat_id IS NULL
+        ON CONFLICT (username) DO UPDATE SET
+            is_admin = max(is_admin, excluded.is_admin),
+            user_id = coalesce(user_id, excluded.user_id);
+    DROP TABLE users;
+    ALTER TABLE users_nocase RENAME TO users;
+    CREATE TABLE roles_nocase (
+        role TEXT NOT NULL,
+        username TEXT NOT NULL COLLATE NOCASE,
+        PRIMARY KEY (role, username)
+    );
+    INSERT OR IGNORE INTO roles_nocase (role, username) SELECT role, username FROM roles;
+    DROP TABLE roles;
+    ALTER TABLE roles_nocase RENAME TO roles;",
+    // 9: telegram user ids bound through invites, which survive a change of username
+    "ALTER TABLE users ADD COLUMN telegram_id INTEGER;",
+    // 10: telegram user ids of the recipients of pending requests, where known
+    "ALTER TABLE pending ADD COLUMN telegram_id INTEGER;",
+];
+
+/// [BauData] backed by a SQLite database. Users are keyed by their telegram username, which is
+/// compared case insensitively. A user without a `chat_id` is known to the database (e.g. as an
+/// admin) but has not registered with [baubot_core::BauBot] yet.
+///
+/// SQLite blocks, so every query runs on [tokio::task::spawn_blocking] rather than the runtime.
+pub struct SqlLiteDb {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqlLiteDb {
+    /// Open (or create) the database at `path` and bring its schema up to date.
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> rusqlite::Result<Self> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    /// Open a fresh in-memory database. Everything is lost on drop.
+    pub fn open_in_memory() -> rusqlite::Result<Self> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(mut connection: Connection) -> rusqlite::Result<Self> {
+        Self::migrate(&mut connection)?;
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// Run every migration past the database's current `user_version`.
+    fn migrate(connection: &mut Connection) -> rusqlite::Result<()> {
+        let transaction = connection.transaction()?;
+        let version: usize = transaction.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            transaction.execute_batch(migration)?;
+            transaction.pragma_update(None, "user_version", index + 1)?;
+        }
+
+        transaction.commit()
+    }
+
+    /// Run `query` against the connection on a blocking thread.
+    async fn run<R: Send + 'static>(
+        &self,
+        query: impl FnOnce(&mut Connection) -> R + Send + 'static,
+    ) -> R {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || {
+            // WARN: OBTAINING MUTEX
+            let mut connection = connection
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+            query(&mut connection)
+            // WARN: DROPPING MUTEX
+        })
+        .await
+        .unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()))
+    }
+
+    /// Grant or revoke admin rights for `username`. The user does not have to be registered.
+    pub async fn set_admin(&self, username: &str, is_admin: bool) -> Result<(), String> {
+        let username = username.to_string();
+        self.run(move |connection| {
+            connection
+                .execute(
+                    "INSERT INTO users (username, is_admin) VALUES (?1, ?2)
+                    ON CONFLICT (username) DO UPDATE SET is_admin = excluded.is_admin",
+                    params![username, is_admin],
+                )
+                .map_err(database_error)?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Application-level user id `username` was bound to by redeeming an invite, if any.
+    pub async fn get_user_id(&self, username: &str) -> Option<String> {
+        let username = username.to_string();
+        self.run(move |connection| {
+            connection
+                .query_row(
+                    "SELECT user_id FROM users WHERE username = ?1",
+                    params![username],
+                    |row| row.get::<_, Option<String>>(0),
+                )
+                .ok()
+                .flatten()
+        })
+        .await
+    }
+}
+
+impl BauData for SqlLiteDb {
+    async fn register_user_chat_id(&self, username: &str) -> Option<i64> {
+        self.get_chat_id(username).await
+    }
+
+    async fn insert_chat_id(&self, username: &str, chat_id: i64) -> Result<Option<i64>, String> {
+        let username = username.to_string();
+        self.run(move |connection| {
+            let transaction = connection.transaction().map_err(database_error)?;
+
+            // Fetch the old registration (if any)
+            let old_chat_id = transaction
+                .query_row(
+                    "SELECT chat_id FROM users WHERE username = ?1",
+                    params![username],
+                    |row| row.get::<_, Option<i64>>(0),
+                )
+                .optional()
+                .map_err(database_error)?
+                .flatten();
+
+            transaction
+                .execute(
+                    "INSERT INTO users (username, chat_id) VALUES (?1, ?2)
+                    ON CONFLICT (username) DO UPDATE SET chat_id = excluded.chat_id",
+                    params![username, chat_id],
+                )
+                .map_err(database_error)?;
+
+            transaction.commit().map_err(database_error)?;
+            Ok(old_chat_id)
+        })
+        .await
+    }
+
+    async fn delete_chat_id(&self, username: &str) -> Result<i64, String> {
+        let username = username.to_string();
+        self.run(move |connection| {
+            let transaction = connection.transaction().map_err(database_error)?;
+
+            let chat_id = transaction
+                .query_row(
+                    "SELECT chat_id FROM users WHERE username = ?1",
+                    params![username],
+                    |row| row.get::<_, Option<i64>>(0),
+                )
+                .optional()
+                .map_err(database_error)?
+                .flatten()
+                .ok_or(format!(
+                    "Username <code>{}</code> was not registered.",
+                    escape(&username)
+                ))?;
+
+            // Admins keep their row so that their flag survives re-registration
+            transaction
+                .execute(
+                    "UPDATE users SET chat_id = NULL WHERE username = ?1",
+                    params![username],
+                )
+                .map_err(database_error)?;
+            transaction
+                .execute(
+                    "DELETE FROM users WHERE username = ?1 AND is_admin = 0",
+                    params![username],
+                )
+                .map_err(database_error)?;
+
+            transaction.commit().map_err(database_error)?;
+            Ok(chat_id)
+        })
+        .await
+    }
+
+    async fn get_chat_id(&self, username: &str) -> Option<i64> {
+        let username = username.to_string();
+        self.run(move |connection| {
+            connection
+                .query_row(
+                    "SELECT chat_id FROM users WHERE username = ?1",
+                    params![username],
+                    |row| row.get::<_, Option<i64>>(0),
+                )
+                .ok()
+                .flatten()
+        })
+        .await
+    }
+
+    async fn is_admin(&self, username: &str) -> bool {
+        let username = username.to_string();
+        self.run(move |connection| {
+            connection
+                .query_row(
+                    "SELECT is_admin FROM users WHERE username = ?1",
+                    params![username],
+                    |row| row.get::<_, bool>(0),
+                )
+                .unwrap_or(false)
+        })
+        .await
+    }
+
+    async fn get_users(&self) -> Result<Vec<(String, i64)>, String> {
+        self.run(|connection| {
+            let mut statement = connection
+                .prepare(
+                    "SELECT username, chat_id FROM users WHERE chat_id IS NOT NULL
+                    ORDER BY username",
+                )
+                .map_err(database_error)?;
+            let users = statement
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+                .map_err(database_error)?
+                .collect::<rusqlite::Result<Vec<_>>>()
+                .map_err(database_error)?;
+            Ok(users)
+        })
+        .await
+    }
+
+    async fn save_pending(&self, pending: &types::PendingRequest) -> Result<(), String> {
+        let pending = pending.clone();
+        self.run(move |connection| {
+            connection
+                .execute(
+                    "INSERT OR REPLACE INTO pending
+                    (chat_id, message_id, request_id, recipient, deadline, timeout, buttons,
+                    telegram_id)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                    params![
+                        pending.chat_id,
+                        pending.message_id,
+                        pending.request_id,
+                        pending.recipient,
+                        pending.deadline,
+                        pending.timeout,
+                        // NOTE: Safe to unwrap because a list of strings always serializes
+                        serde_json::to_string(&pending.buttons).unwrap(),
+                        pending.telegram_id
+                    ],
+                )
+                .map_err(database_error)?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn remove_pending(&self, chat_id: i64, message_id: i32) -> Result<(), String> {
+        self.run(move |connection| {
+            connection
+                .execute(
+                    "DELETE FROM pending WHERE chat_id = ?1 AND message_id = ?2",
+                    params![chat_id, message_id],
+                )
+                .map_err(database_error)?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn load_pending(&self) -> Result<Vec<types::PendingRequest>, String> {
+        self.run(|connection| {
+            let mut statement = connection
+                .prepare(
+                    "SELECT chat_id, message_id, request_id, recipient, deadline, timeout, buttons,
+                    telegram_id
+                    FROM pending",
+                )
+                .map_err(database_error)?;
+            let pending = statement
+                .query_map([], |row| {
+                    Ok(types::PendingRequest {
+                        chat_id: row.get(0)?,
+                        message_id: row.get(1)?,
+                        request_id: row.get(2)?,
+                        recipient: row.get(3)?,
+                        telegram_id: row.get(7)?,
+                        deadline: row.get(4)?,
+                        timeout: row.get(5)?,
+                        buttons: serde_json::from_str(&row.get::<_, String>(6)?).map_err(
+                            |err| {
+                                rusqlite::Error::FromSqlConversionFailure(
+                                    6,
+                                    rusqlite::types::Type::Text,
+                                    Box::new(err),
+                                )
+                            },
+                        )?,
+                    })
+                })
+                .map_err(database_error)?
+                .collect::<rusqlite::Result<Vec<_>>>()
+                .map_err(database_error)?;
+            Ok(pending)
+        })
+        .await
+    }
+
+    async fn save_chat(&self, chat: &types::TrackedChat) -> Result<(), String> {
+        let chat = chat.clone();
+        self.run(move |connection| {
+            connection
+                .execute(
+                    "INSERT OR REPLACE INTO chats (chat_id, alias, title) VALUES (?1, ?2, ?3)",
+                    params![chat.chat_id, chat.alias, chat.title],
+                )
+                .map_err(database_error)?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn remove_chat(&self, chat_id: i64) -> Result<(), String> {
+        self.run(move |connection| {
+            connection
+                .execute("DELETE FROM chats WHERE chat_id = ?1", params![chat_id])
+                .map_err(database_error)?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_group_chat_id(&self, alias: &str) -> Option<i64> {
+        // The most recently saved chat wins if several share an alias
+        let alias = alias.to_string();
+        self.run(move |connection| {
+            connection
+                .query_row(
+                    "SELECT chat_id FROM chats WHERE alias = ?1 ORDER BY rowid DESC LIMIT 1",
+                    params![alias],
+                    |row| row.get(0),
+                )
+                .ok()
+        })
+        .await
+    }
+
+    async fn save_invite(&self, invite: &types::Invite) -> Result<(), String> {
+        let invite = invite.clone();
+        self.run(move |connection| {
+            connection
+                .execute(
+                    "INSERT INTO invites (token, user_id, deadline) VALUES (?1, ?2, ?3)",
+                    params![invite.token, invite.user_id, invite.deadline],
+                )
+                .map_err(database_error)?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn take_invite(&self, token: &str) -> Option<types::Invite> {
+        let token = token.to_string();
+        self.run(move |connection| {
+            let transaction = connection.transaction().ok()?;
+            let invite = transaction
+                .query_row(
+                    "SELECT token, user_id, deadline FROM invites WHERE token = ?1",
+                    params![token],
+                    |row| {
+                        Ok(types::Invite {
+                            token: row.get(0)?,
+                            user_id: row.get(1)?,
+                            deadline: row.get(2)?,
+                        })
+                    },
+                )
+                .ok()?;
+            transaction
+                .execute("DELETE FROM invites WHERE token = ?1", params![token])
+                .ok()?;
+            transaction.commit().ok()?;
+            Some(invite)
+        })
+        .await
+    }
+
+    async fn bind_user_id(
+        &self,
+        username: &str,
+        telegram_id: u64,
+        user_id: &str,
+    ) -> Result<(), String> {
+        let (username, user_id) = (username.to_string(), user_id.to_string());
+        self.run(move |connection| {
+            connection
+                .execute(
+                    "UPDATE users SET telegram_id = ?2, user_id = ?3 WHERE username = ?1",
+                    params![username, telegram_id, user_id],
+                )
+                .map_err(database_error)?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_telegram_id(&self, username: &str) -> Option<u64> {
+        let username = username.to_string();
+        self.run(move |connection| {
+            connection
+                .query_row(
+                    "SELECT telegram_id FROM users WHERE username = ?1",
+                    params![username],
+                    |row| row.get::<_, Option<u64>>(0),
+                )
+                .ok()
+                .flatten()
+        })
+        .await
+    }
+
+    async fn get_role_members(&self, role: &str) -> Vec<String> {
+        let role = role.to_string();
+        self.run(move |connection| {
+            let Ok(mut statement) =
+                connection.prepare("SELECT username FROM roles WHERE role = ?1 ORDER BY username")
+            else {
+                return Vec::new();
+            };
+            statement
+                .query_map(params![role], |row| row.get(0))
+                .and_then(|members| members.collect())
+                .unwrap_or_default()
+        })
+        .await
+    }
+
+    async fn get_roles(&self) -> Result<Vec<(String, Vec<String>)>, String> {
+        let rows = self
+            .run(|connection| {
+                let mut statement = connection
+                    .prepare("SELECT role, username FROM roles ORDER BY role, username")
+                    .map_err(database_error)?;
+                let rows = statement
+                    .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))
+                    .map_err(database_error)?
+                    .collect::<rusqlite::Result<Vec<_>>>()
+                    .map_err(database_error)?;
+                Ok::<_, String>(rows)
+            })
+            .await?;
+
+        let mut roles: Vec<(String, Vec<String>)> = Vec::new();
+        for (role, username) in rows {
+            match roles.last_mut() {
+                Some((last, members)) if *last == role => members.push(username),
+                _ => roles.push((role, vec![username])),
+            }
+        }
+        Ok(roles)
+    }
+
+    async fn add_role_member(&self, role: &str, username: &str) -> Result<bool, String> {
+        let (role, username) = (role.to_string(), username.to_string());
+        self.run(move |connection| {
+            let added = connection
+                .execute(
+                    "INSERT OR IGNORE INTO roles (role, username) VALUES (?1, ?2)",
+                    params![role, username],
+                )
+                .map_err(database_error)?;
+            Ok(added > 0)
+        })
+        .await
+    }
+
+    async fn remove_role_member(&self, role: &str, username: &str) -> Result<bool, String> {
+        let (role, username) = (role.to_string(), username.to_string());
+        self.run(move |connection| {
+            let removed = connection
+                .execute(
+                    "DELETE FROM roles WHERE role = ?1 AND username = ?2",
+                    params![role, username],
+                )
+                .map_err(database_error)?;
+            Ok(removed > 0)
+        })
+        .await
+    }
+}
+
+/// Format a [rusqlite::Error] for the user. [BauData] errors are parsed as HTML.
+fn database_error(err: rusqlite::Error) -> String {
+    format!("Database error: <code>{}</code>", escape(&err.to_string()))
+}
+
+/// Escape `text` for use in a HTML message.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[tokio::test]
+async fn migrations() {
+    let db = SqlLiteDb::open_in_memory().unwrap();
+    let version: usize = db
+        .run(|connection| connection.query_row("PRAGMA user_version", [], |row| row.get(0)))
+        .await
+        .unwrap();
+    assert_eq!(version, MIGRATIONS.len());
+
+    // Users that only differ in case are merged into the registered one
+    let connection = Connection::open_in_memory().unwrap();
+    for migration in &MIGRATIONS[..7] {
+        connection.execute_batch(migration).unwrap();
+    }
+    connection.pragma_update(None, "user_version", 7).unwrap();
+    connection
+        .execute_batch(
+            "INSERT INTO users (username, chat_id, is_admin) VALUES ('alice', NULL, 1);
+            INSERT INTO users (username, chat_id, is_admin) VALUES ('Alice', 1, 0);
+            INSERT INTO roles (role, username) VALUES ('oncall', 'alice'), ('oncall', 'ALICE');",
+        )
+        .unwrap();
+    let db = SqlLiteDb::from_connection(connection).unwrap();
+    assert_eq!(db.get_users().await, Ok(vec![("Alice".to_string(), 1)]));
+    assert!(db.is_admin("alice").await);
+    assert_eq!(db.get_role_members("oncall").await, vec!["alice"]);
+}
+
+#[tokio::test]
+async fn case_insensitive() {
+    let db = SqlLiteDb::open_in_memory().unwrap();
+
+    db.insert_chat_id("Alice", 1).await.unwrap();
+    assert_eq!(db.insert_chat_id("alice", 2).await, Ok(Some(1)));
+    assert_eq!(db.get_chat_id("ALICE").await, Some(2));
+    assert_eq!(db.get_users().await, Ok(vec![("Alice".to_string(), 2)]));
+
+    assert_eq!(db.add_role_member("oncall", "Bob").await, Ok(true));
+    assert_eq!(db.add_role_member("oncall", "bob").await, Ok(false));
+    assert_eq!(db.remove_role_member("oncall", "BOB").await, Ok(true));
+}
+
+#[tokio::test]
+async fn register_and_delete() {
+    let db = SqlLiteDb::open_in_memory().unwrap();
+
+    assert_eq!(db.insert_chat_id("user", 1).await, Ok(None));
+    assert_eq!(db.insert_chat_id("user", 2).await, Ok(Some(1)));
+    assert_eq!(db.get_chat_id("user").await, Some(2));
+
+    assert_eq!(db.delete_chat_id("user").await, Ok(2));
+    assert_eq!(db.get_chat_id("user").await, None);
+    assert!(db.delete_chat_id("user").await.is_err());
+}
+
+#[tokio::test]
+async fn admin_flag() {
+    let db = SqlLiteDb::open_in_memory().unwrap();
+    assert!(!db.is_admin("admin").await);
+
+    // Admin flag survives (re-)registration
+    db.set_admin("admin", true).await.unwrap();
+    db.insert_chat_id("admin", 1).await.unwrap();
+    assert_eq!(db.get_users().await, Ok(vec![("admin".to_string(), 1)]));
+    db.delete_chat_id("admin").await.unwrap();
+    assert!(db.is_admin("admin").await);
+    assert_eq!(db.get_chat_id("admin").await, None);
+
+    // Admins that are not registered are not users
+    assert_eq!(db.get_users().await, Ok(vec![]));
+
+    db.set_admin("admin", false).await.unwrap();
+    assert!(!db.is_admin("admin").await);
+}
+
+#[tokio::test]
+async fn pending_requests() {
+    let db = SqlLiteDb::open_in_memory().unwrap();
+    let pending = types::PendingRequest {
+        request_id: Some("request".to_string()),
+        recipient: "user".to_string(),
+        telegram_id: Some(5),
+        chat_id: 1,
+        message_id: 2,
+        deadline: 3,
+        timeout: 4,
+        buttons: vec!["approve".to_string(), "deny".to_string()],
+    };
+
+    db.save_pending(&pending).await.unwrap();
+    db.save_pending(&pending).await.unwrap();
+    assert_eq!(db.load_pending().await, Ok(vec![pending]));
+
+    db.remove_pending(1, 2).await.unwrap();
+    assert_eq!(db.load_pending().await, Ok(vec![]));
+}
+
+#[tokio::test]
+async fn tracked_chats() {
+    let db = SqlLiteDb::open_in_memory().unwrap();
+    assert_eq!(db.get_group_chat_id("ops").await, None);
+
+    db.save_chat(&types::TrackedChat::new(-1, None, "Ops"))
+        .await
+        .unwrap();
+    db.save_chat(&types::TrackedChat::new(
+        -2,
+        Some("Alerts"),
+        "Alerts channel",
+    ))
+    .await
+    .unwrap();
+    assert_eq!(db.get_group_chat_id("ops").await, Some(-1));
+    assert_eq!(db.get_group_chat_id("alerts").await, Some(-2));
+
+    db.remove_chat(-1).await.unwrap();
+    assert_eq!(db.get_group_chat_id("ops").await, None);
+}
+
+#[tokio::test]
+async fn roles() {
+    let db = SqlLiteDb::open_in_memory().unwrap();
+    assert!(db.get_role_members("oncall").await.is_empty());
+
+    assert_eq!(db.add_role_member("oncall", "bob").await, Ok(true));
+    assert_eq!(db.add_role_member("oncall", "alice").await, Ok(true));
+    assert_eq!(db.add_role_member("oncall", "alice").await, Ok(false));
+    assert_eq!(db.add_role_member("approvers", "alice").await, Ok(true));
+    assert_eq!(db.get_role_members("oncall").await, vec!["alice", "bob"]);
+    assert_eq!(
+        db.get_roles().await,
+        Ok(vec![
+            ("approvers".to_string(), vec!["alice".to_string()]),
+            (
+                "oncall".to_string(),
+                vec!["alice".to_string(), "bob".to_string()]
+            ),
+        ])
+    );
+
+    assert_eq!(db.remove_role_member("oncall", "bob").await, Ok(true));
+    assert_eq!(db.remove_role_member("oncall", "bob").await, Ok(false));
+    assert_eq!(db.get_role_members("oncall").await, vec!["alice"]);
+}
+
+#[tokio::test]
+async fn invites() {
+    let db = SqlLiteDb::open_in_memory().unwrap();
+    let invite = types::Invite {
+        token: "token".to_string(),
+        user_id: "user-1".to_string(),
+        deadline: 1,
+    };
+    db.save_invite(&invite).await.unwrap();
+
+    // Invites are used once
+    assert_eq!(db.take_invite("token").await, Some(invite));
+    assert_eq!(db.take_invite("token").await, None);
+
+    db.insert_chat_id("user", 1).await.unwrap();
+    assert_eq!(db.get_telegram_id("user").await, None);
+    db.bind_user_id("user", 42, "user-1").await.unwrap();
+    assert_eq!(db.get_user_id("user").await, Some("user-1".to_string()));
+    assert_eq!(db.get_telegram_id("User").await, Some(42));
+}
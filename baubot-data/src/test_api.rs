@@ -0,0 +1,562 @@
+//! Fake telegram Bot API for offline integration tests. Point a [Bot] at [TestApi::url] (or use
+//! [TestApi::bot]) and it will long-poll [TestApi] for updates injected by the test through
+//! [TestApi::send_text] and [TestApi::press_button]. Every outgoing call made by the [Bot] is
+//! recorded as a [TestApiCall].
+//!
+//! Once a webhook is registered through `setWebhook`, injected updates are posted to the webhook
+//! (with the registered secret) instead, the way telegram would.
+
+use baubot_core::prelude::Bot;
+use baubot_utils::*;
+use serde_json::json;
+use serde_json::Value;
+use std::sync::atomic::AtomicI64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio::sync::Notify;
+
+/// Username of the bot as reported by `getMe`.
+pub const TEST_API_USERNAME: &str = "baubot";
+
+/// Longest time a `getUpdates` call is held open, regardless of the timeout requested by the
+/// [Bot]. Keeps test teardown snappy.
+const MAX_POLL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Bot API method called on [TestApi] together with its JSON payload and the `result` that
+/// [TestApi] answered with.
+#[derive(Debug, Clone)]
+pub struct TestApiCall {
+    pub method: String,
+    pub body: Value,
+    pub result: Value,
+}
+
+/// Fake telegram Bot API server. The listener is aborted on drop.
+pub struct TestApi {
+    addr: std::net::SocketAddr,
+    state: Arc<State>,
+    listener: tokio::task::JoinHandle<()>,
+}
+
+#[derive(Default)]
+struct State {
+    next_id: AtomicI64,
+    updates: Mutex<Vec<Value>>,
+    updates_notify: Notify,
+    calls: Mutex<Vec<TestApiCall>>,
+    calls_notify: Notify,
+    webhook: Mutex<Option<Webhook>>,
+}
+
+/// Webhook registered through `setWebhook`.
+#[derive(Clone)]
+struct Webhook {
+    url: String,
+    secret_token: Option<String>,
+}
+
+impl Drop for TestApi {
+    fn drop(&mut self) {
+        self.listener.abort();
+    }
+}
+
+impl TestApi {
+    /// Start a [TestApi] on an ephemeral local port.
+    pub async fn start() -> Self {
+        init();
+
+        // NOTE: Init tasks should unwrap
+        let tcp_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = tcp_listener.local_addr().unwrap();
+        trace!("TestApi listening on {addr:?}");
+
+        let state = Arc::new(State {
+            next_id: AtomicI64::new(1),
+            ..Default::default()
+        });
+
+        let state_clone = state.clone();
+        let listener = tokio::task::spawn(async move {
+            loop {
+                match tcp_listener.accept().await {
+                    Ok((tcp_stream, _)) => {
+                        let state = state_clone.clone();
+                        tokio::task::spawn(Self::incoming_handler(state, tcp_stream));
+                    }
+                    Err(err) => error!("Unable to accept connection: {err:?}"),
+                }
+            }
+        });
+
+        Self {
+            addr,
+            state,
+            listener,
+        }
+    }
+
+    /// Base URL to hand to [Bot::set_api_url].
+    pub fn url(&self) -> String {
+        format!("http://{}/", self.addr)
+    }
+
+    /// A [Bot] pointed at this [TestApi].
+    pub fn bot(&self) -> Bot {
+        // NOTE: Safe to unwrap because the URL is assembled above
+        Bot::new(TELOXIDE_TOKEN).set_api_url(self.url().parse().unwrap())
+    }
+
+    /// Snapshot of every call made so far.
+    pub async fn calls(&self) -> Vec<TestApiCall> {
+        self.state.calls.lock().await.clone()
+    }
+
+    /// Wait until a call matching `predicate` shows up and return it.
+    pub async fn wait_for<F: Fn(&TestApiCall) -> bool>(&self, predicate: F) -> TestApiCall {
+        loop {
+            // Register interest before checking to avoid missing a notification
+            let notified = self.state.calls_notify.notified();
+            if let Some(call) = self.calls().await.into_iter().find(|call| predicate(call)) {
+                break call;
+            }
+            notified.await;
+        }
+    }
+
+    /// Wait for `count` `sendMessage` calls to `chat_id` that carry an inline keyboard and
+    /// return their `message_id`s.
+    pub async fn wait_for_keyboards(&self, chat_id: i64, count: usize) -> Vec<i64> {
+        loop {
+            let notified = self.state.calls_notify.notified();
+            let message_ids = self
+                .calls()
+                .await
+                .into_iter()
+                .filter(|call| {
+                    call.method == "sendMessage"
+                        && call.body["chat_id"] == chat_id
+                        && call.body.get("reply_markup").is_some()
+                })
+                .filter_map(|call| call.result["message_id"].as_i64())
+                .collect::<Vec<_>>();
+            if message_ids.len() >= count {
+                break message_ids;
+            }
+            notified.await;
+        }
+    }
+
+    /// Inject a text message (e.g. `/start`) sent by `username` in the private chat `chat_id`.
+    pub async fn send_text(&self, chat_id: i64, username: &str, text: &str) -> i64 {
+        let (message_id, update) = self.text_update(chat_id, username, text);
+        self.push_update(update).await;
+        message_id
+    }
+
+    /// Post a text message to the registered webhook with `secret_token` in place of the
+    /// registered secret. Returns the HTTP status code the webhook answered with.
+    ///
+    /// ## Panics
+    /// If no webhook is registered.
+    pub async fn spoof_text(
+        &self,
+        chat_id: i64,
+        username: &str,
+        text: &str,
+        secret_token: Option<&str>,
+    ) -> std::io::Result<u16> {
+        let (_, mut update) = self.text_update(chat_id, username, text);
+        update["update_id"] = self.next_id().into();
+
+        let webhook = self.state.webhook.lock().await.clone();
+        let webhook = webhook.expect("No webhook registered");
+        post_webhook(&webhook.url, secret_token, &update).await
+    }
+
+    fn text_update(&self, chat_id: i64, username: &str, text: &str) -> (i64, Value) {
+        let message_id = self.next_id();
+        let message = json!({
+            "message_id": message_id,
+            "date": 1,
+            "chat": chat_json(chat_id, username),
+            "from": user_json(chat_id, username),
+            "text": text,
+        });
+        (message_id, json!({ "message": message }))
+    }
+
+    /// Inject a press of the inline keyboard button labelled `label` on `message_id`. If no such
+    /// button was sent, `label` is taken as the callback data.
+    pub async fn press_button(&self, chat_id: i64, username: &str, message_id: i64, label: &str) {
+        let data = self.callback_data(message_id, label).await;
+        let message = json!({
+            "message_id": message_id,
+            "date": 1,
+            "chat": chat_json(chat_id, username),
+            "from": me_json(),
+            "text": "",
+        });
+        let callback_query = json!({
+            "id": self.next_id().to_string(),
+            "from": user_json(chat_id, username),
+            "message": message,
+            "chat_instance": chat_id.to_string(),
+            "data": data,
+        });
+        self.push_update(json!({ "callback_query": callback_query }))
+            .await;
+    }
+
+    /// Callback data of the button labelled `label` on the message `message_id` sent through this
+    /// [TestApi], or `label` itself if there is none.
+    async fn callback_data(&self, message_id: i64, label: &str) -> String {
+        self.calls()
+            .await
+            .into_iter()
+            .filter(|call| call.result["message_id"] == message_id)
+            .find_map(|call| {
+                call.body["reply_markup"]["inline_keyboard"]
+                    .as_array()?
+                    .iter()
+                    .filter_map(Value::as_array)
+                    .flatten()
+                    .find(|button| button["text"] == label)?["callback_data"]
+                    .as_str()
+                    .map(str::to_string)
+            })
+            .unwrap_or_else(|| label.to_string())
+    }
+
+    fn next_id(&self) -> i64 {
+        self.state.next_id.fetch_add(1, Ordering::SeqCst)
+    }
+
+    async fn push_update(&self, mut update: Value) {
+        update["update_id"] = self.next_id().into();
+
+        // Deliver straight to the webhook if there is one
+        let webhook = self.state.webhook.lock().await.clone();
+        if let Some(webhook) = webhook {
+            trace!("Posting update {update} to {}", webhook.url);
+            match post_webhook(&webhook.url, webhook.secret_token.as_deref(), &update).await {
+                Ok(200) => {}
+                Ok(status) => error!("Webhook answered with {status}"),
+                Err(err) => error!("Unable to post to webhook: {err:?}"),
+            }
+            return;
+        }
+
+        trace!("Queueing update {update}");
+        self.state.updates.lock().await.push(update);
+        self.state.updates_notify.notify_waiters();
+    }
+
+    /// Serve a single HTTP request and close the connection.
+    async fn incoming_handler(state: Arc<State>, mut tcp_stream: TcpStream) -> std::io::Result<()> {
+        let (method, body) = read_request(&mut tcp_stream).await?;
+        trace!("TestApi received {method}: {body}");
+
+        let result = Self::method_handler(&state, &method, &body).await;
+
+        if let Some(result) = &result {
+            state.calls.lock().await.push(TestApiCall {
+                method,
+                body,
+                result: result.clone(),
+            });
+            state.calls_notify.notify_waiters();
+        }
+
+        let response = match result {
+            Some(result) => json!({ "ok": true, "result": result }),
+            None => json!({
+                "ok": false,
+                "error_code": 404,
+                "description": "Not Found: method not found",
+            }),
+        }
+        .to_string();
+
+        let response = format!(
+            concat!(
+                "HTTP/1.1 200 OK\r\n",
+                "Content-Type: application/json\r\n",
+                "Content-Length: {}\r\n",
+                "Connection: close\r\n\r\n",
+                "{}"
+            ),
+            response.len(),
+            response
+        );
+        tcp_stream.write_all(response.as_bytes()).await?;
+        tcp_stream.shutdown().await
+    }
+
+    /// Produce the `result` of a Bot API call, or [None] if the method is not supported.
+    async fn method_handler(state: &State, method: &str, body: &Value) -> Option<Value> {
+        match method {
+            "getMe" => Some(me_json()),
+            "answerCallbackQuery" => Some(json!(true)),
+            "setWebhook" => {
+                *state.webhook.lock().await = Some(Webhook {
+                    url: body["url"].as_str().unwrap_or_default().to_string(),
+                    secret_token: body["secret_token"].as_str().map(str::to_string),
+                });
+                Some(json!(true))
+            }
+            "deleteWebhook" => {
+                *state.webhook.lock().await = None;
+                Some(json!(true))
+            }
+            "getWebhookInfo" => Some(json!({
+                "url": state
+                    .webhook
+                    .lock()
+                    .await
+                    .as_ref()
+                    .map(|webhook| webhook.url.clone())
+                    .unwrap_or_default(),
+                "has_custom_certificate": false,
+                "pending_update_count": 0,
+            })),
+            "getUpdates" => {
+                let offset = body["offset"].as_i64().unwrap_or_default();
+                let timeout =
+                    std::time::Duration::from_secs(body["timeout"].as_u64().unwrap_or_default());
+                let deadline = tokio::time::Instant::now() + timeout.min(MAX_POLL);
+
+                loop {
+                    let notified = state.updates_notify.notified();
+                    let updates = state
+                        .updates
+                        .lock()
+                        .await
+                        .iter()
+                        .filter(|update| update["update_id"].as_i64() >= Some(offset))
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    if !updates.is_empty()
+                        || tokio::time::timeout_at(deadline, notified).await.is_err()
+                    {
+                        break Some(Value::Array(updates));
+                    }
+                }
+            }
+            "sendMessage" => {
+                let message_id = state.next_id.fetch_add(1, Ordering::SeqCst);
+                Some(json!({
+                    "message_id": message_id,
+                    "date": 1,
+                    "chat": { "id": body["chat_id"], "type": "private" },
+                    "from": me_json(),
+                    "text": body["text"],
+                }))
+            }
+            "sendPhoto" | "sendDocument" => {
+                let message_id = state.next_id.fetch_add(1, Ordering::SeqCst);
+                Some(json!({
+                    "message_id": message_id,
+                    "date": 1,
+                    "chat": { "id": multipart_i64(&body["chat_id"]), "type": "private" },
+                    "from": me_json(),
+                    "text": body["caption"].as_str().unwrap_or_default(),
+                }))
+            }
+            "sendMediaGroup" => {
+                let media = body["media"]
+                    .as_str()
+                    .and_then(|media| serde_json::from_str::<Vec<Value>>(media).ok())
+                    .unwrap_or_default();
+                let messages = media
+                    .iter()
+                    .map(|_| {
+                        json!({
+                            "message_id": state.next_id.fetch_add(1, Ordering::SeqCst),
+                            "date": 1,
+                            "chat": { "id": multipart_i64(&body["chat_id"]), "type": "private" },
+                            "from": me_json(),
+                            "text": "",
+                        })
+                    })
+                    .collect();
+                Some(Value::Array(messages))
+            }
+            "editMessageReplyMarkup" => Some(json!({
+                "message_id": body["message_id"],
+                "date": 1,
+                "chat": { "id": body["chat_id"], "type": "private" },
+                "from": me_json(),
+                "text": "",
+            })),
+            "editMessageText" => Some(json!({
+                "message_id": body["message_id"],
+                "date": 1,
+                "chat": { "id": body["chat_id"], "type": "private" },
+                "from": me_json(),
+                "text": body["text"],
+            })),
+            _ => None,
+        }
+    }
+}
+
+/// Read the method name and JSON body off a Bot API request.
+async fn read_request(tcp_stream: &mut TcpStream) -> std::io::Result<(String, Value)> {
+    let mut buffer = Vec::with_capacity(1024 * 4);
+
+    // Read until the end of the headers
+    let header_end = loop {
+        if let Some(index) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break index + 4;
+        }
+        if tcp_stream.read_buf(&mut buffer).await? == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+    };
+    let headers = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+
+    // Method name is the last path segment of the request line. Bot API method names are case
+    // insensitive so normalise them to the camelCase used in the documentation.
+    let mut method = headers
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|path| path.rsplit('/').next())
+        .unwrap_or_default()
+        .to_string();
+    if let Some(first) = method.get_mut(..1) {
+        first.make_ascii_lowercase();
+    }
+
+    // Read the body
+    let content_length = headers
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .unwrap_or_default();
+    while buffer.len() < header_end + content_length {
+        if tcp_stream.read_buf(&mut buffer).await? == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+    }
+    let body = &buffer[header_end..header_end + content_length];
+
+    // Some methods (e.g. `setWebhook`) are sent as multipart forms
+    let boundary = headers
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+        .and_then(|(_, value)| value.split_once("boundary="))
+        .map(|(_, boundary)| boundary.trim().trim_matches('"').to_string());
+    let body = match boundary {
+        Some(boundary) => parse_multipart(&String::from_utf8_lossy(body), &boundary),
+        None => serde_json::from_slice(body).unwrap_or(Value::Null),
+    };
+
+    Ok((method, body))
+}
+
+/// Integer sent as a multipart form field (i.e. as a string).
+fn multipart_i64(value: &Value) -> Value {
+    value
+        .as_str()
+        .and_then(|value| value.parse::<i64>().ok())
+        .map(Value::from)
+        .unwrap_or_else(|| value.clone())
+}
+
+/// Flatten a multipart form into a JSON object of its text fields.
+fn parse_multipart(body: &str, boundary: &str) -> Value {
+    let fields = body
+        .split(&format!("--{boundary}"))
+        .filter_map(|part| part.split_once("\r\n\r\n"))
+        .filter_map(|(headers, value)| {
+            let name = headers.split("name=\"").nth(1)?.split('"').next()?;
+            let value = value.strip_suffix("\r\n").unwrap_or(value);
+            Some((name.to_string(), Value::String(value.to_string())))
+        })
+        .collect();
+    Value::Object(fields)
+}
+
+/// POST `update` to the webhook at `url` (plain `http` only) and return the HTTP status code.
+async fn post_webhook(
+    url: &str,
+    secret_token: Option<&str>,
+    update: &Value,
+) -> std::io::Result<u16> {
+    let invalid_url = || std::io::Error::new(std::io::ErrorKind::InvalidInput, url.to_string());
+    let url = url.strip_prefix("http://").ok_or_else(invalid_url)?;
+    let (host, path) = url.split_at(url.find('/').unwrap_or(url.len()));
+    let path = if path.is_empty() { "/" } else { path };
+
+    let body = update.to_string();
+    let secret_header = secret_token
+        .map(|secret_token| format!("X-Telegram-Bot-Api-Secret-Token: {secret_token}\r\n"))
+        .unwrap_or_default();
+    let request = format!(
+        concat!(
+            "POST {} HTTP/1.1\r\n",
+            "Host: {}\r\n",
+            "Content-Type: application/json\r\n",
+            "Content-Length: {}\r\n",
+            "{}",
+            "Connection: close\r\n\r\n",
+            "{}"
+        ),
+        path,
+        host,
+        body.len(),
+        secret_header,
+        body
+    );
+
+    let mut tcp_stream = TcpStream::connect(host).await?;
+    tcp_stream.write_all(request.as_bytes()).await?;
+    let mut response = Vec::new();
+    tcp_stream.read_to_end(&mut response).await?;
+
+    // Status code is the second word of the status line
+    String::from_utf8_lossy(&response)
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| std::io::ErrorKind::InvalidData.into())
+}
+
+fn user_json(id: i64, username: &str) -> Value {
+    json!({
+        "id": id,
+        "is_bot": false,
+        "first_name": username,
+        "username": username,
+    })
+}
+
+fn chat_json(id: i64, username: &str) -> Value {
+    json!({
+        "id": id,
+        "type": "private",
+        "first_name": username,
+        "username": username,
+    })
+}
+
+fn me_json() -> Value {
+    json!({
+        "id": 1,
+        "is_bot": true,
+        "first_name": TEST_API_USERNAME,
+        "username": TEST_API_USERNAME,
+        "can_join_groups": true,
+        "can_read_all_group_messages": false,
+        "supports_inline_queries": false,
+    })
+}
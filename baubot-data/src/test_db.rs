@@ -2,11 +2,18 @@ use crate::*;
 
 use baubot_utils::*;
 
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
 
 #[derive(Default)]
 pub struct TestDB {
     db: tokio::sync::Mutex<HashMap<String, i64>>,
+    pending: tokio::sync::Mutex<HashMap<(i64, i32), types::PendingRequest>>,
+    chats: tokio::sync::Mutex<HashMap<i64, types::TrackedChat>>,
+    roles: tokio::sync::Mutex<BTreeMap<String, BTreeSet<String>>>,
+    invites: tokio::sync::Mutex<HashMap<String, types::Invite>>,
+    user_ids: tokio::sync::Mutex<HashMap<String, (u64, String)>>,
 }
 
 impl TestDB {
@@ -17,7 +24,16 @@ impl TestDB {
         let mut db = HashMap::new();
         db.insert(user, chat_id);
         let db = tokio::sync::Mutex::new(db);
-        Self { db }
+        Self {
+            db,
+            ..Default::default()
+        }
+    }
+
+    /// Application-level user id `username` was bound to by redeeming an invite, if any.
+    pub async fn get_user_id(&self, username: &str) -> Option<String> {
+        let user_ids = self.user_ids.lock().await;
+        user_ids.get(username).map(|(_, user_id)| user_id.clone())
     }
 }
 
@@ -60,8 +76,18 @@ impl BauData for TestDB {
     }
 
     fn is_admin(&self, username: &str) -> impl std::future::Future<Output = bool> + Send {
-        let _ = username;
-        async { true }
+        let admin = username == TEST_USER;
+        async move { admin }
+    }
+
+    async fn get_users(&self) -> Result<Vec<(String, i64)>, String> {
+        let db = self.db.lock().await;
+        let mut users = db
+            .iter()
+            .map(|(username, chat_id)| (username.clone(), *chat_id))
+            .collect::<Vec<_>>();
+        users.sort();
+        Ok(users)
     }
 
     fn get_chat_id(&self, username: &str) -> impl std::future::Future<Output = Option<i64>> + Send {
@@ -73,4 +99,102 @@ impl BauData for TestDB {
             }
         }
     }
+
+    async fn save_pending(&self, pending: &types::PendingRequest) -> Result<(), String> {
+        let mut db = self.pending.lock().await;
+        db.insert((pending.chat_id, pending.message_id), pending.clone());
+        Ok(())
+    }
+
+    async fn remove_pending(&self, chat_id: i64, message_id: i32) -> Result<(), String> {
+        let mut db = self.pending.lock().await;
+        db.remove(&(chat_id, message_id));
+        Ok(())
+    }
+
+    async fn load_pending(&self) -> Result<Vec<types::PendingRequest>, String> {
+        let db = self.pending.lock().await;
+        Ok(db.values().cloned().collect())
+    }
+
+    async fn save_chat(&self, chat: &types::TrackedChat) -> Result<(), String> {
+        let mut db = self.chats.lock().await;
+        db.insert(chat.chat_id, chat.clone());
+        Ok(())
+    }
+
+    async fn remove_chat(&self, chat_id: i64) -> Result<(), String> {
+        let mut db = self.chats.lock().await;
+        db.remove(&chat_id);
+        Ok(())
+    }
+
+    async fn get_group_chat_id(&self, alias: &str) -> Option<i64> {
+        let db = self.chats.lock().await;
+        db.values()
+            .find(|chat| chat.alias == alias)
+            .map(|chat| chat.chat_id)
+    }
+
+    async fn save_invite(&self, invite: &types::Invite) -> Result<(), String> {
+        let mut db = self.invites.lock().await;
+        db.insert(invite.token.clone(), invite.clone());
+        Ok(())
+    }
+
+    async fn take_invite(&self, token: &str) -> Option<types::Invite> {
+        let mut db = self.invites.lock().await;
+        db.remove(token)
+    }
+
+    async fn bind_user_id(
+        &self,
+        username: &str,
+        telegram_id: u64,
+        user_id: &str,
+    ) -> Result<(), String> {
+        let mut db = self.user_ids.lock().await;
+        db.insert(username.to_string(), (telegram_id, user_id.to_string()));
+        Ok(())
+    }
+
+    async fn get_telegram_id(&self, username: &str) -> Option<u64> {
+        let db = self.user_ids.lock().await;
+        db.get(username).map(|(telegram_id, _)| *telegram_id)
+    }
+
+    async fn get_role_members(&self, role: &str) -> Vec<String> {
+        let db = self.roles.lock().await;
+        db.get(role)
+            .map(|members| members.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    async fn get_roles(&self) -> Result<Vec<(String, Vec<String>)>, String> {
+        let db = self.roles.lock().await;
+        Ok(db
+            .iter()
+            .map(|(role, members)| (role.clone(), members.iter().cloned().collect()))
+            .collect())
+    }
+
+    async fn add_role_member(&self, role: &str, username: &str) -> Result<bool, String> {
+        let mut db = self.roles.lock().await;
+        Ok(db
+            .entry(role.to_string())
+            .or_default()
+            .insert(username.to_string()))
+    }
+
+    async fn remove_role_member(&self, role: &str, username: &str) -> Result<bool, String> {
+        let mut db = self.roles.lock().await;
+        let Some(members) = db.get_mut(role) else {
+            return Ok(false);
+        };
+        let removed = members.remove(username);
+        if members.is_empty() {
+            db.remove(role);
+        }
+        Ok(removed)
+    }
 }
@@ -8,6 +8,10 @@
 //!     - [BauClient] rejects the request if it cannot be correctly serialized.
 //! - [BauServer] constructs a [BauMessage] and sends that to [BauBot]
 //!     - [BauServer] rejects the request if it cannot be correctly de-serialized.
+//!     - [BauServer] rejects the request with [BauServerResponse::Busy] if the queue of [BauBot]
+//!       is full.
+//!     - [BauServer] acknowledges the request with [BauServerResponse::Accepted], carrying the
+//!       [BauMessage::id] (generated if the client did not choose one).
 //! - [BauBot] broadcasts the [BauMessage] to the appropriate [BauMessage::recipients]
 //! - (only if response requested) [BauBot] polls the [BauMessage::recipients] for a response
 //! - (only if response requested) [BauBot] receives the [BauResponse] and pipes it back to the
@@ -16,8 +20,19 @@
 //! [BauServerResponseSender] that we received a response.
 //! - (only if response requested) [BauClient] polls the [BauServerResponseReceiver] and obtains
 //! the [BauServerResponse].
+//! - (only if a [Quorum] was requested) [BauServer] sends a final [BauServerResponse::Outcome].
 //! - [net::TcpStream] is closed, signifying the end of the transaction.
+//!
+//! A [BauClient] that lost its connection to a [BauServer] (e.g. because [BauBot] restarted) can
+//! pick the responses to a [BauMessage] with a [BauMessage::id] back up through
+//! [BauClient::reclaim]. See [BauBot::reclaim].
+//!
+//! A [BauMessage] can be taken back with [BauClient::cancel] using the id from
+//! [BauServerResponse::Accepted]. See [BauBot::cancel].
+//!
+//! Invite deep links are issued through [BauClient::invite]. See [BauBot::invite].
 
+use baubot_core::prelude::BauTransport;
 use baubot_core::BauBot;
 pub use prelude::types::*;
 pub(crate) use prelude::*;
@@ -28,22 +43,32 @@ use tokio::io::AsyncWriteExt;
 
 pub mod prelude;
 
+/// [BauMessage::id] to acknowledge (if any), receivers for the responses of every recipient and
+/// for the [QuorumOutcome] (if any)
+type BauBotReceivers = (
+    Option<String>,
+    Vec<(String, BauResponseReceiver)>,
+    Option<QuorumOutcomeReceiver>,
+);
+
 /// [BauServer] listens for requests on the specified address, ideally following the transaction
 /// protocol described in the [crate] documentation.
-pub struct BauServer<Db, DbRef>
+pub struct BauServer<Db, DbRef, T = Bot>
 where
     Db: baubot_core::prelude::BauData + Send + Sync + 'static,
     DbRef: Deref<Target = Db> + Clone + Send + Sync + 'static,
+    T: BauTransport,
 {
-    _baubot: PhantomData<BauBot<Db, DbRef>>,
+    _baubot: PhantomData<BauBot<Db, DbRef, T>>,
     listener: task::JoinHandle<()>,
 }
 
 /// Abort the listener on drop to avoid hanging processes
-impl<Db, DbRef> Drop for BauServer<Db, DbRef>
+impl<Db, DbRef, T> Drop for BauServer<Db, DbRef, T>
 where
     Db: baubot_core::prelude::BauData + Send + Sync + 'static,
     DbRef: Deref<Target = Db> + Clone + Send + Sync + 'static,
+    T: BauTransport,
 {
     fn drop(&mut self) {
         self.listener.abort();
@@ -58,8 +83,26 @@ where
     /// Creates a new [BauServer] object that adheres to the transaction protocol specified in the
     /// [crate] documentation.
     pub fn new<S: Into<String>>(db: DbRef, addr: ::core::net::SocketAddr, token: S) -> Self {
-        // Create baubot
-        let baubot = Arc::new(BauBot::new(db, token));
+        Self::with_transport(db, addr, Bot::new(token))
+    }
+}
+
+impl<Db, DbRef, T> BauServer<Db, DbRef, T>
+where
+    Db: baubot_core::prelude::BauData + Send + Sync + 'static,
+    DbRef: Deref<Target = Db> + Clone + Send + Sync + 'static,
+    T: BauTransport,
+{
+    /// Creates a new [BauServer] whose [BauBot] talks to telegram through `transport`. See
+    /// [BauBot::with_transport].
+    pub fn with_transport(db: DbRef, addr: ::core::net::SocketAddr, transport: T) -> Self {
+        Self::with_baubot(BauBot::with_transport(db, transport), addr)
+    }
+
+    /// Creates a new [BauServer] in front of an existing `baubot`, e.g. one configured through
+    /// [baubot_core::config::BauBotBuilder].
+    pub fn with_baubot(baubot: BauBot<Db, DbRef, T>, addr: ::core::net::SocketAddr) -> Self {
+        let baubot = Arc::new(baubot);
 
         // Create listening thread
         let listener = task::spawn(Self::listen(baubot, addr));
@@ -71,7 +114,7 @@ where
     }
 
     /// Loop that listens for [net::TcpStream] connections and spawns threads to deal with them.
-    async fn listen(baubot: Arc<BauBot<Db, DbRef>>, addr: ::core::net::SocketAddr) {
+    async fn listen(baubot: Arc<BauBot<Db, DbRef, T>>, addr: ::core::net::SocketAddr) {
         // Create TCP listener
         // NOTE: Init tasks should unwrap
         let tcp_listener = net::TcpListener::bind(addr).await.unwrap();
@@ -91,7 +134,7 @@ where
     }
 
     async fn incoming_handler(
-        baubot: Arc<BauBot<Db, DbRef>>,
+        baubot: Arc<BauBot<Db, DbRef, T>>,
         (mut tcp_stream, socket_addr): (net::TcpStream, std::net::SocketAddr),
     ) -> std::io::Result<()> {
         let _ = baubot;
@@ -101,18 +144,46 @@ where
         let request = read_stream(&tcp_stream).await?;
         trace!("Received request: {request}");
 
-        // Pass off to baubot notification
-        let baubot_response_receivers = Self::notify_baubot(baubot, request);
+        // Cancel requests are answered straight away
+        if let Some((request_id, withdraw)) = cancel_request(&request) {
+            let retracted = baubot.cancel(&request_id, withdraw).await;
+
+            // NOTE: Safe to unwrap because we checked the serialization chain
+            let response =
+                serde_json::to_string(&BauServerResponse::Cancelled { retracted }).unwrap();
+            write_stream(&tcp_stream, &response).await?;
+
+            trace!("Shutting stream down");
+            return tcp_stream.shutdown().await;
+        }
+
+        // Invite requests are answered straight away
+        if let Some((user_id, valid_for)) = invite_request(&request) {
+            let token = baubot.invite(&user_id, valid_for).await;
+
+            // NOTE: Safe to unwrap because we checked the serialization chain
+            let response = serde_json::to_string(&BauServerResponse::Invited { token }).unwrap();
+            write_stream(&tcp_stream, &response).await?;
+
+            trace!("Shutting stream down");
+            return tcp_stream.shutdown().await;
+        }
+
+        // Pass off to baubot notification, unless the client is picking up an earlier request
+        let baubot_response_receivers = match reclaim_id(&request) {
+            Some(request_id) => Ok((None, baubot.reclaim(&request_id).await, None)),
+            None => Self::notify_baubot(baubot, request),
+        };
 
         // Check the status of the baubot_response_recievers
         match baubot_response_receivers {
             // If baubot managed to assemble a set of receivers:
-            Ok(responses) => Self::await_baubot_responses(&tcp_stream, responses).await?,
+            Ok((id, responses, outcome)) => {
+                Self::await_baubot_responses(&tcp_stream, id, responses, outcome).await?
+            }
 
-            // If there was a serialization error, report it
+            // If there was a serialization error or baubot is busy, report it
             Err(err) => {
-                let err = BauServerResponse::InvalidData(err);
-
                 // NOTE: Safe to unwrap becausee we have checked the serialization pipeline
                 let err = serde_json::to_string(&err).unwrap();
                 write_stream(&tcp_stream, &err).await?;
@@ -125,31 +196,65 @@ where
     }
 
     fn notify_baubot(
-        baubot: Arc<BauBot<Db, DbRef>>,
+        baubot: Arc<BauBot<Db, DbRef, T>>,
         request: String,
-    ) -> Result<Vec<(String, BauResponseReceiver)>, SerializeError> {
-        let mut bau_message = BauMessage::builder(&request)?();
+    ) -> Result<BauBotReceivers, BauServerResponse> {
+        let mut bau_message = BauMessage::builder(&request)
+            .map_err(|error| BauServerResponse::InvalidData { error })?(
+        );
         let mut baubot_responses = Vec::new();
 
+        // Give the client a handle to cancel the payload with
+        let id = bau_message.ensure_id().to_string();
+
+        // If payload has a quorum, listen for its outcome
+        let mut baubot_outcome = None;
+        if bau_message.responses.quorum != Quorum::Independent {
+            let (outcome_sender, outcome_receiver) = sync::oneshot::channel();
+            bau_message.outcome = Some(outcome_sender);
+            baubot_outcome = Some(outcome_receiver);
+        }
+
         // If payload requries a response, create handlers
         if !bau_message.responses.keyboard.is_empty() {}
         for (recipient, baubot_response_sender_field) in bau_message.recipients.iter_mut() {
             let (baubot_response_sender, baubot_response_receiver) = sync::oneshot::channel();
-            baubot_responses.push((recipient.clone(), baubot_response_receiver));
+            baubot_responses.push((recipient.to_string(), baubot_response_receiver));
 
             *baubot_response_sender_field = Some(baubot_response_sender);
         }
 
-        // NOTE: Safe to unwrap because if the baubot has died... this entire thing is dogshit
-        baubot.send(bau_message).unwrap();
+        // Turn the client away rather than queue up without limit
+        match baubot.try_send(bau_message) {
+            Ok(()) => {}
+            Err(sync::mpsc::error::TrySendError::Full(_)) => return Err(BauServerResponse::Busy),
 
-        Ok(baubot_responses)
+            // Baubot is shutting down: every recipient is told so
+            Err(sync::mpsc::error::TrySendError::Closed(bau_message)) => {
+                for (_, baubot_response_sender) in bau_message.recipients {
+                    if let Some(baubot_response_sender) = baubot_response_sender {
+                        let _ = baubot_response_sender.send(Err(BauBotError::Shutdown));
+                    }
+                }
+            }
+        }
+
+        Ok((Some(id), baubot_responses, baubot_outcome))
     }
 
     async fn await_baubot_responses(
         tcp_stream: &net::TcpStream,
+        id: Option<String>,
         responses: Vec<(String, BauResponseReceiver)>,
+        outcome: Option<QuorumOutcomeReceiver>,
     ) -> std::io::Result<()> {
+        // Acknowledge the payload first
+        if let Some(id) = id {
+            // NOTE: Safe to unwrap because we checked the serialization chain
+            let accepted = serde_json::to_string(&BauServerResponse::Accepted { id }).unwrap();
+            write_stream(tcp_stream, &accepted).await?;
+        }
+
         // Create iterator over responses that returns a future
         let responses = responses.into_iter().map(|(recipient, receiver)| async {
             let receiver = receiver.await;
@@ -180,6 +285,16 @@ where
             response.await?;
         }
 
+        // Report the outcome last. It is decided by the time every recipient has responded.
+        if let Some(outcome) = outcome {
+            if let Ok(outcome) = outcome.await {
+                // NOTE: Safe to unwrap because we checked the serialization chain
+                let outcome =
+                    serde_json::to_string(&BauServerResponse::Outcome { outcome }).unwrap();
+                write_stream(tcp_stream, &outcome).await?;
+            }
+        }
+
         Ok(())
     }
 }
@@ -261,6 +376,61 @@ impl<const RETRIES: usize> BauClient<RETRIES> {
         Ok(bau_response_receiver)
     }
 
+    /// Picks up the responses to an earlier [BauMessage] with [BauMessage::id] `request_id` that
+    /// was restored after a restart. The [BauServerResponseReceiver] yields nothing if there is
+    /// nothing to reclaim.
+    pub async fn reclaim(&self, request_id: &str) -> Result<BauServerResponseReceiver, SendError> {
+        self.send_request(serde_json::json!({ "reclaim": request_id }))
+            .await
+    }
+
+    /// Cancels the [BauMessage] with [BauMessage::id] `request_id` (see
+    /// [BauServerResponse::Accepted]). The [BauServerResponseReceiver] yields a single
+    /// [BauServerResponse::Cancelled]. See [BauBot::cancel] for `withdraw`.
+    pub async fn cancel(
+        &self,
+        request_id: &str,
+        withdraw: bool,
+    ) -> Result<BauServerResponseReceiver, SendError> {
+        self.send_request(serde_json::json!({ "cancel": request_id, "withdraw": withdraw }))
+            .await
+    }
+
+    /// Issues an invite deep link for the client application's `user_id`, valid for `valid_for`.
+    /// The [BauServerResponseReceiver] yields a single [BauServerResponse::Invited]. See
+    /// [BauBot::invite].
+    pub async fn invite(
+        &self,
+        user_id: &str,
+        valid_for: std::time::Duration,
+    ) -> Result<BauServerResponseReceiver, SendError> {
+        self.send_request(serde_json::json!({
+            "invite": user_id,
+            "valid_for": valid_for.as_millis() as u64,
+        }))
+        .await
+    }
+
+    /// Sends a `request` that is not a [BauMessage] to the [BauServer]. Opaque to the end user.
+    async fn send_request(
+        &self,
+        request: serde_json::Value,
+    ) -> Result<BauServerResponseReceiver, SendError> {
+        // Create stream
+        let tcp_stream = self.connect().await?;
+
+        // Write to the stream
+        let request = request.to_string();
+        trace!("Attemping to send request to stream: {request}");
+        let _ = write_stream(&tcp_stream, &request).await?;
+
+        // Create senders and receivers and send them away with the tcp_stream
+        let (bau_response_sender, bau_response_receiver) = sync::mpsc::unbounded_channel();
+        task::spawn(Self::receive_responses(tcp_stream, bau_response_sender));
+
+        Ok(bau_response_receiver)
+    }
+
     /// Sends a [BauMessage] through the [BauClient] to the [BauServer] and returns a
     /// [BauServerResponseReceiver] that we can poll for responses.
     ///
@@ -295,17 +465,49 @@ impl<const RETRIES: usize> BauClient<RETRIES> {
                 break;
             }
 
-            // Check if we are able to construct a response from the server
-            if let Ok(response) = serde_json::from_str::<BauServerResponse>(&response) {
+            // Check if we are able to construct responses from the server. Responses written in
+            // quick succession (e.g. the last response and the quorum outcome) may arrive in a
+            // single read
+            let responses = serde_json::Deserializer::from_str(&response)
+                .into_iter::<BauServerResponse>()
+                .map_while(Result::ok);
+            for response in responses {
                 trace!("Sending response to receiver.");
 
                 if let Err(_) = bau_response_sender.send(response) {
                     error!("Reciever went out of scope.");
-                    break;
+                    return;
                 }
-            };
+            }
         }
 
         trace!("Finished receiving responses from BauServer.");
     }
 }
+
+/// Request id of a reclaim request (`{"reclaim": "<id>"}`), if `request` is one.
+fn reclaim_id(request: &str) -> Option<String> {
+    let request = serde_json::from_str::<serde_json::Value>(request).ok()?;
+    Some(request.get("reclaim")?.as_str()?.to_string())
+}
+
+/// Request id and `withdraw` flag of a cancel request (`{"cancel": "<id>", "withdraw": true}`),
+/// if `request` is one. `withdraw` defaults to `false`.
+fn cancel_request(request: &str) -> Option<(String, bool)> {
+    let request = serde_json::from_str::<serde_json::Value>(request).ok()?;
+    let request_id = request.get("cancel")?.as_str()?.to_string();
+    let withdraw = request
+        .get("withdraw")
+        .and_then(serde_json::Value::as_bool)
+        .unwrap_or(false);
+    Some((request_id, withdraw))
+}
+
+/// User id and validity of an invite request (`{"invite": "<user id>", "valid_for": <ms>}`), if
+/// `request` is one.
+fn invite_request(request: &str) -> Option<(String, std::time::Duration)> {
+    let request = serde_json::from_str::<serde_json::Value>(request).ok()?;
+    let user_id = request.get("invite")?.as_str()?.to_string();
+    let valid_for = request.get("valid_for")?.as_u64()?;
+    Some((user_id, std::time::Duration::from_millis(valid_for)))
+}
@@ -1,5 +1,6 @@
 //! Prelude definitiions
 
+pub(crate) use baubot_core::prelude::Bot;
 #[allow(unused_imports)]
 pub(crate) use log::{debug, error, info, trace, warn};
 pub(crate) use serde::Deserialize;
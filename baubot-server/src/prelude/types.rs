@@ -7,6 +7,10 @@ pub(crate) use baubot_core::broadcaster::types::*;
 #[serde(tag = "type")]
 /// Types of responses that the [crate::BauServer] could distribute.
 pub enum BauServerResponse {
+    /// The [BauMessage] was handed to [crate::BauBot] under [BauMessage::id] `id`. Always sent
+    /// first; pass `id` to [crate::BauClient::cancel] to take the [BauMessage] back.
+    Accepted { id: String },
+
     /// Response from each individual recipient. This variant will only apply if the [BauMessage]
     /// was correctly constructed and sent on to the [crate::BauBot] leaving [crate::BauBot] to
     /// handle the individual payloads and errors on a per-recipient level.
@@ -16,7 +20,23 @@ pub enum BauServerResponse {
     },
 
     /// Data was rejected by the [crate::BauServer]
-    InvalidData(SerializeError),
+    InvalidData { error: SerializeError },
+
+    /// Outcome of the [Quorum] of the [BauMessage]. Sent after every [BauServerResponse::Recipient]
+    /// if the [BauMessage] has a [Quorum] other than [Quorum::Independent].
+    Outcome { outcome: QuorumOutcome },
+
+    /// Answer to [crate::BauClient::cancel]: `retracted` broadcasts were still waiting for a
+    /// response and have been retracted.
+    Cancelled { retracted: usize },
+
+    /// Answer to [crate::BauClient::invite]: the token of the invite, or why it could not be
+    /// issued.
+    Invited { token: Result<String, String> },
+
+    /// [crate::BauBot]'s queue is full (see
+    /// [baubot_core::config::BauBotBuilder::queue_capacity]). Nothing was sent; try again later.
+    Busy,
 }
 
 /// Handle for **sending** responses from the [crate::BauServer]
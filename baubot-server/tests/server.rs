@@ -0,0 +1,470 @@
+use baubot_core::config::BauBotBuilder;
+use baubot_core::prelude::types::*;
+use baubot_core::prelude::BauData;
+use baubot_core::transport::UpdateMode;
+use baubot_core::transport::Webhook;
+use baubot_core::BauBot;
+use baubot_data::test_api::TestApi;
+use baubot_data::test_db::TestDB;
+use baubot_server::*;
+use baubot_utils::*;
+use std::sync::Arc;
+
+use std::str::FromStr;
+
+/// Address for the [BauServer] under test. Each test gets its own port so that they can run in
+/// parallel.
+fn socket_addr(offset: u128) -> ::core::net::SocketAddr {
+    let host = baubot_utils::BAUBOT_LISTEN_HOST;
+    let port = baubot_utils::BAUBOT_LISTEN_PORT + offset;
+    ::core::net::SocketAddr::from_str(&format!("{host}:{port}")).unwrap()
+}
+
+#[tokio::test]
+async fn simple_message() {
+    baubot_utils::init();
+
+    let test_user = baubot_utils::TEST_USER;
+    let socket_addr = socket_addr(0);
+    let api = TestApi::start().await;
+    let db = Arc::new(TestDB::seed());
+    let server = BauServer::with_transport(db, socket_addr, api.bot());
+    let client = BauClient::<3>::new(socket_addr);
+
+    let _response_handler = client
+        .send_string(format!(
+            r#"{{
+                "sender": "{}",
+                "recipients": [
+                    "{}"
+                ],
+                "message": "hello world"
+            }}"#,
+            test_user, test_user
+        ))
+        .await
+        .unwrap();
+
+    let call = api
+        .wait_for(|call| call.method == "sendMessage" && call.body["text"] == "hello world")
+        .await;
+    assert_eq!(call.body["chat_id"], TEST_CHATID as i64);
+    info!("Successful connection achieved");
+    drop(server);
+}
+
+#[tokio::test]
+async fn response_required() {
+    baubot_utils::init();
+
+    let test_user = baubot_utils::TEST_USER;
+    let chat_id = TEST_CHATID as i64;
+    let socket_addr = socket_addr(1);
+    let api = TestApi::start().await;
+    let db = Arc::new(TestDB::seed());
+    let server = BauServer::with_transport(db, socket_addr, api.bot());
+    let client = BauClient::<3>::new(socket_addr);
+
+    let mut response_handler = client
+        .send_string(format!(
+            r#"{{
+                "sender": "{}",
+                "recipients": [
+                    "{}", "{}", "{}"
+                ],
+                "message": "Attempting to access your life secrets:",
+                "responses": {{
+                    "timeout": 10000,
+                    "keyboard": [["approve", "deny"]]
+                }}
+            }}"#,
+            test_user, test_user, test_user, test_user
+        ))
+        .await
+        .unwrap();
+
+    // Approve twice and deny once
+    let message_ids = api.wait_for_keyboards(chat_id, 3).await;
+    api.press_button(chat_id, test_user, message_ids[0], "approve")
+        .await;
+    api.press_button(chat_id, test_user, message_ids[1], "deny")
+        .await;
+    api.press_button(chat_id, test_user, message_ids[2], "approve")
+        .await;
+
+    let mut responses = Vec::new();
+    while let Some(response) = response_handler.recv().await {
+        info!("Received response from server: {response:#?}");
+        match response {
+            BauServerResponse::Recipient {
+                recipient,
+                response,
+            } => {
+                assert_eq!(recipient, test_user);
+                responses.push(response.unwrap());
+            }
+            BauServerResponse::Accepted { .. } => assert!(responses.is_empty()),
+            response => panic!("Unexpected response: {response:?}"),
+        }
+    }
+
+    responses.sort();
+    assert_eq!(responses, vec!["approve", "approve", "deny"]);
+    info!("Successful transaction");
+    drop(server);
+}
+
+#[tokio::test]
+async fn object_payload() {
+    baubot_utils::init();
+
+    let test_user = baubot_utils::TEST_USER;
+    let chat_id = TEST_CHATID as i64;
+    let socket_addr = socket_addr(2);
+    let api = TestApi::start().await;
+    let db = Arc::new(TestDB::seed());
+    let server = BauServer::with_transport(db, socket_addr, api.bot());
+    let client = BauClient::<3>::new(socket_addr);
+
+    let mut response_handler = client
+        .send(BauMessage {
+            id: None,
+            sender: test_user.to_string(),
+            recipients: vec![(Recipient::User(test_user.to_string()), None)],
+            message: "Approve?".to_string(),
+            format: None,
+            attachments: vec![],
+            responses: RequestedResponses {
+                timeout: 10000,
+                keyboard: vec![vec![
+                    "yes".into(),
+                    Button::Labelled {
+                        label: "No".to_string(),
+                        value: "no".to_string(),
+                    },
+                ]],
+                quorum: Quorum::Independent,
+                free_text: false,
+            },
+            outcome: None,
+        })
+        .await
+        .unwrap();
+
+    // Client is handed an id before any response
+    let response = response_handler.recv().await.unwrap();
+    assert!(matches!(response, BauServerResponse::Accepted { .. }));
+
+    let message_ids = api.wait_for_keyboards(chat_id, 1).await;
+    api.press_button(chat_id, test_user, message_ids[0], "No")
+        .await;
+
+    let response = response_handler.recv().await.unwrap();
+    info!("Received response from server: {response:#?}");
+    assert!(matches!(
+        response,
+        BauServerResponse::Recipient { response: Ok(ref data), .. } if data == "no"
+    ));
+
+    // Keyboard must be stripped once the user has answered
+    api.wait_for(|call| {
+        call.method == "editMessageReplyMarkup" && call.body["message_id"] == message_ids[0]
+    })
+    .await;
+
+    info!("Successful transaction");
+    drop(server);
+}
+
+#[tokio::test]
+async fn webhook() {
+    baubot_utils::init();
+
+    let chat_id = 42;
+    let address = socket_addr(3);
+    let url = format!("http://{address}/webhook").parse().unwrap();
+    let api = TestApi::start().await;
+    let db = Arc::new(TestDB::seed());
+    let baubot = BauBot::with_update_mode(
+        db.clone(),
+        api.bot(),
+        UpdateMode::Webhook(Webhook::new(address, url).secret_token("s3cret")),
+    );
+
+    let call = api.wait_for(|call| call.method == "setWebhook").await;
+    assert_eq!(call.body["secret_token"], "s3cret");
+
+    // Updates without the secret are refused
+    let status = api
+        .spoof_text(chat_id, "newcomer", "/start", Some("wrong"))
+        .await
+        .unwrap();
+    assert_eq!(status, 401);
+
+    // Updates are delivered through the webhook
+    let message_id = api.send_text(chat_id, "newcomer", "/start").await;
+    api.wait_for(|call| {
+        call.method == "sendMessage" && call.body["reply_parameters"]["message_id"] == message_id
+    })
+    .await;
+    assert_eq!(db.get_chat_id("newcomer").await, Some(chat_id));
+
+    // Webhook is removed on shutdown
+    baubot.shutdown().await;
+    assert!(api
+        .calls()
+        .await
+        .iter()
+        .any(|call| call.method == "deleteWebhook"));
+    info!("Successful transaction");
+}
+
+#[tokio::test]
+async fn quorum_outcome() {
+    baubot_utils::init();
+
+    let test_user = baubot_utils::TEST_USER;
+    let chat_id = TEST_CHATID as i64;
+    let socket_addr = socket_addr(4);
+    let api = TestApi::start().await;
+    let db = Arc::new(TestDB::seed());
+    let server = BauServer::with_transport(db, socket_addr, api.bot());
+    let client = BauClient::<3>::new(socket_addr);
+
+    let mut response_handler = client
+        .send_string(format!(
+            r#"{{
+                "sender": "{test_user}",
+                "recipients": ["{test_user}", "{test_user}"],
+                "message": "Deploy to production?",
+                "responses": {{
+                    "timeout": 10000,
+                    "keyboard": [["approve", "deny"]],
+                    "quorum": {{ "type": "All", "approve": "approve" }}
+                }}
+            }}"#
+        ))
+        .await
+        .unwrap();
+
+    // Approve both
+    let message_ids = api.wait_for_keyboards(chat_id, 2).await;
+    for message_id in message_ids {
+        api.press_button(chat_id, test_user, message_id, "approve")
+            .await;
+    }
+
+    let mut responses = Vec::new();
+    while let Some(response) = response_handler.recv().await {
+        info!("Received response from server: {response:#?}");
+        responses.push(response);
+    }
+
+    // Outcome follows the recipients' responses
+    assert_eq!(responses.len(), 4);
+    assert!(matches!(
+        responses.pop(),
+        Some(BauServerResponse::Outcome {
+            outcome: QuorumOutcome::Approved { ref approvals }
+        }) if approvals.len() == 2
+    ));
+    drop(server);
+}
+
+#[tokio::test]
+async fn cancel() {
+    baubot_utils::init();
+
+    let test_user = baubot_utils::TEST_USER;
+    let chat_id = TEST_CHATID as i64;
+    let socket_addr = socket_addr(5);
+    let api = TestApi::start().await;
+    let db = Arc::new(TestDB::seed());
+    let server = BauServer::with_transport(db, socket_addr, api.bot());
+    let client = BauClient::<3>::new(socket_addr);
+
+    let mut response_handler = client
+        .send_string(format!(
+            r#"{{
+                "sender": "{test_user}",
+                "recipients": ["{test_user}"],
+                "message": "Approve?",
+                "responses": {{
+                    "timeout": 10000,
+                    "keyboard": [["approve", "deny"]]
+                }}
+            }}"#
+        ))
+        .await
+        .unwrap();
+    let id = match response_handler.recv().await {
+        Some(BauServerResponse::Accepted { id }) => id,
+        response => panic!("Unexpected response: {response:?}"),
+    };
+    let message_ids = api.wait_for_keyboards(chat_id, 1).await;
+
+    // Withdraw the request
+    let mut cancel_handler = client.cancel(&id, true).await.unwrap();
+    assert!(matches!(
+        cancel_handler.recv().await,
+        Some(BauServerResponse::Cancelled { retracted: 1 })
+    ));
+    assert!(matches!(
+        response_handler.recv().await,
+        Some(BauServerResponse::Recipient {
+            response: Err(BauBotError::Cancelled),
+            ..
+        })
+    ));
+    api.wait_for(|call| {
+        call.method == "editMessageText" && call.body["message_id"] == message_ids[0]
+    })
+    .await;
+
+    // Nothing left to cancel
+    let mut cancel_handler = client.cancel(&id, true).await.unwrap();
+    assert!(matches!(
+        cancel_handler.recv().await,
+        Some(BauServerResponse::Cancelled { retracted: 0 })
+    ));
+    drop(server);
+}
+
+#[tokio::test]
+async fn attachment() {
+    baubot_utils::init();
+
+    let test_user = baubot_utils::TEST_USER;
+    let socket_addr = socket_addr(6);
+    let api = TestApi::start().await;
+    let db = Arc::new(TestDB::seed());
+    let server = BauServer::with_transport(db, socket_addr, api.bot());
+    let client = BauClient::<3>::new(socket_addr);
+
+    let _response_handler = client
+        .send_string(format!(
+            r#"{{
+                "sender": "{test_user}",
+                "recipients": ["{test_user}"],
+                "message": "Invoice attached",
+                "attachments": [{{
+                    "filename": "invoice.pdf",
+                    "mime_type": "application/pdf",
+                    "base64": "JVBERi0xLjQK"
+                }}],
+                "responses": {{
+                    "timeout": 10000,
+                    "keyboard": [["paid"]]
+                }}
+            }}"#
+        ))
+        .await
+        .unwrap();
+
+    // Attachment carries the message and the keyboard
+    let call = api.wait_for(|call| call.method == "sendDocument").await;
+    assert_eq!(call.body["caption"], "Invoice attached");
+    assert!(call.body.get("reply_markup").is_some());
+    assert!(call.result["message_id"].is_i64());
+    drop(server);
+}
+
+#[tokio::test]
+async fn busy() {
+    baubot_utils::init();
+
+    let test_user = baubot_utils::TEST_USER;
+    let socket_addr = socket_addr(7);
+    let api = TestApi::start().await;
+    let baubot = BauBotBuilder::new()
+        .queue_capacity(1)
+        .concurrency(1)
+        .build_with_transport(Arc::new(TestDB::seed()), api.bot())
+        .unwrap();
+    let server = BauServer::with_baubot(baubot, socket_addr);
+    let client = BauClient::<3>::new(socket_addr);
+
+    // Messages to the same chat pile up behind the per-chat rate limit until the queue is full
+    let mut responses = Vec::new();
+    for _ in 0..4 {
+        let mut response_handler = client
+            .send_string(format!(
+                r#"{{
+                    "sender": "{test_user}",
+                    "recipients": ["{test_user}"],
+                    "message": "hello world"
+                }}"#
+            ))
+            .await
+            .unwrap();
+        responses.push(response_handler.recv().await);
+    }
+    assert!(matches!(
+        responses[0],
+        Some(BauServerResponse::Accepted { .. })
+    ));
+    assert!(matches!(responses[3], Some(BauServerResponse::Busy)));
+    drop(server);
+}
+
+#[tokio::test]
+async fn invite() {
+    baubot_utils::init();
+
+    let socket_addr = socket_addr(8);
+    let api = TestApi::start().await;
+    let db = Arc::new(TestDB::seed());
+    let _server = BauServer::with_transport(db.clone(), socket_addr, api.bot());
+    let client = BauClient::<3>::new(socket_addr);
+
+    let mut invite_handler = client
+        .invite("user-1", std::time::Duration::from_secs(60))
+        .await
+        .unwrap();
+    let token = match invite_handler.recv().await {
+        Some(BauServerResponse::Invited { token: Ok(token) }) => token,
+        response => panic!("Unexpected response: {response:?}"),
+    };
+    let invite = db.take_invite(&token).await.unwrap();
+    assert_eq!(invite.user_id, "user-1");
+}
+
+#[tokio::test]
+async fn invalid_markup() {
+    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
+
+    baubot_utils::init();
+
+    let test_user = baubot_utils::TEST_USER;
+    let socket_addr = socket_addr(9);
+    let api = TestApi::start().await;
+    let db = Arc::new(TestDB::seed());
+    let _server = BauServer::with_transport(db, socket_addr, api.bot());
+
+    // Clients that skip the checks of BauClient are turned away by the server
+    let mut tcp_stream = loop {
+        match tokio::net::TcpStream::connect(socket_addr).await {
+            Ok(tcp_stream) => break tcp_stream,
+            Err(_) => tokio::time::sleep(std::time::Duration::from_millis(10)).await,
+        }
+    };
+    let request = format!(
+        r#"{{
+            "sender": "{test_user}",
+            "recipients": ["{test_user}"],
+            "message": "<div>Deploy</div>",
+            "format": "html"
+        }}"#
+    );
+    tcp_stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    tcp_stream.read_to_string(&mut response).await.unwrap();
+    let response = serde_json::from_str::<BauServerResponse>(&response).unwrap();
+    assert!(matches!(
+        response,
+        BauServerResponse::InvalidData {
+            error: SerializeError::InvalidMarkup(_)
+        }
+    ));
+}